@@ -1,21 +1,19 @@
-extern crate base64;
 extern crate clap;
 extern crate fibers;
 extern crate futures;
 extern crate handy_async;
 extern crate miasht;
-extern crate sha1;
 #[macro_use]
 extern crate trackable;
 
 use fibers::{Executor, Spawn, ThreadPoolExecutor};
-use futures::{BoxFuture, Future, IntoFuture};
+use futures::{BoxFuture, Future, IntoFuture, Stream};
 use miasht::{Error, Method, Server, Status};
 use miasht::builtin::servers::{RawConnection, SimpleHttpServer};
 use miasht::builtin::headers::ContentLength;
 use miasht::builtin::{FutureExt, IoExt};
 use miasht::builtin::router::{RouteBuilder, Router};
-use handy_async::io::AsyncRead;
+use miasht::websocket::{self, Role, ServerHandshake, WebSocket};
 
 type TcpRequest = miasht::server::Request<fibers::net::TcpStream>;
 
@@ -72,41 +70,26 @@ fn handle_get_file(_: (), request: TcpRequest) -> Result<BoxFuture<(), ()>, TcpR
 }
 
 fn handle_upgrade(_: (), request: TcpRequest) -> Result<BoxFuture<(), ()>, TcpRequest> {
-    if request.method() != Method::Get {
+    if request.headers().get("Upgrade").is_none() {
         return Err(request);
     }
-    if request.headers().get("Upgrade") != Some(b"websocket") {
-        return Err(request);
-    }
-    let key = std::str::from_utf8(
-        request
-            .headers()
-            .get("Sec-WebSocket-Key")
-            .expect("No 'Sec-WebSocket-Key'"),
-    ).unwrap()
-        .to_string();
-    println!("# WebSocket: key={}", key);
-
-    let connection = request.finish();
-    let mut response = connection.build_response(Status::SwitchingProtocols);
-    response.add_raw_header("Upgrade", b"websocket");
-    response.add_raw_header("Connection", b"upgrade");
+    let handshake = match ServerHandshake::from_request(&request) {
+        Err(e) => {
+            println!("# WebSocket: rejected: {}", e);
+            return Ok(websocket::reject(request, &e).then(|_| Ok(())).boxed());
+        }
+        Ok(handshake) => handshake,
+    };
+    println!("# WebSocket: key={}", handshake.key());
 
-    let accept_key = format!("{}258EAFA5-E914-47DA-95CA-C5AB0DC85B11", key);
-    let mut m = sha1::Sha1::new();
-    m.update(accept_key.as_bytes());
-    let accept_key = base64::encode(&m.digest().bytes()[..]);
-    response.add_raw_header("Sec-WebSocket-Accept", accept_key.as_bytes());
-    //response.add_header(&ContentLength(buf.len() as u64));
+    let response = handshake.accept(request, None).expect("Never fails");
     Ok(response
-        .finish()
-        .and_then(|conn| {
-            let stream = conn.into_raw_stream();
-            let buf = vec![0; 128];
-            let future = stream
-                .async_read(buf)
-                .map(|(_, buf, size)| println!("# BUF: {:?}", &buf[..size]));
-            future.map_err(|e| track!(Error::from(e)))
+        .and_then(|stream| {
+            let ws = WebSocket::new(stream, Role::Server);
+            ws.for_each(|message| {
+                println!("# MESSAGE: {:?}", message);
+                Ok(())
+            })
         })
         .then(|_| Ok(()))
         .boxed())
@@ -0,0 +1,739 @@
+//! HTTP Digest access authentication ([RFC 7616](https://tools.ietf.org/html/rfc7616)).
+//!
+//! # Examples
+//!
+//! The example of RFC 7616 §3.9.1:
+//!
+//! ```
+//! use miasht::auth::Challenge;
+//! use miasht::auth::digest::{Algorithm, DigestChallenge, DigestSession};
+//! use miasht::header::Header;
+//! use miasht::Method;
+//!
+//! let challenge = Challenge::parse_value_str(
+//!     r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=MD5,
+//!        nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v",
+//!        opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#
+//! ).unwrap();
+//! let challenge = DigestChallenge::from_challenge(&challenge).unwrap();
+//! assert_eq!(challenge.algorithm, Algorithm::Md5);
+//!
+//! let mut session = DigestSession::new("Mufasa", "Circle of Life");
+//! session.set_challenge(challenge);
+//! let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+//! let response = session.respond_with_cnonce(Method::Get, "/dir/index.html", cnonce).unwrap();
+//! assert_eq!(response.nc, 1);
+//! assert_eq!(response.response, "8ca523f5e9506fed4657c9700eebdbec");
+//!
+//! // The nonce count is incremented for each request.
+//! let response = session.respond_with_cnonce(Method::Get, "/dir/index.html", cnonce).unwrap();
+//! assert_eq!(response.nc, 2);
+//! ```
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use base64;
+use md5;
+use sha2::{Digest, Sha256};
+
+use {Method, Status, TransportStream};
+use header::ContentLength;
+use server;
+use util::random_bytes;
+use super::{is_token, quote, split_list, unquote, Authorization, Challenge};
+
+/// Digest algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+impl Algorithm {
+    /// Parses an algorithm name (case-insensitive).
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Algorithm::Md5,
+            Algorithm::Md5Sess,
+            Algorithm::Sha256,
+            Algorithm::Sha256Sess,
+        ].iter()
+            .find(|a| a.as_str().eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    /// Returns the name of the algorithm.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Md5Sess => "MD5-sess",
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    /// Returns `true` if this is a session variant algorithm, otherwise `false`.
+    pub fn is_session(&self) -> bool {
+        *self == Algorithm::Md5Sess || *self == Algorithm::Sha256Sess
+    }
+
+    /// Returns the lowercase hex representation of the hash of `data`.
+    pub fn hash(&self, data: &[u8]) -> String {
+        match *self {
+            Algorithm::Md5 | Algorithm::Md5Sess => format!("{:x}", md5::compute(data)),
+            Algorithm::Sha256 | Algorithm::Sha256Sess => {
+                Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+            }
+        }
+    }
+}
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A challenge of the `Digest` scheme.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestChallenge {
+    pub realm: String,
+    pub nonce: String,
+    pub opaque: Option<String>,
+    pub algorithm: Algorithm,
+
+    /// Supported quality of protections (e.g., `"auth"`).
+    ///
+    /// Empty means the legacy RFC 2069 mode.
+    pub qop: Vec<String>,
+    pub stale: bool,
+}
+impl DigestChallenge {
+    /// Converts from a generic challenge.
+    pub fn from_challenge(challenge: &Challenge) -> ::Result<Self> {
+        track_assert!(
+            challenge.scheme().eq_ignore_ascii_case("Digest"),
+            Status::BadRequest,
+            "Not a Digest challenge: scheme={:?}",
+            challenge.scheme()
+        );
+        let realm = track_assert_some!(challenge.realm(), Status::BadRequest, "No realm");
+        let nonce = track_assert_some!(challenge.get_param("nonce"), Status::BadRequest, "No nonce");
+        let algorithm = if let Some(a) = challenge.get_param("algorithm") {
+            track_assert_some!(
+                Algorithm::from_name(a),
+                Status::BadRequest,
+                "Unsupported algorithm: {:?}",
+                a
+            )
+        } else {
+            Algorithm::Md5
+        };
+        let qop = challenge.get_param("qop").map_or_else(Vec::new, |q| {
+            q.split(',')
+                .map(|s| s.trim().to_owned())
+                .filter(|s| !s.is_empty())
+                .collect()
+        });
+        let stale = challenge
+            .get_param("stale")
+            .is_some_and(|s| s.eq_ignore_ascii_case("true"));
+        Ok(DigestChallenge {
+            realm: realm.to_owned(),
+            nonce: nonce.to_owned(),
+            opaque: challenge.get_param("opaque").map(|s| s.to_owned()),
+            algorithm,
+            qop,
+            stale,
+        })
+    }
+
+    /// Converts to a generic challenge.
+    pub fn to_challenge(&self) -> Challenge {
+        let mut c = Challenge::new("Digest").param("realm", &self.realm);
+        if !self.qop.is_empty() {
+            c = c.param("qop", &self.qop.join(", "));
+        }
+        c = c.token_param("algorithm", self.algorithm.as_str())
+            .param("nonce", &self.nonce);
+        if let Some(ref opaque) = self.opaque {
+            c = c.param("opaque", opaque);
+        }
+        if self.stale {
+            c = c.token_param("stale", "true");
+        }
+        c
+    }
+}
+
+/// The credentials of the `Digest` scheme (i.e., the parameters of `Authorization: Digest ...`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestResponse {
+    pub username: String,
+    pub realm: String,
+    pub uri: String,
+    pub algorithm: Algorithm,
+    pub nonce: String,
+    pub nc: u32,
+    pub cnonce: Option<String>,
+    pub qop: Option<String>,
+    pub response: String,
+    pub opaque: Option<String>,
+}
+impl DigestResponse {
+    /// Converts from `Authorization` header.
+    pub fn from_authorization(auth: &Authorization) -> ::Result<Self> {
+        let params = match *auth {
+            Authorization::Other {
+                ref scheme,
+                ref params,
+            } if scheme.eq_ignore_ascii_case("Digest") => params,
+            _ => track_panic!(Status::BadRequest, "Not a Digest credentials"),
+        };
+        let mut map = HashMap::new();
+        for item in split_list(params) {
+            let eq = track_assert_some!(item.find('='), Status::BadRequest, "Malformed auth-param");
+            let name = item[..eq].trim().to_ascii_lowercase();
+            let value = item[eq + 1..].trim();
+            track_assert!(is_token(&name), Status::BadRequest, "Malformed auth-param");
+            let value = if value.starts_with('"') {
+                track!(unquote(value))?
+            } else {
+                value.to_owned()
+            };
+            map.insert(name, value);
+        }
+        macro_rules! required {
+            ($name:expr) => {
+                track_assert_some!(
+                    map.remove($name),
+                    Status::BadRequest,
+                    "Missing Digest parameter: {:?}",
+                    $name
+                )
+            };
+        }
+        let algorithm = if let Some(a) = map.remove("algorithm") {
+            track_assert_some!(
+                Algorithm::from_name(&a),
+                Status::BadRequest,
+                "Unsupported algorithm: {:?}",
+                a
+            )
+        } else {
+            Algorithm::Md5
+        };
+        let nc = if let Some(nc) = map.remove("nc") {
+            track_assert!(nc.len() == 8, Status::BadRequest, "Malformed nc: {:?}", nc);
+            track_assert_some!(
+                u32::from_str_radix(&nc, 16).ok(),
+                Status::BadRequest,
+                "Malformed nc: {:?}",
+                nc
+            )
+        } else {
+            0
+        };
+        Ok(DigestResponse {
+            username: required!("username"),
+            realm: required!("realm"),
+            uri: required!("uri"),
+            nonce: required!("nonce"),
+            response: required!("response"),
+            algorithm,
+            nc,
+            cnonce: map.remove("cnonce"),
+            qop: map.remove("qop"),
+            opaque: map.remove("opaque"),
+        })
+    }
+
+    /// Converts to `Authorization` header.
+    pub fn to_authorization(&self) -> Authorization {
+        let mut params = format!(
+            "username={}, realm={}, uri={}, algorithm={}, nonce={}",
+            quote(&self.username),
+            quote(&self.realm),
+            quote(&self.uri),
+            self.algorithm,
+            quote(&self.nonce)
+        );
+        if let Some(ref qop) = self.qop {
+            params += &format!(", nc={:08x}, qop={}", self.nc, qop);
+        }
+        if let Some(ref cnonce) = self.cnonce {
+            params += &format!(", cnonce={}", quote(cnonce));
+        }
+        params += &format!(", response={}", quote(&self.response));
+        if let Some(ref opaque) = self.opaque {
+            params += &format!(", opaque={}", quote(opaque));
+        }
+        Authorization::Other {
+            scheme: "Digest".to_owned(),
+            params,
+        }
+    }
+}
+
+/// Computes the `response` value of the Digest credentials.
+///
+/// `qop` is `None` in the legacy RFC 2069 mode (the `auth-int` protection is not supported).
+///
+/// # Examples
+///
+/// The SHA-256 example of RFC 7616 §3.9.1:
+///
+/// ```
+/// use miasht::Method;
+/// use miasht::auth::digest::{compute_response, Algorithm};
+///
+/// let response = compute_response(
+///     Algorithm::Sha256,
+///     "Mufasa",
+///     "http-auth@example.org",
+///     "Circle of Life",
+///     Method::Get,
+///     "/dir/index.html",
+///     "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v",
+///     1,
+///     "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+///     Some("auth"),
+/// );
+/// assert_eq!(response, "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1");
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub fn compute_response(
+    algorithm: Algorithm,
+    username: &str,
+    realm: &str,
+    password: &str,
+    method: Method,
+    uri: &str,
+    nonce: &str,
+    nc: u32,
+    cnonce: &str,
+    qop: Option<&str>,
+) -> String {
+    let mut ha1 = algorithm.hash(format!("{}:{}:{}", username, realm, password).as_bytes());
+    if algorithm.is_session() {
+        ha1 = algorithm.hash(format!("{}:{}:{}", ha1, nonce, cnonce).as_bytes());
+    }
+    let ha2 = algorithm.hash(format!("{}:{}", method, uri).as_bytes());
+    let data = if let Some(qop) = qop {
+        format!("{}:{}:{:08x}:{}:{}:{}", ha1, nonce, nc, cnonce, qop, ha2)
+    } else {
+        format!("{}:{}:{}", ha1, nonce, ha2)
+    };
+    algorithm.hash(data.as_bytes())
+}
+
+/// Client side state of the Digest authentication.
+///
+/// A session is expected to be used for the requests to a server
+/// (e.g., the requests sent over a `client::Connection`).
+/// It keeps the last challenge and increments the nonce count for each request.
+#[derive(Debug, Clone)]
+pub struct DigestSession {
+    username: String,
+    password: String,
+    challenge: Option<DigestChallenge>,
+    nc: u32,
+}
+impl DigestSession {
+    /// Makes a new `DigestSession` instance.
+    pub fn new(username: &str, password: &str) -> Self {
+        DigestSession {
+            username: username.to_owned(),
+            password: password.to_owned(),
+            challenge: None,
+            nc: 0,
+        }
+    }
+
+    /// Returns the current challenge.
+    pub fn challenge(&self) -> Option<&DigestChallenge> {
+        self.challenge.as_ref()
+    }
+
+    /// Returns the nonce count of the last request.
+    pub fn nonce_count(&self) -> u32 {
+        self.nc
+    }
+
+    /// Updates the challenge (e.g., after receiving a `401` response).
+    ///
+    /// The nonce count is reset if the nonce is changed.
+    pub fn set_challenge(&mut self, challenge: DigestChallenge) {
+        if self.challenge.as_ref().map(|c| &c.nonce) != Some(&challenge.nonce) {
+            self.nc = 0;
+        }
+        self.challenge = Some(challenge);
+    }
+
+    /// Updates the challenge by the first `Digest` challenge in `headers`.
+    ///
+    /// Returns `false` if there is no valid `Digest` challenge.
+    pub fn update(&mut self, headers: &::header::Headers) -> bool {
+        let challenge = super::WwwAuthenticate::collect(headers)
+            .iter()
+            .filter_map(|c| DigestChallenge::from_challenge(c).ok())
+            .next();
+        if let Some(challenge) = challenge {
+            self.set_challenge(challenge);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Makes the credentials for the next request.
+    pub fn respond(&mut self, method: Method, uri: &str) -> ::Result<DigestResponse> {
+        let cnonce = generate_cnonce();
+        track!(self.respond_with_cnonce(method, uri, &cnonce))
+    }
+
+    /// Makes the credentials for the next request using the given client nonce.
+    pub fn respond_with_cnonce(
+        &mut self,
+        method: Method,
+        uri: &str,
+        cnonce: &str,
+    ) -> ::Result<DigestResponse> {
+        let challenge = track_assert_some!(
+            self.challenge.as_ref(),
+            Status::Unauthorized,
+            "No Digest challenge"
+        );
+        let qop = if challenge.qop.is_empty() {
+            None
+        } else {
+            track_assert!(
+                challenge.qop.iter().any(|q| q == "auth"),
+                Status::Unauthorized,
+                "Unsupported qop: {:?}",
+                challenge.qop
+            );
+            Some("auth")
+        };
+        self.nc += 1;
+        let cnonce = if qop.is_some() || challenge.algorithm.is_session() {
+            Some(cnonce.to_owned())
+        } else {
+            None
+        };
+        let response = compute_response(
+            challenge.algorithm,
+            &self.username,
+            &challenge.realm,
+            &self.password,
+            method,
+            uri,
+            &challenge.nonce,
+            self.nc,
+            cnonce.as_deref().unwrap_or(""),
+            qop,
+        );
+        Ok(DigestResponse {
+            username: self.username.clone(),
+            realm: challenge.realm.clone(),
+            uri: uri.to_owned(),
+            algorithm: challenge.algorithm,
+            nonce: challenge.nonce.clone(),
+            nc: self.nc,
+            cnonce,
+            qop: qop.map(|s| s.to_owned()),
+            response,
+            opaque: challenge.opaque.clone(),
+        })
+    }
+}
+
+/// The result of nonce verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NonceStatus {
+    Valid,
+
+    /// The nonce was issued by this issuer, but it has been expired.
+    Stale,
+    Invalid,
+}
+
+/// Issuer of server nonces.
+///
+/// A nonce consists of the issued time and its keyed hash,
+/// so it can be verified without keeping the issued nonces.
+#[derive(Debug, Clone)]
+pub struct NonceIssuer {
+    secret: String,
+    lifetime: Duration,
+}
+impl NonceIssuer {
+    /// Makes a new `NonceIssuer` instance.
+    ///
+    /// `secret` should be a private random string.
+    pub fn new(secret: &str, lifetime: Duration) -> Self {
+        NonceIssuer {
+            secret: secret.to_owned(),
+            lifetime,
+        }
+    }
+
+    /// Issues a new nonce.
+    pub fn issue(&self) -> String {
+        self.issue_at(SystemTime::now())
+    }
+
+    /// Issues a new nonce as if the current time is `now`.
+    pub fn issue_at(&self, now: SystemTime) -> String {
+        let time = now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mac = Algorithm::Sha256.hash(format!("{}:{}", time, self.secret).as_bytes());
+        base64::encode(format!("{}:{}", time, mac).as_bytes())
+    }
+
+    /// Verifies `nonce`.
+    pub fn verify(&self, nonce: &str) -> NonceStatus {
+        self.verify_at(nonce, SystemTime::now())
+    }
+
+    /// Verifies `nonce` as if the current time is `now`.
+    pub fn verify_at(&self, nonce: &str, now: SystemTime) -> NonceStatus {
+        let time = match self.issued_at(nonce) {
+            Some(time) => time,
+            None => return NonceStatus::Invalid,
+        };
+        let expiry = (UNIX_EPOCH + Duration::from_secs(time)).checked_add(self.lifetime);
+        if expiry.is_some_and(|expiry| expiry < now) {
+            NonceStatus::Stale
+        } else {
+            NonceStatus::Valid
+        }
+    }
+
+    /// Returns the issued time (in seconds since the UNIX epoch) of `nonce`
+    /// if it was issued by this issuer.
+    fn issued_at(&self, nonce: &str) -> Option<u64> {
+        let decoded = base64::decode(nonce).ok().and_then(|b| String::from_utf8(b).ok())?;
+        let mut fields = decoded.splitn(2, ':');
+        let time = fields.next().and_then(|t| t.parse::<u64>().ok())?;
+        let mac = fields.next()?;
+        let expected = Algorithm::Sha256.hash(format!("{}:{}", time, self.secret).as_bytes());
+        if constant_time_eq(expected.as_bytes(), mac.as_bytes()) {
+            Some(time)
+        } else {
+            None
+        }
+    }
+}
+
+/// The default maximum number of nonces tracked by `DigestAuthenticator`.
+pub const DEFAULT_MAX_NONCES: usize = 4096;
+
+/// Server side Digest authenticator.
+///
+/// `F` is a function which returns the password of a user.
+/// Replayed nonce counts are rejected by tracking the last nonce count of each nonce.
+///
+/// At most `max_nonces` nonces are tracked. When the limit is exceeded, the expired nonces
+/// are forgotten, and then the oldest ones. The nonces issued before the forgotten ones
+/// are treated as stale, so that the clients retry with fresh nonces.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use miasht::Method;
+/// use miasht::auth::digest::{Algorithm, DigestAuthenticator, DigestSession, NonceIssuer};
+///
+/// let issuer = NonceIssuer::new("secret", Duration::from_secs(300));
+/// let password = |user: &str| if user == "alice" { Some("pass".to_owned()) } else { None };
+/// let mut authenticator = DigestAuthenticator::new("example", Algorithm::Sha256, issuer.clone(), password);
+///
+/// let mut session = DigestSession::new("alice", "pass");
+/// session.set_challenge(authenticator.challenge(false));
+/// let auth = session.respond(Method::Get, "/a").unwrap().to_authorization();
+/// assert_eq!(authenticator.verify(Method::Get, "/a", &auth), Ok("alice".to_owned()));
+///
+/// // Replayed
+/// assert_eq!(authenticator.verify(Method::Get, "/a", &auth), Err(false));
+///
+/// // The `uri` parameter must be the request target.
+/// let auth = session.respond(Method::Get, "/a").unwrap().to_authorization();
+/// assert_eq!(authenticator.verify(Method::Get, "/b", &auth), Err(false));
+///
+/// // An older nonce is forgotten when the limit is exceeded,
+/// // and it cannot be replayed after that.
+/// authenticator.set_max_nonces(1);
+/// let mut challenge = authenticator.challenge(false);
+/// challenge.nonce = issuer.issue_at(SystemTime::now() - Duration::from_secs(60));
+/// let mut old_session = DigestSession::new("alice", "pass");
+/// old_session.set_challenge(challenge);
+/// let auth = old_session.respond(Method::Get, "/a").unwrap().to_authorization();
+/// assert!(authenticator.verify(Method::Get, "/a", &auth).is_ok());
+/// assert_eq!(authenticator.verify(Method::Get, "/a", &auth), Err(true));
+/// ```
+#[derive(Debug)]
+pub struct DigestAuthenticator<F> {
+    realm: String,
+    algorithm: Algorithm,
+    issuer: NonceIssuer,
+    password: F,
+    nonce_counts: HashMap<String, NonceCount>,
+    max_nonces: usize,
+    forgotten_until: Option<u64>,
+}
+#[derive(Debug, Clone, Copy)]
+struct NonceCount {
+    issued_at: u64,
+    last: u32,
+}
+impl<F> DigestAuthenticator<F>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    /// Makes a new `DigestAuthenticator` instance.
+    pub fn new(realm: &str, algorithm: Algorithm, issuer: NonceIssuer, password: F) -> Self {
+        DigestAuthenticator {
+            realm: realm.to_owned(),
+            algorithm,
+            issuer,
+            password,
+            nonce_counts: HashMap::new(),
+            max_nonces: DEFAULT_MAX_NONCES,
+            forgotten_until: None,
+        }
+    }
+
+    /// Sets the maximum number of tracked nonces.
+    ///
+    /// The default value is `DEFAULT_MAX_NONCES`.
+    pub fn set_max_nonces(&mut self, max: usize) {
+        self.max_nonces = max;
+    }
+
+    /// Makes a new challenge which has a fresh nonce.
+    pub fn challenge(&self, stale: bool) -> DigestChallenge {
+        DigestChallenge {
+            realm: self.realm.clone(),
+            nonce: self.issuer.issue(),
+            opaque: None,
+            algorithm: self.algorithm,
+            qop: vec!["auth".to_owned()],
+            stale,
+        }
+    }
+
+    /// Verifies the credentials for the request of `method` to `uri` (the request target).
+    ///
+    /// The `uri` parameter of the credentials must be equal to `uri`
+    /// ([RFC 7616 §3.4.6](https://tools.ietf.org/html/rfc7616#section-3.4.6)).
+    ///
+    /// If the credentials are valid, the user name is returned.
+    /// `Err(true)` means that the nonce is stale (i.e., the client may retry with a new nonce).
+    pub fn verify(&mut self, method: Method, uri: &str, auth: &Authorization) -> Result<String, bool> {
+        let r = DigestResponse::from_authorization(auth).map_err(|_| false)?;
+        if r.realm != self.realm || r.algorithm != self.algorithm || r.uri != uri {
+            return Err(false);
+        }
+        let issued_at = self.issuer.issued_at(&r.nonce).ok_or(false)?;
+        let mut status = self.issuer.verify(&r.nonce);
+        if self.forgotten_until.is_some_and(|t| issued_at <= t)
+            && !self.nonce_counts.contains_key(&r.nonce)
+        {
+            status = NonceStatus::Stale;
+        }
+        let password = (self.password)(&r.username).ok_or(false)?;
+        let qop = match r.qop.as_deref() {
+            Some("auth") => Some("auth"),
+            _ => return Err(false),
+        };
+        let cnonce = r.cnonce.as_deref().unwrap_or("");
+        let expected = compute_response(
+            r.algorithm,
+            &r.username,
+            &r.realm,
+            &password,
+            method,
+            &r.uri,
+            &r.nonce,
+            r.nc,
+            cnonce,
+            qop,
+        );
+        if !constant_time_eq(expected.as_bytes(), r.response.as_bytes()) {
+            return Err(false);
+        }
+        if status == NonceStatus::Stale {
+            self.nonce_counts.remove(&r.nonce);
+            return Err(true);
+        }
+        let count = self.nonce_counts.entry(r.nonce.clone()).or_insert(NonceCount {
+            issued_at,
+            last: 0,
+        });
+        if r.nc <= count.last {
+            return Err(false);
+        }
+        count.last = r.nc;
+        if self.nonce_counts.len() > self.max_nonces {
+            self.forget_nonces();
+        }
+        Ok(r.username)
+    }
+
+    /// Checks the credentials of `request`.
+    ///
+    /// If the request is authenticated, it is returned with the user name.
+    /// Otherwise a `401 Unauthorized` response which has a fresh challenge is returned
+    /// (it should be polled to be sent).
+    pub fn authorize<T>(
+        &mut self,
+        request: server::Request<T>,
+    ) -> Result<(server::Request<T>, String), server::Response<T>>
+    where
+        T: TransportStream,
+    {
+        let result = match request.headers().parse::<Authorization>() {
+            Ok(Some(auth)) => self.verify(request.method(), request.path(), &auth),
+            _ => Err(false),
+        };
+        match result {
+            Ok(username) => Ok((request, username)),
+            Err(stale) => {
+                let mut response = request.finish().build_response(Status::Unauthorized);
+                response.add_header(&self.challenge(stale).to_challenge());
+                response.add_header(&ContentLength(0));
+                Err(response.finish())
+            }
+        }
+    }
+
+    /// Forgets the nonce counts of the stale nonces.
+    pub fn remove_stale_nonces(&mut self) {
+        let issuer = &self.issuer;
+        self.nonce_counts
+            .retain(|nonce, _| issuer.verify(nonce) == NonceStatus::Valid);
+    }
+
+    fn forget_nonces(&mut self) {
+        self.remove_stale_nonces();
+        while self.nonce_counts.len() > self.max_nonces {
+            let oldest = self.nonce_counts
+                .iter()
+                .min_by_key(|&(_, c)| c.issued_at)
+                .map(|(nonce, c)| (nonce.clone(), c.issued_at))
+                .expect("Never fails");
+            self.nonce_counts.remove(&oldest.0);
+            self.forgotten_until = Some(cmp::max(self.forgotten_until.unwrap_or(0), oldest.1));
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn generate_cnonce() -> String {
+    base64::encode(&random_bytes())
+}
+
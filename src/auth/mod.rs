@@ -0,0 +1,544 @@
+//! HTTP authentication ([RFC 7235](https://tools.ietf.org/html/rfc7235)).
+//!
+//! This module supports the [Basic](https://tools.ietf.org/html/rfc7617) and
+//! the [Bearer](https://tools.ietf.org/html/rfc6750) schemes.
+//!
+//! # Examples
+//!
+//! ```
+//! use miasht::auth::{Authorization, Challenge, WwwAuthenticate};
+//! use miasht::header::Header;
+//!
+//! let auth = Authorization::parse_value_str("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==").unwrap();
+//! assert_eq!(auth, Authorization::basic("Aladdin", "open sesame"));
+//!
+//! let auth = Authorization::parse_value_str("Bearer mF_9.B5f-4.1JqM").unwrap();
+//! assert_eq!(auth, Authorization::bearer("mF_9.B5f-4.1JqM"));
+//!
+//! let challenge = Challenge::basic("WallyWorld").param("charset", "UTF-8");
+//! let mut value = Vec::new();
+//! challenge.write_value(&mut value).unwrap();
+//! assert_eq!(value, br#"Basic realm="WallyWorld", charset="UTF-8""#);
+//!
+//! let www = WwwAuthenticate::parse_value_str(
+//!     r#"Newauth realm="apps", type=1, title="Login, please", Basic realm="simple""#
+//! ).unwrap();
+//! assert_eq!(www.0.len(), 2);
+//! assert_eq!(www.0[0].get_param("title"), Some("Login, please"));
+//! assert_eq!(www.0[1].scheme(), "Basic");
+//! ```
+use std::io::{self, Write};
+use base64;
+use futures::{Async, Future, Poll};
+use trackable::error::ErrorKindExt;
+
+use {Error, Status, TransportStream};
+use client;
+use header::{ContentLength, Header, Headers};
+use server;
+
+pub mod digest;
+
+/// `Authorization` request header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Authorization {
+    /// `Basic` scheme credentials.
+    Basic { user_id: String, password: String },
+
+    /// `Bearer` scheme credentials.
+    Bearer { token: String },
+
+    /// Credentials of other schemes.
+    ///
+    /// `params` is the raw string following the scheme name.
+    Other { scheme: String, params: String },
+}
+impl Authorization {
+    /// Makes a new `Basic` credentials.
+    pub fn basic(user_id: &str, password: &str) -> Self {
+        Authorization::Basic {
+            user_id: user_id.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+
+    /// Makes a new `Bearer` credentials.
+    pub fn bearer(token: &str) -> Self {
+        Authorization::Bearer {
+            token: token.to_owned(),
+        }
+    }
+
+    /// Returns the name of the authentication scheme.
+    pub fn scheme(&self) -> &str {
+        match *self {
+            Authorization::Basic { .. } => "Basic",
+            Authorization::Bearer { .. } => "Bearer",
+            Authorization::Other { ref scheme, .. } => scheme,
+        }
+    }
+}
+impl<'a> Header<'a> for Authorization {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Authorization"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match *self {
+            Authorization::Basic {
+                ref user_id,
+                ref password,
+            } => {
+                let encoded = base64::encode(format!("{}:{}", user_id, password).as_bytes());
+                write!(writer, "Basic {}", encoded)
+            }
+            Authorization::Bearer { ref token } => write!(writer, "Bearer {}", token),
+            Authorization::Other {
+                ref scheme,
+                ref params,
+            } => write!(writer, "{} {}", scheme, params),
+        }
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        let (scheme, params) = match value.find(' ') {
+            Some(i) => (&value[..i], value[i + 1..].trim()),
+            None => (value, ""),
+        };
+        track_assert!(is_token(scheme), Status::BadRequest, "Malformed auth-scheme");
+        if scheme.eq_ignore_ascii_case("Basic") {
+            let decoded = track!(
+                base64::decode(params).map_err(|e| Error::from(Status::BadRequest.cause(e)))
+            )?;
+            let decoded = track!(
+                String::from_utf8(decoded).map_err(|e| Error::from(Status::BadRequest.cause(e)))
+            )?;
+            let colon = track_assert_some!(
+                decoded.find(':'),
+                Status::BadRequest,
+                "No ':' in Basic credentials"
+            );
+            Ok(Authorization::Basic {
+                user_id: decoded[..colon].to_owned(),
+                password: decoded[colon + 1..].to_owned(),
+            })
+        } else if scheme.eq_ignore_ascii_case("Bearer") {
+            track_assert!(
+                is_token68(params),
+                Status::BadRequest,
+                "Malformed Bearer token"
+            );
+            Ok(Authorization::Bearer {
+                token: params.to_owned(),
+            })
+        } else {
+            Ok(Authorization::Other {
+                scheme: scheme.to_owned(),
+                params: params.to_owned(),
+            })
+        }
+    }
+}
+
+/// An authentication challenge.
+///
+/// This is also used as a `WWW-Authenticate` header which has the single challenge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    scheme: String,
+    token68: Option<String>,
+    params: Vec<(String, String, bool)>,
+}
+impl Challenge {
+    /// Makes a new `Challenge` instance of the scheme `scheme`.
+    pub fn new(scheme: &str) -> Self {
+        Challenge {
+            scheme: scheme.to_owned(),
+            token68: None,
+            params: Vec::new(),
+        }
+    }
+
+    /// Makes a new `Basic` challenge.
+    pub fn basic(realm: &str) -> Self {
+        Challenge::new("Basic").param("realm", realm)
+    }
+
+    /// Makes a new `Bearer` challenge.
+    pub fn bearer(realm: &str) -> Self {
+        Challenge::new("Bearer").param("realm", realm)
+    }
+
+    /// Adds a parameter which value will be sent as a quoted-string.
+    pub fn param(mut self, name: &str, value: &str) -> Self {
+        self.params.push((name.to_owned(), value.to_owned(), true));
+        self
+    }
+
+    /// Adds a parameter which value will be sent as a token.
+    pub fn token_param(mut self, name: &str, value: &str) -> Self {
+        self.params.push((name.to_owned(), value.to_owned(), false));
+        self
+    }
+
+    /// Returns the name of the authentication scheme.
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// Returns the `token68` form value of the challenge.
+    pub fn token68(&self) -> Option<&str> {
+        self.token68.as_deref()
+    }
+
+    /// Returns the value of the parameter named `name` (case-insensitive).
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|p| p.0.eq_ignore_ascii_case(name))
+            .map(|p| p.1.as_str())
+    }
+
+    /// Returns the realm of the challenge.
+    pub fn realm(&self) -> Option<&str> {
+        self.get_param("realm")
+    }
+}
+impl<'a> Header<'a> for Challenge {
+    type Error = Error;
+    fn name() -> &'static str {
+        "WWW-Authenticate"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.scheme)?;
+        if let Some(ref token68) = self.token68 {
+            write!(writer, " {}", token68)?;
+        }
+        for (i, &(ref name, ref value, quoted)) in self.params.iter().enumerate() {
+            let delim = if i == 0 { " " } else { ", " };
+            if quoted {
+                write!(writer, "{}{}={}", delim, name, quote(value))?;
+            } else {
+                write!(writer, "{}{}={}", delim, name, value)?;
+            }
+        }
+        Ok(())
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        let mut challenges = track!(parse_challenges(value))?;
+        track_assert_eq!(
+            challenges.len(),
+            1,
+            Status::BadRequest,
+            "Expected a single challenge"
+        );
+        Ok(challenges.remove(0))
+    }
+}
+
+/// `WWW-Authenticate` response header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WwwAuthenticate(pub Vec<Challenge>);
+impl WwwAuthenticate {
+    /// Collects the challenges in all the `WWW-Authenticate` headers in `headers`.
+    ///
+    /// Malformed headers are ignored.
+    pub fn collect(headers: &Headers) -> Vec<Challenge> {
+        headers
+            .iter()
+            .filter(|&(name, _)| name.eq_ignore_ascii_case(Self::name()))
+            .filter_map(|(_, value)| Self::parse_value_bytes(value).ok())
+            .flat_map(|h| h.0)
+            .collect()
+    }
+}
+impl<'a> Header<'a> for WwwAuthenticate {
+    type Error = Error;
+    fn name() -> &'static str {
+        "WWW-Authenticate"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (i, challenge) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(writer, ", ")?;
+            }
+            challenge.write_value(writer)?;
+        }
+        Ok(())
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        track!(parse_challenges(value)).map(WwwAuthenticate)
+    }
+}
+
+fn parse_challenges(value: &str) -> ::Result<Vec<Challenge>> {
+    let mut challenges: Vec<Challenge> = Vec::new();
+    for item in split_list(value) {
+        let (head, rest) = match item.find([' ', '=']) {
+            Some(i) if item.as_bytes()[i] == b' ' => (&item[..i], item[i + 1..].trim()),
+            Some(_) => ("", item),
+            None => (item, ""),
+        };
+        if !head.is_empty() {
+            track_assert!(is_token(head), Status::BadRequest, "Malformed auth-scheme");
+            challenges.push(Challenge::new(head));
+        }
+        if rest.is_empty() {
+            continue;
+        }
+        let challenge = track_assert_some!(
+            challenges.last_mut(),
+            Status::BadRequest,
+            "No auth-scheme: {:?}",
+            value
+        );
+        match rest.find('=') {
+            Some(i) if !rest[i..].bytes().all(|b| b == b'=') => {
+                let name = rest[..i].trim();
+                let value = rest[i + 1..].trim();
+                track_assert!(is_token(name), Status::BadRequest, "Malformed auth-param");
+                let (value, quoted) = if value.starts_with('"') {
+                    (track!(unquote(value))?, true)
+                } else {
+                    (value.to_owned(), false)
+                };
+                challenge.params.push((name.to_owned(), value, quoted));
+            }
+            _ => {
+                track_assert!(
+                    is_token68(rest) && challenge.params.is_empty(),
+                    Status::BadRequest,
+                    "Malformed challenge: {:?}",
+                    value
+                );
+                challenge.token68 = Some(rest.to_owned());
+            }
+        }
+    }
+    Ok(challenges)
+}
+
+/// Splits a comma separated list (quoted-strings are taken into account).
+pub(crate) fn split_list(value: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut in_quote = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if in_quote && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_quote = !in_quote;
+        } else if c == ',' && !in_quote {
+            items.push(value[start..i].trim());
+            start = i + 1;
+        }
+    }
+    items.push(value[start..].trim());
+    items.retain(|s| !s.is_empty());
+    items
+}
+
+pub(crate) fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub(crate) fn unquote(value: &str) -> ::Result<String> {
+    track_assert!(
+        value.len() >= 2 && value.starts_with('"') && value.ends_with('"'),
+        Status::BadRequest,
+        "Malformed quoted-string: {:?}",
+        value
+    );
+    let mut s = String::with_capacity(value.len());
+    let mut escaped = false;
+    for c in value[1..value.len() - 1].chars() {
+        if !escaped && c == '\\' {
+            escaped = true;
+        } else {
+            s.push(c);
+            escaped = false;
+        }
+    }
+    Ok(s)
+}
+
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| match b {
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
+        | b'`' | b'|' | b'~' => true,
+        _ => b.is_ascii_alphanumeric(),
+    })
+}
+
+fn is_token68(s: &str) -> bool {
+    let body = s.trim_end_matches('=');
+    !body.is_empty() && body.bytes().all(|b| {
+        b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~' | b'+' | b'/')
+    })
+}
+
+/// The result of a credential check performed by `Guard`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Verdict {
+    /// The request is allowed.
+    Allow,
+
+    /// The credentials are invalid (`401 Unauthorized`).
+    Unauthorized,
+
+    /// The credentials are valid, but the access is not allowed (`403 Forbidden`).
+    Forbidden,
+}
+
+/// Server side authentication guard.
+///
+/// The credentials of a request are checked by the user supplied callback.
+/// If the request has no (or malformed) credentials or the callback
+/// returns `Verdict::Unauthorized`, a `401 Unauthorized` response containing
+/// the challenges is made. If the callback returns `Verdict::Forbidden`,
+/// a `403 Forbidden` response is made.
+#[derive(Debug)]
+pub struct Guard<F> {
+    challenges: Vec<Challenge>,
+    check: F,
+}
+impl<F> Guard<F>
+where
+    F: Fn(&Authorization) -> Verdict,
+{
+    /// Makes a new `Guard` instance.
+    pub fn new(challenge: Challenge, check: F) -> Self {
+        Guard {
+            challenges: vec![challenge],
+            check,
+        }
+    }
+
+    /// Adds an additional challenge sent with `401` responses.
+    pub fn add_challenge(&mut self, challenge: Challenge) -> &mut Self {
+        self.challenges.push(challenge);
+        self
+    }
+
+    /// Checks the credentials of `request`.
+    ///
+    /// If the request is allowed, it will be returned as is.
+    /// Otherwise the error response is returned (it should be polled to be sent).
+    /// The body of the rejected request is not read, so the error response has
+    /// the `Connection: close` header and the connection should not be reused.
+    pub fn authorize<T>(&self, request: server::Request<T>) -> Result<server::Request<T>, server::Response<T>>
+    where
+        T: TransportStream,
+    {
+        let verdict = match request.headers().parse::<Authorization>() {
+            Ok(Some(auth)) => (self.check)(&auth),
+            _ => Verdict::Unauthorized,
+        };
+        let status = match verdict {
+            Verdict::Allow => return Ok(request),
+            Verdict::Unauthorized => Status::Unauthorized,
+            Verdict::Forbidden => Status::Forbidden,
+        };
+        let mut response = request.finish().build_response(status);
+        if status == Status::Unauthorized {
+            for challenge in &self.challenges {
+                response.add_header(challenge);
+            }
+        }
+        response.add_raw_header("Connection", b"close");
+        response.add_header(&ContentLength(0));
+        Err(response.finish())
+    }
+}
+
+/// A future which sends a request and retries it once with credentials
+/// if the server responds `401 Unauthorized` with a challenge of the credentials' scheme.
+///
+/// `build` is called to build (and write the body of) the request.
+/// The second argument is the credentials which should be attached to the request
+/// (it is `None` for the first non-preemptive attempt).
+/// Before retrying, the body of the `401` response is discarded
+/// and the same connection is reused.
+#[derive(Debug)]
+pub struct Authenticate<T, F> {
+    credentials: Authorization,
+    build: F,
+    state: State<T>,
+    retried: bool,
+}
+#[derive(Debug)]
+enum State<T> {
+    Send(client::Request<T>),
+    Read(client::ReadResponse<T>),
+    Discard(client::DiscardBody<T>),
+    Done,
+}
+impl<T, F> Authenticate<T, F>
+where
+    T: TransportStream,
+    F: FnMut(client::Connection<T>, Option<&Authorization>) -> client::Request<T>,
+{
+    /// Makes a new `Authenticate` future.
+    ///
+    /// If `preemptive` is `true`, the credentials are sent with the first request.
+    pub fn new(
+        connection: client::Connection<T>,
+        credentials: Authorization,
+        preemptive: bool,
+        mut build: F,
+    ) -> Self {
+        let request = build(
+            connection,
+            if preemptive { Some(&credentials) } else { None },
+        );
+        Authenticate {
+            credentials,
+            build,
+            state: State::Send(request),
+            retried: preemptive,
+        }
+    }
+}
+impl<T, F> Future for Authenticate<T, F>
+where
+    T: TransportStream,
+    F: FnMut(client::Connection<T>, Option<&Authorization>) -> client::Request<T>,
+{
+    type Item = client::Response<T>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Send(ref mut f) => State::Read(try_ready!(track!(f.poll())).read_response()),
+                State::Read(ref mut f) => {
+                    let response = try_ready!(track!(f.poll()));
+                    let retry = !self.retried && response.status().code() == 401
+                        && has_challenge(response.headers(), self.credentials.scheme());
+                    if !retry {
+                        self.state = State::Done;
+                        return Ok(Async::Ready(response));
+                    }
+                    State::Discard(response.discard_body())
+                }
+                State::Discard(ref mut f) => {
+                    let connection = try_ready!(track!(f.poll()));
+                    self.retried = true;
+                    State::Send((self.build)(connection, Some(&self.credentials)))
+                }
+                State::Done => panic!("Cannot poll Authenticate twice"),
+            };
+            self.state = next;
+        }
+    }
+}
+
+/// Returns `true` if `headers` has a challenge of `scheme`.
+pub fn has_challenge(headers: &Headers, scheme: &str) -> bool {
+    WwwAuthenticate::collect(headers)
+        .iter()
+        .any(|c| c.scheme().eq_ignore_ascii_case(scheme))
+}
+
@@ -0,0 +1,1129 @@
+//! HTTP caching ([RFC 7234](https://tools.ietf.org/html/rfc7234)).
+//!
+//! `Cache` is an in-memory private cache for clients.
+//! `CachingClient` layers it on `client::Client`.
+//! The cache itself does not send requests. Instead, a client consults it
+//! before sending a request and feeds it the received responses:
+//!
+//! 1. `Cache::lookup` returns a fresh response, or the validators for revalidating a stale one.
+//! 2. If the response is not fresh, the request is sent
+//!    (with the headers added by `Revalidation::add_headers`).
+//! 3. A `304 Not Modified` response is passed to `Cache::freshen` and
+//!    the other responses are passed to `Cache::store` (the body can be
+//!    read by `client::Response::read_body`).
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate httparse;
+//! extern crate miasht;
+//!
+//! use std::time::{Duration, UNIX_EPOCH};
+//! use miasht::Method;
+//! use miasht::cache::{Cache, Lookup, ManualClock};
+//! use miasht::header::Headers;
+//! use miasht::status::RawStatus;
+//!
+//! # fn main() {
+//! let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+//! let mut cache = Cache::with_clock(clock.clone());
+//!
+//! let request = Headers::new(&[]);
+//! assert!(cache.lookup(Method::Get, "example.com/foo", &request).is_miss());
+//!
+//! let requested_at = cache.now();
+//! let response = [
+//!     httparse::Header { name: "Cache-Control", value: b"max-age=60" },
+//!     httparse::Header { name: "ETag", value: b"\"v1\"" },
+//! ];
+//! let stored = cache.store(
+//!     Method::Get,
+//!     "example.com/foo",
+//!     &request,
+//!     &RawStatus::new(200, "OK"),
+//!     &Headers::new(&response),
+//!     b"foo".to_vec(),
+//!     requested_at,
+//! );
+//! assert!(stored);
+//!
+//! clock.advance(Duration::from_secs(30));
+//! match cache.lookup(Method::Get, "example.com/foo", &request) {
+//!     Lookup::Fresh(cached) => {
+//!         assert_eq!(cached.body(), b"foo");
+//!         assert_eq!(cached.age(), Duration::from_secs(30));
+//!     }
+//!     _ => panic!(),
+//! }
+//!
+//! clock.advance(Duration::from_secs(31));
+//! match cache.lookup(Method::Get, "example.com/foo", &request) {
+//!     Lookup::Stale(revalidation) => assert!(revalidation.get_etag().is_some()),
+//!     _ => panic!(),
+//! }
+//! # }
+//! ```
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use futures::{Async, Future, IntoFuture, Poll};
+use httparse;
+
+use {Error, Method, Status, TransportStream};
+use client::{Call, Client, ReadBody, Response};
+use date::{Date, HttpDate};
+use header::{Header, Headers, HeadersMut};
+use pool::Key;
+use status::RawStatus;
+use uri::Uri;
+
+/// A directive of `Cache-Control` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CacheDirective {
+    /// `no-cache` (the optional field names are ignored).
+    NoCache,
+    NoStore,
+    MaxAge(u64),
+
+    /// `max-stale` (`None` means that any stale response is acceptable).
+    MaxStale(Option<u64>),
+    MinFresh(u64),
+    NoTransform,
+    OnlyIfCached,
+    MustRevalidate,
+    Public,
+
+    /// `private` (the optional field names are ignored).
+    Private,
+    ProxyRevalidate,
+    SMaxAge(u64),
+
+    /// A directive which is not defined by RFC 7234.
+    Extension(String, Option<String>),
+}
+impl fmt::Display for CacheDirective {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CacheDirective::NoCache => write!(f, "no-cache"),
+            CacheDirective::NoStore => write!(f, "no-store"),
+            CacheDirective::MaxAge(n) => write!(f, "max-age={}", n),
+            CacheDirective::MaxStale(None) => write!(f, "max-stale"),
+            CacheDirective::MaxStale(Some(n)) => write!(f, "max-stale={}", n),
+            CacheDirective::MinFresh(n) => write!(f, "min-fresh={}", n),
+            CacheDirective::NoTransform => write!(f, "no-transform"),
+            CacheDirective::OnlyIfCached => write!(f, "only-if-cached"),
+            CacheDirective::MustRevalidate => write!(f, "must-revalidate"),
+            CacheDirective::Public => write!(f, "public"),
+            CacheDirective::Private => write!(f, "private"),
+            CacheDirective::ProxyRevalidate => write!(f, "proxy-revalidate"),
+            CacheDirective::SMaxAge(n) => write!(f, "s-maxage={}", n),
+            CacheDirective::Extension(ref name, None) => write!(f, "{}", name),
+            CacheDirective::Extension(ref name, Some(ref value)) => {
+                if ::auth::is_token(value) {
+                    write!(f, "{}={}", name, value)
+                } else {
+                    write!(f, "{}={}", name, ::auth::quote(value))
+                }
+            }
+        }
+    }
+}
+impl FromStr for CacheDirective {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, value) = match s.find('=') {
+            None => (s, None),
+            Some(i) => {
+                let value = s[i + 1..].trim();
+                if value.starts_with('"') {
+                    (s[..i].trim(), Some(track!(::auth::unquote(value))?))
+                } else {
+                    (s[..i].trim(), Some(value.to_owned()))
+                }
+            }
+        };
+        track_assert!(::auth::is_token(name), Status::BadRequest, "Malformed directive: {:?}", s);
+        let seconds = || -> ::Result<u64> {
+            let value = track_assert_some!(value.as_ref(), Status::BadRequest, "No value: {:?}", s);
+            track_assert!(
+                !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()),
+                Status::BadRequest,
+                "Malformed delta-seconds: {:?}",
+                s
+            );
+            Ok(delta_seconds(value))
+        };
+        let directive = match name.to_ascii_lowercase().as_str() {
+            "no-cache" => CacheDirective::NoCache,
+            "no-store" => CacheDirective::NoStore,
+            "max-age" => CacheDirective::MaxAge(track!(seconds())?),
+            "max-stale" if value.is_none() => CacheDirective::MaxStale(None),
+            "max-stale" => CacheDirective::MaxStale(Some(track!(seconds())?)),
+            "min-fresh" => CacheDirective::MinFresh(track!(seconds())?),
+            "no-transform" => CacheDirective::NoTransform,
+            "only-if-cached" => CacheDirective::OnlyIfCached,
+            "must-revalidate" => CacheDirective::MustRevalidate,
+            "public" => CacheDirective::Public,
+            "private" => CacheDirective::Private,
+            "proxy-revalidate" => CacheDirective::ProxyRevalidate,
+            "s-maxage" => CacheDirective::SMaxAge(track!(seconds())?),
+            _ => CacheDirective::Extension(name.to_owned(), value),
+        };
+        Ok(directive)
+    }
+}
+
+/// `Cache-Control` header.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CacheControl(pub Vec<CacheDirective>);
+impl CacheControl {
+    /// Returns `true` if this contains `directive`.
+    pub fn contains(&self, directive: &CacheDirective) -> bool {
+        self.0.contains(directive)
+    }
+
+    /// Returns the value of the `max-age` directive.
+    pub fn max_age(&self) -> Option<u64> {
+        self.0.iter().filter_map(|d| match *d {
+            CacheDirective::MaxAge(n) => Some(n),
+            _ => None,
+        }).next()
+    }
+
+    /// Returns the value of the `max-stale` directive.
+    pub fn max_stale(&self) -> Option<Option<u64>> {
+        self.0.iter().filter_map(|d| match *d {
+            CacheDirective::MaxStale(n) => Some(n),
+            _ => None,
+        }).next()
+    }
+
+    /// Returns the value of the `min-fresh` directive.
+    pub fn min_fresh(&self) -> Option<u64> {
+        self.0.iter().filter_map(|d| match *d {
+            CacheDirective::MinFresh(n) => Some(n),
+            _ => None,
+        }).next()
+    }
+}
+impl<'a> Header<'a> for CacheControl {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Cache-Control"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (i, directive) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(writer, ", ")?;
+            }
+            write!(writer, "{}", directive)?;
+        }
+        Ok(())
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        let mut directives = Vec::new();
+        for item in ::auth::split_list(value) {
+            directives.push(track!(item.parse())?);
+        }
+        Ok(CacheControl(directives))
+    }
+}
+
+/// `Expires` header.
+///
+/// Note that an invalid value (e.g., `0`) should be treated as a time in the past.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Expires(pub HttpDate);
+impl<'a> Header<'a> for Expires {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Expires"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.0)
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        track!(value.trim().parse()).map(Expires)
+    }
+}
+
+/// `Age` header.
+///
+/// Values greater than `2147483648` are capped to it (RFC 7234 §1.2.1).
+///
+/// # Examples
+///
+/// ```
+/// extern crate httparse;
+/// extern crate miasht;
+///
+/// use miasht::Method;
+/// use miasht::cache::{Age, Cache};
+/// use miasht::header::{Header, Headers};
+/// use miasht::status::RawStatus;
+///
+/// # fn main() {
+/// assert_eq!(Age::parse_value_str("18446744073709551616").unwrap(), Age(2147483648));
+///
+/// let mut cache = Cache::new();
+/// let request = Headers::new(&[]);
+/// let response = [
+///     httparse::Header { name: "Cache-Control", value: b"max-age=18446744073709551615" },
+///     httparse::Header { name: "Age", value: b"18446744073709551615" },
+/// ];
+/// let now = cache.now();
+/// let status = RawStatus::new(200, "OK");
+/// assert!(cache.store(Method::Get, "a", &request, &status, &Headers::new(&response), Vec::new(), now));
+/// assert!(!cache.lookup(Method::Get, "a", &request).is_miss());
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Age(pub u64);
+impl<'a> Header<'a> for Age {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Age"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.0)
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        track_assert!(
+            !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()),
+            Status::BadRequest,
+            "Malformed Age: {:?}",
+            value
+        );
+        Ok(Age(delta_seconds(value)))
+    }
+}
+
+/// `Vary` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Vary {
+    /// `*`
+    Any,
+    Fields(Vec<String>),
+}
+impl<'a> Header<'a> for Vary {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Vary"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match *self {
+            Vary::Any => write!(writer, "*"),
+            Vary::Fields(ref fields) => write!(writer, "{}", fields.join(", ")),
+        }
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        let mut fields = Vec::new();
+        for field in value.split(',').map(|f| f.trim()).filter(|f| !f.is_empty()) {
+            if field == "*" {
+                return Ok(Vary::Any);
+            }
+            track_assert!(::auth::is_token(field), Status::BadRequest, "Malformed Vary: {:?}", value);
+            fields.push(field.to_owned());
+        }
+        Ok(Vary::Fields(fields))
+    }
+}
+
+/// A source of the current time.
+pub trait Clock {
+    /// Returns the current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// `Clock` which returns the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// `Clock` which is moved manually (mainly for testing).
+///
+/// Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<SystemTime>>);
+impl ManualClock {
+    /// Makes a new `ManualClock` instance which points to `now`.
+    pub fn new(now: SystemTime) -> Self {
+        ManualClock(Arc::new(Mutex::new(now)))
+    }
+
+    /// Sets the current time.
+    pub fn set(&self, now: SystemTime) {
+        *self.0.lock().expect("Poisoned lock") = now;
+    }
+
+    /// Advances the current time by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().expect("Poisoned lock") += duration;
+    }
+}
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().expect("Poisoned lock")
+    }
+}
+
+/// The result of `Cache::lookup`.
+#[derive(Debug, Clone)]
+pub enum Lookup {
+    /// A fresh response which can be used without contacting the server.
+    Fresh(CachedResponse),
+
+    /// The stored response is stale (or requires validation).
+    ///
+    /// The request should be sent with the headers added by `Revalidation::add_headers`.
+    Stale(Revalidation),
+
+    /// No response is stored.
+    Miss,
+}
+impl Lookup {
+    /// Returns `true` if this is `Lookup::Miss`.
+    pub fn is_miss(&self) -> bool {
+        matches!(*self, Lookup::Miss)
+    }
+}
+
+/// The validators of a stored response which are used to revalidate it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revalidation {
+    etag: Option<Vec<u8>>,
+    last_modified: Option<Vec<u8>>,
+}
+impl Revalidation {
+    /// Returns the raw value of the `ETag` header of the stored response.
+    pub fn get_etag(&self) -> Option<&[u8]> {
+        self.etag.as_deref()
+    }
+
+    /// Returns the raw value of the `Last-Modified` header of the stored response.
+    pub fn get_last_modified(&self) -> Option<&[u8]> {
+        self.last_modified.as_deref()
+    }
+
+    /// Adds the `If-None-Match` and `If-Modified-Since` headers.
+    pub fn add_headers(&self, headers: &mut HeadersMut) {
+        if let Some(ref etag) = self.etag {
+            headers.add_raw_header("If-None-Match", etag);
+        }
+        if let Some(ref last_modified) = self.last_modified {
+            headers.add_raw_header("If-Modified-Since", last_modified);
+        }
+    }
+}
+
+/// A response returned from `Cache`.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    code: u16,
+    reason: String,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+    age: Duration,
+}
+impl CachedResponse {
+    /// Returns the status of the response.
+    pub fn status(&self) -> RawStatus<'_> {
+        RawStatus::new(self.code, &self.reason)
+    }
+
+    /// Returns the header fields of the response.
+    ///
+    /// They can be wrapped by `header::Headers::new`.
+    pub fn header_fields(&self) -> Vec<httparse::Header<'_>> {
+        self.headers
+            .iter()
+            .map(|(name, value)| httparse::Header { name, value })
+            .collect()
+    }
+
+    /// Returns the body of the response.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Converts into the body of the response.
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
+    /// Returns the current age of the response (RFC 7234 §4.2.3).
+    ///
+    /// It should be sent as the `Age` header if the response is forwarded.
+    pub fn age(&self) -> Duration {
+        self.age
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    code: u16,
+    reason: String,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+    vary: Vec<(String, Option<Vec<u8>>)>,
+    request_time: SystemTime,
+    response_time: SystemTime,
+}
+impl Entry {
+    fn with_headers<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Headers) -> R,
+    {
+        let fields = self.headers
+            .iter()
+            .map(|(name, value)| httparse::Header { name, value })
+            .collect::<Vec<_>>();
+        f(&Headers::new(&fields))
+    }
+
+    fn matches(&self, request: &Headers) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request.get(name) == value.as_deref())
+    }
+
+    // RFC 7234 §4.2.3
+    fn current_age(&self, now: SystemTime) -> Duration {
+        let (date, age) = self.with_headers(|h| {
+            let date = h.parse::<Date>().ok().and_then(|d| d).map(|d| d.0.to_system_time());
+            let age = h.parse::<Age>().ok().and_then(|a| a).map_or(0, |a| a.0);
+            (date, Duration::from_secs(age))
+        });
+        let apparent_age = date.and_then(|d| self.response_time.duration_since(d).ok())
+            .unwrap_or_default();
+        let response_delay = self.response_time
+            .duration_since(self.request_time)
+            .unwrap_or_default();
+        let corrected_initial_age = cmp::max(apparent_age, age.saturating_add(response_delay));
+        let resident_time = now.duration_since(self.response_time).unwrap_or_default();
+        corrected_initial_age.saturating_add(resident_time)
+    }
+
+    // RFC 7234 §4.2.1
+    fn freshness_lifetime(&self) -> Duration {
+        self.with_headers(|h| {
+            let cache_control = h.parse::<CacheControl>().ok().and_then(|c| c).unwrap_or_default();
+            if let Some(max_age) = cache_control.max_age() {
+                return Duration::from_secs(max_age);
+            }
+            let date = h.parse::<Date>().ok().and_then(|d| d).map_or_else(
+                || HttpDate::from(self.response_time),
+                |d| d.0,
+            );
+            if h.get(Expires::name()).is_some() {
+                return match h.parse::<Expires>() {
+                    Ok(Some(expires)) => Duration::from_secs(expires.0.as_secs().saturating_sub(date.as_secs())),
+                    _ => Duration::from_secs(0),
+                };
+            }
+            if is_heuristically_cacheable(self.code) || cache_control.contains(&CacheDirective::Public) {
+                // RFC 7234 §4.2.2: 10% of the time since the last modification
+                if let Some(ref last_modified) = h.parse::<::conditional::LastModified>().ok().and_then(|l| l) {
+                    return Duration::from_secs(date.as_secs().saturating_sub(last_modified.0.as_secs()) / 10);
+                }
+            }
+            Duration::from_secs(0)
+        })
+    }
+
+    fn to_response(&self, now: SystemTime) -> CachedResponse {
+        CachedResponse {
+            code: self.code,
+            reason: self.reason.clone(),
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+            age: self.current_age(now),
+        }
+    }
+}
+
+/// An in-memory private cache for clients.
+///
+/// Responses are stored per key (typically the host and the path of the request target).
+/// Multiple responses are stored for a key if they vary on request headers (`Vary`).
+#[derive(Debug)]
+pub struct Cache<C = SystemClock> {
+    clock: C,
+    entries: HashMap<String, Vec<Entry>>,
+    max_entries: usize,
+    len: usize,
+}
+impl Cache<SystemClock> {
+    /// Makes a new `Cache` instance which uses the system clock.
+    pub fn new() -> Self {
+        Cache::with_clock(SystemClock)
+    }
+}
+impl Default for Cache<SystemClock> {
+    fn default() -> Self {
+        Cache::new()
+    }
+}
+impl<C: Clock> Cache<C> {
+    /// The default value of the maximum number of the stored responses.
+    pub const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+    /// Makes a new `Cache` instance which uses `clock`.
+    pub fn with_clock(clock: C) -> Self {
+        Cache {
+            clock,
+            entries: HashMap::new(),
+            max_entries: Self::DEFAULT_MAX_ENTRIES,
+            len: 0,
+        }
+    }
+
+    /// Sets the maximum number of the stored responses.
+    ///
+    /// If the limit is exceeded, the oldest response is evicted.
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+        self.evict();
+    }
+
+    /// Returns the current time of the clock of this cache.
+    ///
+    /// This should be called just before sending a request, and
+    /// the result should be passed to `store` or `freshen` as `request_time`.
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    /// Returns the number of the stored responses.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no response is stored.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Looks up the response for a request.
+    ///
+    /// Only `GET` requests are served from the cache.
+    /// The request directives `no-store`, `no-cache`, `max-age`, `max-stale` and `min-fresh`
+    /// (and `Pragma: no-cache`) are honoured.
+    pub fn lookup(&self, method: Method, key: &str, request: &Headers) -> Lookup {
+        if method != Method::Get {
+            return Lookup::Miss;
+        }
+        let request_cc = request_cache_control(request);
+        if request_cc.contains(&CacheDirective::NoStore) {
+            return Lookup::Miss;
+        }
+        let entry = match self.entries
+            .get(key)
+            .and_then(|entries| entries.iter().rev().find(|e| e.matches(request)))
+        {
+            None => return Lookup::Miss,
+            Some(entry) => entry,
+        };
+
+        let now = self.clock.now();
+        let response_cc = entry.with_headers(|h| h.parse::<CacheControl>().ok().and_then(|c| c).unwrap_or_default());
+        let age = entry.current_age(now).as_secs();
+        let lifetime = entry.freshness_lifetime().as_secs();
+        let mut fresh = age < lifetime
+            && !request_cc.contains(&CacheDirective::NoCache)
+            && !response_cc.contains(&CacheDirective::NoCache);
+        if let Some(max_age) = request_cc.max_age() {
+            fresh &= age <= max_age;
+        }
+        if let Some(min_fresh) = request_cc.min_fresh() {
+            fresh &= age.saturating_add(min_fresh) < lifetime;
+        }
+        if !fresh && age >= lifetime && !response_cc.contains(&CacheDirective::MustRevalidate)
+            && !response_cc.contains(&CacheDirective::NoCache)
+            && !request_cc.contains(&CacheDirective::NoCache)
+            && request_cc.max_age().is_none_or(|max_age| age <= max_age)
+        {
+            // RFC 7234 §4.2.4
+            match request_cc.max_stale() {
+                Some(None) => fresh = true,
+                Some(Some(max_stale)) => fresh = age - lifetime <= max_stale,
+                None => {}
+            }
+        }
+
+        if fresh {
+            Lookup::Fresh(entry.to_response(now))
+        } else {
+            let get = |name: &str| entry.with_headers(|h| h.get(name).map(|v| v.to_owned()));
+            Lookup::Stale(Revalidation {
+                etag: get("ETag"),
+                last_modified: get("Last-Modified"),
+            })
+        }
+    }
+
+    /// Stores the response for a request (RFC 7234 §3).
+    ///
+    /// `request_time` is the time at which the request was sent (see `now`).
+    /// If the response cannot be stored, this method returns `false`.
+    ///
+    /// Only the responses of the status codes understood by the cache are stored.
+    /// Partial content is never stored (i.e., responses to requests which have
+    /// the `Range` header and `206 Partial Content` responses), because the cache
+    /// cannot combine it into the full representation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate httparse;
+    /// extern crate miasht;
+    ///
+    /// use std::time::SystemTime;
+    /// use miasht::Method;
+    /// use miasht::cache::Cache;
+    /// use miasht::header::Headers;
+    /// use miasht::status::RawStatus;
+    ///
+    /// # fn main() {
+    /// let mut cache = Cache::new();
+    /// let fields = [httparse::Header { name: "Cache-Control", value: b"max-age=60" }];
+    /// let response = Headers::new(&fields);
+    /// let store = |cache: &mut Cache, request: &Headers, code: u16| {
+    ///     let status = RawStatus::new(code, "");
+    ///     cache.store(Method::Get, "http://example.com/", request, &status, &response, Vec::new(), SystemTime::now())
+    /// };
+    ///
+    /// let range = [httparse::Header { name: "Range", value: b"bytes=0-9" }];
+    /// assert!(!store(&mut cache, &Headers::new(&[]), 206));
+    /// assert!(!store(&mut cache, &Headers::new(&range), 200));
+    /// assert!(!store(&mut cache, &Headers::new(&[]), 503));
+    /// assert!(store(&mut cache, &Headers::new(&[]), 200));
+    /// # }
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn store(
+        &mut self,
+        method: Method,
+        key: &str,
+        request: &Headers,
+        status: &RawStatus,
+        response: &Headers,
+        body: Vec<u8>,
+        request_time: SystemTime,
+    ) -> bool {
+        if method != Method::Get {
+            if status.code() < 400 && !method.is_safe() {
+                // RFC 7234 §4.4
+                self.invalidate(key);
+            }
+            return false;
+        }
+        if request_cache_control(request).contains(&CacheDirective::NoStore)
+            || request.get("Range").is_some()
+        {
+            return false;
+        }
+        if !is_understood(status.code()) {
+            return false;
+        }
+        let response_cc = response.parse::<CacheControl>().ok().and_then(|c| c).unwrap_or_default();
+        if response_cc.contains(&CacheDirective::NoStore) {
+            return false;
+        }
+        let explicit = response.get(Expires::name()).is_some() || response_cc.max_age().is_some()
+            || response_cc.contains(&CacheDirective::Public);
+        if !explicit && !is_heuristically_cacheable(status.code()) {
+            return false;
+        }
+        let vary = match response.parse::<Vary>() {
+            Ok(None) => Vec::new(),
+            Ok(Some(Vary::Fields(fields))) => fields
+                .into_iter()
+                .map(|f| {
+                    let value = request.get(&f).map(|v| v.to_owned());
+                    (f, value)
+                })
+                .collect(),
+            Ok(Some(Vary::Any)) | Err(_) => return false,
+        };
+        let entry = Entry {
+            code: status.code(),
+            reason: status.reason().to_owned(),
+            headers: response
+                .iter()
+                .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                .collect(),
+            body,
+            vary,
+            request_time,
+            response_time: self.clock.now(),
+        };
+
+        let entries = self.entries.entry(key.to_owned()).or_default();
+        let old_len = entries.len();
+        entries.retain(|e| !e.vary.iter().eq(entry.vary.iter()));
+        entries.push(entry);
+        self.len = self.len + entries.len() - old_len;
+        self.evict();
+        true
+    }
+
+    /// Updates the stored response with a `304 Not Modified` response (RFC 7234 §4.3.4)
+    /// and returns it.
+    ///
+    /// If no stored response is selected, this method returns `None`
+    /// (the request should be sent again without the conditional headers).
+    pub fn freshen(
+        &mut self,
+        key: &str,
+        request: &Headers,
+        response: &Headers,
+        request_time: SystemTime,
+    ) -> Option<CachedResponse> {
+        let now = self.clock.now();
+        let etag = response.get("ETag");
+        let entry = self.entries.get_mut(key)?.iter_mut().rev().find(|e| {
+            e.matches(request) && etag.is_none_or(|etag| {
+                e.headers
+                    .iter()
+                    .any(|(n, v)| n.eq_ignore_ascii_case("ETag") && v.as_slice() == etag)
+            })
+        })?;
+        for (name, _) in response.iter() {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                continue;
+            }
+            entry.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        }
+        for (name, value) in response.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                entry.headers.push((name.to_owned(), value.to_owned()));
+            }
+        }
+        entry.request_time = request_time;
+        entry.response_time = now;
+        Some(entry.to_response(now))
+    }
+
+    /// Removes the responses stored for `key`.
+    pub fn invalidate(&mut self, key: &str) {
+        if let Some(entries) = self.entries.remove(key) {
+            self.len -= entries.len();
+        }
+    }
+
+    /// Removes all the stored responses.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.len = 0;
+    }
+
+    fn evict(&mut self) {
+        while self.len > self.max_entries {
+            let oldest = self.entries
+                .iter()
+                .flat_map(|(k, entries)| entries.iter().enumerate().map(move |(i, e)| (e.response_time, k, i)))
+                .min()
+                .map(|(_, k, i)| (k.clone(), i));
+            let (key, i) = match oldest {
+                None => break,
+                Some(x) => x,
+            };
+            let entries = self.entries.get_mut(&key).expect("Never fails");
+            entries.remove(i);
+            if entries.is_empty() {
+                self.entries.remove(&key);
+            }
+            self.len -= 1;
+        }
+    }
+}
+
+// Too large values are treated as the greatest positive integer (RFC 7234 §1.2.1).
+fn delta_seconds(value: &str) -> u64 {
+    const MAX: u64 = 2_147_483_648;
+    value.parse().map_or(MAX, |n: u64| cmp::min(n, MAX))
+}
+
+fn request_cache_control(request: &Headers) -> CacheControl {
+    match request.parse::<CacheControl>() {
+        Ok(Some(cc)) => cc,
+        Ok(None) => {
+            // RFC 7234 §5.4
+            let pragma = request.get("Pragma").and_then(|v| ::std::str::from_utf8(v).ok());
+            if pragma.is_some_and(|p| p.split(',').any(|d| d.trim().eq_ignore_ascii_case("no-cache"))) {
+                CacheControl(vec![CacheDirective::NoCache])
+            } else {
+                CacheControl::default()
+            }
+        }
+        Err(_) => CacheControl::default(),
+    }
+}
+
+// RFC 7231 §6.1 (except for `206 Partial Content` which this cache does not store)
+fn is_heuristically_cacheable(code: u16) -> bool {
+    matches!(code, 200 | 203 | 204 | 300 | 301 | 404 | 405 | 410 | 414 | 501)
+}
+
+/// Returns `true` if the cache knows the semantics of `code` (RFC 7234 §3).
+fn is_understood(code: u16) -> bool {
+    is_heuristically_cacheable(code) || matches!(code, 302 | 307 | 308)
+}
+
+/// The default value of the maximum size of a response body read by `CachingClient`.
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 10 * 1024 * 1024;
+
+/// A `client::Client` which serves `GET` requests from a `Cache`.
+///
+/// Fresh responses are returned without contacting the server,
+/// stale ones are revalidated with conditional requests, and
+/// the other responses are stored in the cache if they are cacheable.
+/// The responses are keyed by their URLs (without fragments).
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate futures;
+/// # extern crate miasht;
+/// use futures::Future;
+/// use miasht::{Error, TransportStream};
+/// use miasht::cache::{Cache, CachingClient};
+/// use miasht::client::Client;
+/// use miasht::pool::Key;
+///
+/// fn fetch<T, F>(connect: F)
+/// where
+///     T: TransportStream,
+///     F: FnMut(&Key) -> Result<T, Error>,
+/// {
+///     let mut client = CachingClient::new(Client::new(connect), Cache::new());
+///     for _ in 0..2 {
+///         let response = client
+///             .get("http://example.com/index.html")
+///             .add_raw_header("Accept", b"text/html")
+///             .wait()
+///             .unwrap();
+///         println!("{} (age={:?})", response.status().code(), response.age());
+///     }
+///     println!("{} responses are cached", client.cache().lock().unwrap().len());
+/// }
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct CachingClient<C, K = SystemClock> {
+    client: Client<C>,
+    cache: Arc<Mutex<Cache<K>>>,
+    max_body_size: u64,
+}
+impl<C, F, T, K> CachingClient<C, K>
+where
+    C: FnMut(&Key) -> F,
+    F: IntoFuture<Item = T, Error = Error>,
+    T: TransportStream,
+    K: Clock,
+{
+    /// Makes a new `CachingClient` instance.
+    pub fn new(client: Client<C>, cache: Cache<K>) -> Self {
+        CachingClient {
+            client,
+            cache: Arc::new(Mutex::new(cache)),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Sets the maximum size of a response body.
+    ///
+    /// If a body exceeds it, the request fails with `Status::PayloadTooLarge`.
+    pub fn set_max_body_size(&mut self, max: u64) {
+        self.max_body_size = max;
+    }
+
+    /// Returns a reference to the underlying client.
+    pub fn client_mut(&mut self) -> &mut Client<C> {
+        &mut self.client
+    }
+
+    /// Returns the cache shared by the requests.
+    pub fn cache(&self) -> &Arc<Mutex<Cache<K>>> {
+        &self.cache
+    }
+
+    /// Starts a `GET` request to `url`.
+    ///
+    /// If `url` is not an absolute `http` or `https` URL,
+    /// the resulting future will fail with `Status::BadRequest`.
+    pub fn get(&mut self, url: &str) -> CachedCall<'_, C, T, F, K> {
+        let key = url.parse::<Uri>()
+            .map(|u| u.normalize().without_fragment().to_string())
+            .unwrap_or_else(|_| url.to_owned());
+        CachedCall {
+            client: &mut self.client,
+            cache: Arc::clone(&self.cache),
+            max_body_size: self.max_body_size,
+            url: url.to_owned(),
+            key,
+            headers: Vec::new(),
+            state: CallState::Start,
+        }
+    }
+}
+
+/// A future which sends a request started by `CachingClient` (or serves it from the cache).
+pub struct CachedCall<'a, C: 'a, T: TransportStream, F: IntoFuture, K> {
+    client: &'a mut Client<C>,
+    cache: Arc<Mutex<Cache<K>>>,
+    max_body_size: u64,
+    url: String,
+    key: String,
+    headers: Vec<(String, Vec<u8>)>,
+    state: CallState<T, F>,
+}
+impl<'a, C, F, T, K> CachedCall<'a, C, T, F, K>
+where
+    C: FnMut(&Key) -> F,
+    F: IntoFuture<Item = T, Error = Error>,
+    T: TransportStream,
+    K: Clock,
+{
+    /// Adds a header to the request.
+    ///
+    /// The request headers are also used to select a stored response (e.g., `Vary`)
+    /// and to find the request directives of `Cache-Control`.
+    pub fn add_raw_header(mut self, name: &str, value: &[u8]) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    fn with_request_headers<G, R>(&self, f: G) -> R
+    where
+        G: FnOnce(&Headers) -> R,
+    {
+        let fields = self.headers
+            .iter()
+            .map(|(name, value)| httparse::Header { name, value })
+            .collect::<Vec<_>>();
+        f(&Headers::new(&fields))
+    }
+
+    fn send(&mut self, revalidation: Option<Revalidation>) -> CallState<T, F> {
+        let request_time = self.cache.lock().expect("Poisoned lock").now();
+        let mut call = self.client.request(Method::Get, &self.url);
+        for (name, value) in &self.headers {
+            call = call.add_raw_header(name, value);
+        }
+        if let Some(ref revalidation) = revalidation {
+            if let Some(etag) = revalidation.get_etag() {
+                call = call.add_raw_header("If-None-Match", etag);
+            }
+            if let Some(last_modified) = revalidation.get_last_modified() {
+                call = call.add_raw_header("If-Modified-Since", last_modified);
+            }
+        }
+        CallState::Send(call, revalidation.is_some(), request_time)
+    }
+
+    fn handle_response(
+        &self,
+        response: &Response<T>,
+        body: Vec<u8>,
+        revalidated: bool,
+        request_time: SystemTime,
+    ) -> Option<CachedResponse> {
+        let mut cache = self.cache.lock().expect("Poisoned lock");
+        let key = &self.key;
+        if revalidated && response.status().code() == 304 {
+            // If no stored response is selected, the request is sent again unconditionally.
+            return self.with_request_headers(|h| cache.freshen(key, h, response.headers(), request_time));
+        }
+        self.with_request_headers(|h| {
+            let status = response.status();
+            cache.store(Method::Get, key, h, status, response.headers(), body.clone(), request_time)
+        });
+        Some(CachedResponse {
+            code: response.status().code(),
+            reason: response.status().reason().to_owned(),
+            headers: response
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                .collect(),
+            body,
+            age: Duration::from_secs(0),
+        })
+    }
+}
+impl<'a, C, F, T, K> Future for CachedCall<'a, C, T, F, K>
+where
+    C: FnMut(&Key) -> F,
+    F: IntoFuture<Item = T, Error = Error>,
+    T: TransportStream,
+    K: Clock,
+{
+    type Item = CachedResponse;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match mem::replace(&mut self.state, CallState::Done) {
+                CallState::Start => {
+                    let lookup = {
+                        let cache = self.cache.lock().expect("Poisoned lock");
+                        self.with_request_headers(|h| cache.lookup(Method::Get, &self.key, h))
+                    };
+                    match lookup {
+                        Lookup::Fresh(response) => return Ok(Async::Ready(response)),
+                        Lookup::Stale(revalidation) => self.send(Some(revalidation)),
+                        Lookup::Miss => self.send(None),
+                    }
+                }
+                CallState::Send(mut call, revalidating, request_time) => match track!(call.poll())? {
+                    Async::NotReady => {
+                        self.state = CallState::Send(call, revalidating, request_time);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready(response) => {
+                        let read = response.read_body(self.max_body_size);
+                        CallState::Read(read, revalidating, request_time)
+                    }
+                },
+                CallState::Read(mut read, revalidating, request_time) => match track!(read.poll())? {
+                    Async::NotReady => {
+                        self.state = CallState::Read(read, revalidating, request_time);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready((response, body)) => {
+                        match self.handle_response(&response, body, revalidating, request_time) {
+                            Some(cached) => return Ok(Async::Ready(cached)),
+                            None => self.send(None),
+                        }
+                    }
+                },
+                CallState::Done => panic!("Cannot poll CachedCall twice"),
+            };
+            self.state = next;
+        }
+    }
+}
+impl<'a, C, T: TransportStream, F: IntoFuture, K> fmt::Debug for CachedCall<'a, C, T, F, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CachedCall {{ url: {:?}, key: {:?}, .. }}", self.url, self.key)
+    }
+}
+
+enum CallState<T: TransportStream, F: IntoFuture> {
+    Start,
+    Send(Call<T, F>, bool, SystemTime),
+    Read(ReadBody<T>, bool, SystemTime),
+    Done,
+}
@@ -0,0 +1,345 @@
+//! Chunked transfer coding ([RFC 7230 §4.1](https://tools.ietf.org/html/rfc7230#section-4.1)).
+//!
+//! Both the encoder and the decoder can be used with non-blocking streams
+//! (i.e., operations failed with `WouldBlock` can be retried).
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::{Read, Write};
+//! use miasht::chunked::{ChunkedDecoder, ChunkedEncoder};
+//!
+//! let mut encoder = ChunkedEncoder::new(Vec::new());
+//! encoder.write_all(b"Hello, ").unwrap();
+//! encoder.write_all(b"World!").unwrap();
+//! let encoded = encoder.finish().unwrap();
+//! assert_eq!(encoded, b"7\r\nHello, \r\n6\r\nWorld!\r\n0\r\n\r\n");
+//!
+//! let mut decoder = ChunkedDecoder::new(&encoded[..]);
+//! let mut decoded = String::new();
+//! decoder.read_to_string(&mut decoded).unwrap();
+//! assert_eq!(decoded, "Hello, World!");
+//! assert!(decoder.is_finished());
+//! ```
+use std::io::{self, BufRead, Read, Write};
+
+/// A writer which encodes the written data into chunks.
+///
+/// Each `write` call produces a chunk.
+/// `finish` (or `try_finish`) must be called to write the last chunk.
+#[derive(Debug)]
+pub struct ChunkedEncoder<W> {
+    inner: W,
+    pending: Vec<u8>,
+    offset: usize,
+    finished: bool,
+}
+impl<W: Write> ChunkedEncoder<W> {
+    /// Makes a new `ChunkedEncoder` instance.
+    pub fn new(inner: W) -> Self {
+        ChunkedEncoder {
+            inner,
+            pending: Vec::new(),
+            offset: 0,
+            finished: false,
+        }
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Returns the inner writer.
+    ///
+    /// Note that the unwritten chunks are discarded.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes the last chunk and flushes the inner writer.
+    ///
+    /// This can be called again if it failed with `WouldBlock`.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.flush_pending()?;
+        if !self.finished {
+            self.finished = true;
+            self.pending.extend_from_slice(b"0\r\n\r\n");
+            self.offset = 0;
+        }
+        self.flush()
+    }
+
+    /// Writes the last chunk and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner)
+    }
+
+    fn flush_pending(&mut self) -> io::Result<()> {
+        while self.offset < self.pending.len() {
+            match self.inner.write(&self.pending[self.offset..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "Failed to write a chunk",
+                    ))
+                }
+                Ok(n) => self.offset += n,
+                Err(e) => return Err(e),
+            }
+        }
+        self.pending.clear();
+        self.offset = 0;
+        Ok(())
+    }
+}
+impl<W: Write> Write for ChunkedEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "Cannot write into finished chunked stream",
+            ));
+        }
+        self.flush_pending()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.pending, "{:x}\r\n", buf.len())?;
+        self.pending.extend_from_slice(buf);
+        self.pending.extend_from_slice(b"\r\n");
+        match self.flush_pending() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+            Ok(()) => {}
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_pending()?;
+        self.inner.flush()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Size { size: u64, digits: usize, in_ext: bool },
+    SizeLf { size: u64 },
+    Data { remaining: u64 },
+    DataCr,
+    DataLf,
+    Trailer { line_len: usize },
+    TrailerLf { line_len: usize },
+    Finished,
+}
+
+/// A reader which decodes a chunked body.
+///
+/// Chunk extensions and trailer fields are discarded.
+/// The reader returns EOF after the last chunk, so the inner reader is positioned
+/// just after the chunked body (the inner reader is a `BufRead` for this reason:
+/// the bytes after the body are left in its buffer).
+///
+/// # Examples
+///
+/// ```
+/// use std::cmp;
+/// use std::io::{self, BufRead, ErrorKind, Read};
+/// use miasht::chunked::ChunkedDecoder;
+///
+/// fn decode(input: &[u8]) -> io::Result<Vec<u8>> {
+///     let mut body = Vec::new();
+///     ChunkedDecoder::new(input).read_to_end(&mut body)?;
+///     Ok(body)
+/// }
+/// assert_eq!(decode(b"3;ext=1\r\nabc\r\n0\r\nTrailer: x\r\n\r\n").unwrap(), b"abc");
+///
+/// // Malformed chunk size
+/// assert_eq!(decode(b"xyz\r\nabc\r\n0\r\n\r\n").unwrap_err().kind(), ErrorKind::InvalidData);
+/// assert_eq!(decode(b"10000000000000000\r\n").unwrap_err().kind(), ErrorKind::InvalidData);
+///
+/// // Missing CRLF after the chunk data
+/// assert_eq!(decode(b"3\r\nabcd\r\n0\r\n\r\n").unwrap_err().kind(), ErrorKind::InvalidData);
+///
+/// // EOF in the middle of a chunk
+/// assert_eq!(decode(b"5\r\nabc").unwrap_err().kind(), ErrorKind::UnexpectedEof);
+///
+/// // Decoding is resumed after `WouldBlock`.
+/// struct Trickle<'a>(&'a [u8], bool);
+/// impl<'a> Read for Trickle<'a> {
+///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+///         let n = {
+///             let bytes = self.fill_buf()?;
+///             let n = cmp::min(bytes.len(), buf.len());
+///             buf[..n].copy_from_slice(&bytes[..n]);
+///             n
+///         };
+///         self.consume(n);
+///         Ok(n)
+///     }
+/// }
+/// impl<'a> BufRead for Trickle<'a> {
+///     fn fill_buf(&mut self) -> io::Result<&[u8]> {
+///         self.1 = !self.1;
+///         if self.1 {
+///             return Err(ErrorKind::WouldBlock.into());
+///         }
+///         Ok(&self.0[..cmp::min(self.0.len(), 2)])
+///     }
+///     fn consume(&mut self, amt: usize) {
+///         self.0 = &self.0[amt..];
+///     }
+/// }
+/// let mut decoder = ChunkedDecoder::new(Trickle(b"3\r\nabc\r\n0\r\n\r\nnext", false));
+/// let mut body = Vec::new();
+/// let mut buf = [0; 16];
+/// loop {
+///     match decoder.read(&mut buf) {
+///         Ok(0) => break,
+///         Ok(n) => body.extend_from_slice(&buf[..n]),
+///         Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+///         Err(e) => panic!("{}", e),
+///     }
+/// }
+/// assert_eq!(body, b"abc");
+/// assert!(decoder.is_finished());
+/// assert_eq!(decoder.into_inner().0, b"next");
+/// ```
+#[derive(Debug)]
+pub struct ChunkedDecoder<R> {
+    inner: R,
+    state: State,
+}
+impl<R> ChunkedDecoder<R> {
+    /// Makes a new `ChunkedDecoder` instance.
+    pub fn new(inner: R) -> Self {
+        ChunkedDecoder {
+            inner,
+            state: State::Size {
+                size: 0,
+                digits: 0,
+                in_ext: false,
+            },
+        }
+    }
+
+    /// Returns `true` if the whole body has been read.
+    pub fn is_finished(&self) -> bool {
+        self.state == State::Finished
+    }
+
+    /// Returns a reference to the inner reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Returns the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+impl<R: BufRead> Read for ChunkedDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Finished => return Ok(0),
+                State::Data { remaining } => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let limit = if (buf.len() as u64) < remaining {
+                        buf.len()
+                    } else {
+                        remaining as usize
+                    };
+                    let n = self.inner.read(&mut buf[..limit])?;
+                    if n == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "Unexpected EOF in a chunked body",
+                        ));
+                    }
+                    self.state = if remaining == n as u64 {
+                        State::DataCr
+                    } else {
+                        State::Data {
+                            remaining: remaining - n as u64,
+                        }
+                    };
+                    return Ok(n);
+                }
+                mut state => {
+                    let consumed = {
+                        let bytes = self.inner.fill_buf()?;
+                        if bytes.is_empty() {
+                            return Err(io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                "Unexpected EOF in a chunked body",
+                            ));
+                        }
+                        let mut consumed = 0;
+                        for &b in bytes {
+                            state = next_state(state, b)?;
+                            consumed += 1;
+                            if let State::Data { .. } | State::Finished = state {
+                                break;
+                            }
+                        }
+                        consumed
+                    };
+                    self.inner.consume(consumed);
+                    self.state = state;
+                }
+            }
+        }
+    }
+}
+
+fn next_state(state: State, b: u8) -> io::Result<State> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Malformed chunked body");
+    Ok(match (state, b) {
+        (State::Size { size, digits, in_ext: false }, _) if (b as char).is_ascii_hexdigit() => {
+            if digits >= 16 {
+                return Err(invalid());
+            }
+            State::Size {
+                size: (size << 4) | u64::from((b as char).to_digit(16).expect("Never fails")),
+                digits: digits + 1,
+                in_ext: false,
+            }
+        }
+        (State::Size { digits: 0, .. }, _) => return Err(invalid()),
+        (State::Size { size, .. }, b'\r') => State::SizeLf { size },
+        (State::Size { size, digits, .. }, _) => State::Size {
+            size,
+            digits,
+            in_ext: true,
+        },
+        (State::SizeLf { size: 0 }, b'\n') => State::Trailer { line_len: 0 },
+        (State::SizeLf { size }, b'\n') => State::Data { remaining: size },
+        (State::DataCr, b'\r') => State::DataLf,
+        (State::DataLf, b'\n') => State::Size {
+            size: 0,
+            digits: 0,
+            in_ext: false,
+        },
+        (State::Trailer { line_len }, b'\r') => State::TrailerLf { line_len },
+        (State::Trailer { line_len }, _) => State::Trailer {
+            line_len: line_len + 1,
+        },
+        (State::TrailerLf { line_len: 0 }, b'\n') => State::Finished,
+        (State::TrailerLf { .. }, b'\n') => State::Trailer { line_len: 0 },
+        _ => return Err(invalid()),
+    })
+}
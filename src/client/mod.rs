@@ -1,38 +1,74 @@
 pub use self::request::{Request, RequestBuilder};
-pub use self::response::{ReadResponse, Response};
+pub use self::simple::{Call, Client, DEFAULT_USER_AGENT};
+pub use self::response::{DecodedBody, DiscardBody, ReadAllBytes, ReadAllString, ReadBody, ReadResponse,
+                         Response};
 
 use {Method, Version};
 use connection::{self, TransportStream};
+use query;
+use upgrade::Upgraded;
 
 mod request;
 mod response;
+mod simple;
 
 #[derive(Debug)]
 pub struct Connection<T> {
     inner: connection::Connection<T>,
     version: Version,
+    method: Method,
 }
 impl<T: TransportStream> Connection<T> {
-    // fn new(stream: T, client: &Client) -> Self {
-    //     let max_header_count = client.max_response_header_count;
-    //     let inner = connection::Connection::new(
-    //         stream,
-    //         client.min_buffer_size,
-    //         client.max_buffer_size,
-    //         max_header_count,
-    //     );
-    //     Connection {
-    //         inner: inner,
-    //         version: client.version,
-    //     }
-    // }
+    pub fn new(
+        stream: T,
+        min_buffer_size: usize,
+        max_buffer_size: usize,
+        max_header_count: usize,
+    ) -> Self {
+        let inner =
+            connection::Connection::new(stream, min_buffer_size, max_buffer_size, max_header_count);
+        Connection {
+            inner,
+            version: Version::default(),
+            method: Method::Get,
+        }
+    }
     pub fn build_request(self, method: Method, path: &str) -> RequestBuilder<T> {
         request::builder(self, method, path)
     }
 
+    /// Builds a request which has the query string encoded by `query`.
+    pub fn build_request_with_query(
+        self,
+        method: Method,
+        path: &str,
+        query: &query::Encoder,
+    ) -> RequestBuilder<T> {
+        if query.is_empty() {
+            self.build_request(method, path)
+        } else {
+            let sep = if path.contains('?') { '&' } else { '?' };
+            let target = format!("{}{}{}", path, sep, query.as_str());
+            self.build_request(method, &target)
+        }
+    }
+
     pub fn read_response(self) -> ReadResponse<T> {
         ReadResponse::new(self)
     }
+    /// Returns the raw stream of the connection.
+    ///
+    /// Note that the bytes buffered in the connection are discarded.
+    /// Use `into_upgraded` to keep them.
+    pub fn into_raw_stream(self) -> T {
+        self.inner.stream
+    }
+
+    /// Converts into the raw stream which also returns the bytes buffered in the connection.
+    pub fn into_upgraded(self) -> Upgraded<T> {
+        let (stream, buffered) = self.inner.into_parts();
+        Upgraded::new(stream, buffered)
+    }
 }
 impl<T> AsMut<connection::Connection<T>> for Connection<T> {
     fn as_mut(&mut self) -> &mut connection::Connection<T> {
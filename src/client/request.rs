@@ -2,7 +2,9 @@ use std::io::{self, Write};
 use futures::{Async, Future, Poll};
 
 use {Error, Method, Status};
-use header::{Header, HeadersMut};
+use header::{ContentLength, ContentType, Header, HeadersMut};
+use cookie::CookieJar;
+use query;
 use connection::TransportStream;
 use super::Connection;
 
@@ -11,6 +13,7 @@ where
     T: TransportStream,
 {
     connection.inner.buffer.enter_write_phase();
+    connection.method = method;
     let _ = write!(
         connection.inner.buffer,
         "{} {} {}\r\n",
@@ -33,6 +36,26 @@ impl<T: TransportStream> RequestBuilder<T> {
         self.headers_mut().add_header(header);
         self
     }
+
+    /// Adds the `Content-Type` and `Content-Length` headers for the form body `form`.
+    ///
+    /// The body itself should be written to the `Request` returned by `finish` method.
+    pub fn add_form_headers(&mut self, form: &query::Encoder) -> &mut Self {
+        self.add_header(&ContentType(query::FORM_URLENCODED));
+        self.add_header(&ContentLength(form.len() as u64));
+        self
+    }
+
+    /// Adds the `Cookie` header which contains the cookies in `jar` matching the request.
+    ///
+    /// `host` and `path` are those of the request and
+    /// `secure` indicates whether the request will be sent over a secure channel.
+    pub fn add_cookies(&mut self, jar: &CookieJar, host: &str, path: &str, secure: bool) -> &mut Self {
+        if let Some(value) = jar.cookie_header(host, path, secure) {
+            self.add_raw_header("Cookie", value.as_bytes());
+        }
+        self
+    }
     pub fn finish(mut self) -> Request<T> {
         let _ = write!(self.0.inner.buffer, "\r\n");
         Request(Some(self.0))
@@ -1,10 +1,14 @@
 use std::io::{self, BufRead, Read};
+use std::str;
 use httparse;
 use futures::{Async, Future, Poll};
+use trackable::error::ErrorKindExt;
 
-use {Error, Metadata, Method, Version};
+use {Error, Metadata, Method, Status, Version};
 use status::RawStatus;
-use header::Headers;
+use chunked::ChunkedDecoder;
+use compression::{self, ContentEncoding};
+use header::{ContentLength, Headers};
 use connection::TransportStream;
 use unsafe_types::UnsafeRawStatus;
 use super::Connection;
@@ -16,6 +20,16 @@ impl<T: TransportStream> ReadResponse<T> {
         connection.inner.buffer.enter_read_phase();
         ReadResponse(Some(connection))
     }
+
+    /// Returns `true` if any byte of the response has been received, otherwise `false`.
+    ///
+    /// If this future failed while this returns `false`, the connection was closed (or broken)
+    /// before the server started responding (e.g., an idle keep-alive connection closed by the server).
+    pub fn is_started(&self) -> bool {
+        self.0
+            .as_ref()
+            .is_some_and(|c| c.inner.has_unread_bytes())
+    }
 }
 impl<T: TransportStream> Future for ReadResponse<T> {
     type Item = Response<T>;
@@ -24,9 +38,14 @@ impl<T: TransportStream> Future for ReadResponse<T> {
         let mut connection = self.0.take().expect("Cannot poll ReadResponse twice");
         let (bytes, headers) = unsafe { connection.inner.buffer_and_headers() };
         let mut res = httparse::Response::new(headers);
-        if let httparse::Status::Complete(body_offset) =
-            track!(res.parse(bytes).map_err(Error::from))?
-        {
+        let parsed = match res.parse(bytes) {
+            Err(e) => {
+                self.0 = Some(connection);
+                return Err(track!(Error::from(e)));
+            }
+            Ok(parsed) => parsed,
+        };
+        if let httparse::Status::Complete(body_offset) = parsed {
             connection.inner.buffer.consume(body_offset);
             let version = if res.version.unwrap() == 0 {
                 Version::Http1_0
@@ -41,10 +60,17 @@ impl<T: TransportStream> Future for ReadResponse<T> {
                 headers: Headers::new(res.headers),
                 connection: connection,
             }))
+        } else if connection.inner.buffer.is_full() {
+            self.0 = Some(connection);
+            track_panic!(
+                Status::BadGateway,
+                "The response head exceeds the buffer: size={}",
+                bytes.len()
+            )
         } else {
-            let filled = track!(connection.inner.fill_buffer().map_err(Error::from))?;
+            let filled = connection.inner.fill_buffer();
             self.0 = Some(connection);
-            if filled {
+            if track!(filled.map_err(Error::from))? {
                 self.poll()
             } else {
                 Ok(Async::NotReady)
@@ -70,19 +96,413 @@ impl<T> Response<T> {
     pub fn headers(&self) -> &Headers {
         &self.headers
     }
+
+    /// Returns the method of the request to which this response corresponds.
+    pub fn request_method(&self) -> Method {
+        self.connection.method
+    }
     pub fn finish(self) -> Connection<T> {
         self.connection
     }
 }
+impl<T: TransportStream> Response<T> {
+    /// Makes a future which reads and discards the body of this response.
+    ///
+    /// The end of the body is determined by the request method, the status code,
+    /// and the `Transfer-Encoding` and `Content-Length` headers.
+    /// If the body continues until the connection is closed, the future will fail because
+    /// the connection is not reusable.
+    pub fn discard_body(self) -> DiscardBody<T> {
+        let body = self.into_framed().and_then(|framed| {
+            if let Framed::Eof(_) = framed {
+                track_panic!(
+                    Status::BadGateway,
+                    "Cannot determine the length of the response body"
+                );
+            }
+            Ok(Some(framed))
+        });
+        DiscardBody { body }
+    }
+
+    /// Makes a future which reads the body of this response into memory.
+    ///
+    /// Unlike `read_all_bytes`, only the transfer coding is decoded and
+    /// the content codings are kept (i.e., the body matches the `Content-Encoding` header).
+    /// If its size exceeds `max_size`, the future will fail with `Status::PayloadTooLarge`.
+    ///
+    /// The response is returned together with the body, so that
+    /// its headers can be inspected after reading.
+    pub fn read_body(self, max_size: u64) -> ReadBody<T> {
+        let body = self.into_framed()
+            .map(|framed| Some(DecodedBody(compression::Decoder::new(framed, None, u64::MAX))));
+        ReadBody(ReadAllBytes {
+            body,
+            max_size,
+            bytes: Vec::new(),
+        })
+    }
+
+    /// Makes a future which reads the whole decoded body of this response into memory.
+    ///
+    /// The body is decoded in the same way as `into_decoded_body`.
+    /// If its size exceeds `max_size`, the future will fail with `Status::PayloadTooLarge`.
+    pub fn read_all_bytes(self, max_size: u64) -> ReadAllBytes<T> {
+        ReadAllBytes {
+            body: self.into_decoded_body(u64::MAX).map(Some),
+            max_size,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Makes a future which reads the whole decoded body of this response as a UTF-8 string.
+    ///
+    /// If the body is not a valid UTF-8 string, the future will fail with `Status::BadGateway`.
+    pub fn read_all_string(self, max_size: u64) -> ReadAllString<T> {
+        ReadAllString(self.read_all_bytes(max_size))
+    }
+
+    /// Converts into a reader of the decoded body.
+    ///
+    /// The chunked transfer coding and the `gzip` and `deflate` content codings are decoded.
+    /// Responses to `HEAD` requests and `1xx`, `204` and `304` responses have no body.
+    /// If the length of the body is determined by neither `Transfer-Encoding` nor `Content-Length`,
+    /// the body continues until the connection is closed.
+    /// If the size of the decoded body exceeds `max_size`,
+    /// reading fails with `io::ErrorKind::InvalidData`.
+    ///
+    /// `Content-Encoding: identity` is regarded as no coding.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate miasht;
+    /// use std::io::{self, Cursor, Read, Write};
+    /// use futures::Future;
+    /// use miasht::TransportStream;
+    ///
+    /// struct Mock(Cursor<Vec<u8>>);
+    /// impl Read for Mock {
+    ///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    ///         self.0.read(buf)
+    ///     }
+    /// }
+    /// impl Write for Mock {
+    ///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    ///         Ok(buf.len())
+    ///     }
+    ///     fn flush(&mut self) -> io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    /// impl TransportStream for Mock {}
+    ///
+    /// # fn main() {
+    /// let bytes = b"HTTP/1.1 200 OK\r\nContent-Encoding: Identity\r\nContent-Length: 3\r\n\r\nabc";
+    /// let connection = miasht::client::Connection::new(Mock(Cursor::new(bytes.to_vec())), 1024, 8192, 32);
+    /// let (_, body) = connection
+    ///     .build_request(miasht::Method::Get, "/")
+    ///     .finish()
+    ///     .and_then(|connection| connection.read_response())
+    ///     .and_then(|response| response.read_all_bytes(1024))
+    ///     .wait()
+    ///     .unwrap();
+    /// assert_eq!(body, b"abc");
+    /// # }
+    /// ```
+    pub fn into_decoded_body(self, max_size: u64) -> ::Result<DecodedBody<T>> {
+        let identity = self.headers
+            .get("Content-Encoding")
+            .and_then(|v| str::from_utf8(v).ok())
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("identity"));
+        let coding = match self.headers.parse::<ContentEncoding>() {
+            _ if identity => None,
+            Ok(None) => None,
+            Ok(Some(ContentEncoding(coding))) => Some(coding),
+            Err(e) => track_panic!(Status::BadGateway, "{}", e),
+        };
+        let framed = track!(self.into_framed())?;
+        Ok(DecodedBody(compression::Decoder::new(framed, coding, max_size)))
+    }
+
+    fn into_framed(self) -> ::Result<Framed<T>> {
+        if !self.has_body() {
+            return Ok(Framed::Length(self.take(0)));
+        }
+        let chunked = self.headers
+            .get("Transfer-Encoding")
+            .and_then(|v| str::from_utf8(v).ok())
+            .and_then(|v| v.rsplit(',').next())
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("chunked"));
+        if chunked {
+            Ok(Framed::Chunked(ChunkedDecoder::new(self)))
+        } else if self.headers.get("Transfer-Encoding").is_some()
+            || self.headers.get("Content-Length").is_none()
+        {
+            Ok(Framed::Eof(self))
+        } else {
+            let length = track!(self.body_length())?;
+            Ok(Framed::Length(self.take(length)))
+        }
+    }
+
+    fn has_body(&self) -> bool {
+        self.connection.method.has_response_body(self.status.code())
+    }
+
+    fn body_length(&self) -> Result<u64, Error> {
+        match self.headers.parse::<ContentLength>() {
+            Ok(Some(n)) => Ok(n.0),
+            Ok(None) => Err(track!(Error::from(Status::BadGateway.cause(
+                "Cannot determine the length of the response body"
+            )))),
+            Err(e) => Err(track!(Error::from(e))),
+        }
+    }
+}
 impl<T: TransportStream> Read for Response<T> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if !self.connection.inner.buffer.is_empty() {
-            self.connection.inner.buffer.read(buf)
-        } else {
-            self.connection.inner.stream.read(buf)
+        self.connection.inner.read_body(buf)
+    }
+}
+/// The body can be read without invalidating the status line and the headers.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate miasht;
+/// use std::io::{self, Cursor, Read, Write};
+/// use futures::Future;
+/// use miasht::TransportStream;
+/// use miasht::chunked::{ChunkedDecoder, ChunkedEncoder};
+///
+/// struct Mock(Cursor<Vec<u8>>);
+/// impl Read for Mock {
+///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+///         self.0.read(buf)
+///     }
+/// }
+/// impl Write for Mock {
+///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+///         Ok(buf.len())
+///     }
+///     fn flush(&mut self) -> io::Result<()> {
+///         Ok(())
+///     }
+/// }
+/// impl TransportStream for Mock {}
+///
+/// # fn main() {
+/// let body = vec![b'a'; 5000];
+/// let mut encoder = ChunkedEncoder::new(Vec::new());
+/// encoder.write_all(&body).unwrap();
+/// let chunked = encoder.finish().unwrap();
+///
+/// let mut bytes = b"HTTP/1.1 200 OK\r\nETag: \"abc\"\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+/// bytes.extend_from_slice(&chunked);
+/// let connection = miasht::client::Connection::new(Mock(Cursor::new(bytes)), 1024, 8192, 32);
+/// let (response, decoded) = connection
+///     .build_request(miasht::Method::Get, "/")
+///     .finish()
+///     .and_then(|connection| connection.read_response())
+///     .and_then(|response| response.read_body(1024 * 1024))
+///     .wait()
+///     .unwrap();
+/// assert_eq!(decoded, body);
+/// assert_eq!(response.status().code(), 200);
+/// assert_eq!(response.status().reason(), "OK");
+/// assert_eq!(response.headers().get("ETag"), Some(&b"\"abc\""[..]));
+/// # }
+/// ```
+impl<T: TransportStream> BufRead for Response<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.connection.inner.fill_body_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        self.connection.inner.consume_body(amt)
+    }
+}
+/// A future which discards the body of a response.
+///
+/// This is created by calling `Response::discard_body` method.
+#[derive(Debug)]
+pub struct DiscardBody<T> {
+    body: Result<Option<Framed<T>>, Error>,
+}
+impl<T: TransportStream> Future for DiscardBody<T> {
+    type Item = Connection<T>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut buf = [0; 1024];
+        {
+            let body = match self.body {
+                Ok(ref mut body) => body.as_mut().expect("Cannot poll DiscardBody twice"),
+                Err(ref e) => return Err(track!(e.clone())),
+            };
+            loop {
+                match body.read(&mut buf) {
+                    Err(e) => {
+                        if e.kind() == io::ErrorKind::WouldBlock {
+                            return Ok(Async::NotReady);
+                        }
+                        return Err(track!(Error::from(e)));
+                    }
+                    Ok(0) => {
+                        track_assert!(
+                            !body.is_truncated(),
+                            Status::BadGateway,
+                            "Unexpected EOF in a response body"
+                        );
+                        break;
+                    }
+                    Ok(_) => {}
+                }
+            }
         }
+        let body = self.body.as_mut().ok().and_then(|b| b.take()).expect("Never fails");
+        Ok(Async::Ready(body.into_response().finish()))
     }
 }
+
+/// A reader of a decoded response body.
+///
+/// This is created by calling `Response::into_decoded_body` method.
+#[derive(Debug)]
+pub struct DecodedBody<T: TransportStream>(compression::Decoder<Framed<T>>);
+impl<T: TransportStream> DecodedBody<T> {
+    /// Returns the response.
+    ///
+    /// If the body has been read to the end, the connection of the response can be reused.
+    pub fn into_response(self) -> Response<T> {
+        self.0.into_inner().into_response()
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.0.get_ref().is_truncated()
+    }
+}
+impl<T: TransportStream> Read for DecodedBody<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[derive(Debug)]
+enum Framed<T> {
+    Length(io::Take<Response<T>>),
+    Chunked(ChunkedDecoder<Response<T>>),
+    Eof(Response<T>),
+}
+impl<T: TransportStream> Framed<T> {
+    fn into_response(self) -> Response<T> {
+        match self {
+            Framed::Length(r) => r.into_inner(),
+            Framed::Chunked(r) => r.into_inner(),
+            Framed::Eof(r) => r,
+        }
+    }
+
+    fn is_truncated(&self) -> bool {
+        match *self {
+            Framed::Length(ref r) => r.limit() > 0,
+            Framed::Chunked(ref r) => !r.is_finished(),
+            Framed::Eof(_) => false,
+        }
+    }
+}
+impl<T: TransportStream> Read for Framed<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Framed::Length(ref mut r) => r.read(buf),
+            Framed::Chunked(ref mut r) => r.read(buf),
+            Framed::Eof(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+/// A future which reads the body of a response.
+///
+/// This is created by calling `Response::read_body` method.
+#[derive(Debug)]
+pub struct ReadBody<T: TransportStream>(ReadAllBytes<T>);
+impl<T: TransportStream> Future for ReadBody<T> {
+    type Item = (Response<T>, Vec<u8>);
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        track!(self.0.poll())
+    }
+}
+
+/// A future which reads the whole decoded body of a response.
+///
+/// This is created by calling `Response::read_all_bytes` method.
+#[derive(Debug)]
+pub struct ReadAllBytes<T: TransportStream> {
+    body: Result<Option<DecodedBody<T>>, Error>,
+    max_size: u64,
+    bytes: Vec<u8>,
+}
+impl<T: TransportStream> Future for ReadAllBytes<T> {
+    type Item = (Response<T>, Vec<u8>);
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut buf = [0; 1024];
+        {
+            let body = match self.body {
+                Ok(ref mut body) => body.as_mut().expect("Cannot poll ReadAllBytes twice"),
+                Err(ref e) => return Err(track!(e.clone())),
+            };
+            loop {
+                match body.read(&mut buf) {
+                    Err(e) => {
+                        if e.kind() == io::ErrorKind::WouldBlock {
+                            return Ok(Async::NotReady);
+                        }
+                        return Err(track!(Error::from(e)));
+                    }
+                    Ok(0) => {
+                        track_assert!(
+                            !body.is_truncated(),
+                            Status::BadGateway,
+                            "Unexpected EOF in a response body"
+                        );
+                        break;
+                    }
+                    Ok(size) => {
+                        track_assert!(
+                            (self.bytes.len() + size) as u64 <= self.max_size,
+                            Status::PayloadTooLarge,
+                            "Too large response body: limit={}",
+                            self.max_size
+                        );
+                        self.bytes.extend_from_slice(&buf[..size]);
+                    }
+                }
+            }
+        }
+        let body = self.body.as_mut().ok().and_then(|b| b.take()).expect("Never fails");
+        let bytes = ::std::mem::take(&mut self.bytes);
+        Ok(Async::Ready((body.into_response(), bytes)))
+    }
+}
+
+/// A future which reads the whole decoded body of a response as a UTF-8 string.
+///
+/// This is created by calling `Response::read_all_string` method.
+#[derive(Debug)]
+pub struct ReadAllString<T: TransportStream>(ReadAllBytes<T>);
+impl<T: TransportStream> Future for ReadAllString<T> {
+    type Item = (Response<T>, String);
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (response, bytes) = try_ready!(track!(self.0.poll()));
+        let string = track!(String::from_utf8(bytes).map_err(|e| Error::from(Status::BadGateway.cause(e))))?;
+        Ok(Async::Ready((response, string)))
+    }
+}
+
 impl<T> Metadata for Response<T> {
     fn version(&self) -> Version {
         self.version
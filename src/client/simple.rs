@@ -0,0 +1,369 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+use futures::{Async, Future, IntoFuture, Poll};
+use trackable::error::ErrorKindExt;
+
+use {Error, Method, Status, TransportStream};
+use chunked::ChunkedEncoder;
+use defaults;
+use header::{ContentLength, Header};
+use pool::Key;
+use uri::Uri;
+use util::write_all;
+use super::{Connection, ReadResponse, Request, Response};
+
+/// The default value of the `User-Agent` header.
+pub const DEFAULT_USER_AGENT: &str = concat!("miasht/", env!("CARGO_PKG_VERSION"));
+
+/// A high-level HTTP client which sends requests to URLs.
+///
+/// The client opens a new connection for each request with `connect`,
+/// which is called with the origin of the URL (the scheme, host and port) and
+/// should return a future of the transport stream (e.g., a TCP stream, or a TLS stream for `https`).
+///
+/// The `Host`, `User-Agent` and `Content-Length` (or `Transfer-Encoding`) headers are added automatically.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate futures;
+/// # extern crate miasht;
+/// use futures::Future;
+/// use miasht::{Error, TransportStream};
+/// use miasht::client::Client;
+/// use miasht::pool::Key;
+///
+/// fn fetch<T, F>(connect: F)
+/// where
+///     T: TransportStream,
+///     F: FnMut(&Key) -> Result<T, Error>,
+/// {
+///     let mut client = Client::new(connect);
+///     let (response, body) = client
+///         .get("http://example.com/index.html")
+///         .add_raw_header("Accept", b"text/html")
+///         .and_then(|response| response.read_all_string(1024 * 1024))
+///         .wait()
+///         .unwrap();
+///     println!("{}\n{}", response.status().code(), body);
+///
+///     let response = client.post("http://example.com/items", b"{}".to_vec())
+///         .add_raw_header("Content-Type", b"application/json")
+///         .wait()
+///         .unwrap();
+///     println!("{}", response.status().code());
+/// }
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct Client<C> {
+    connect: C,
+    user_agent: Option<String>,
+    min_buffer_size: usize,
+    max_buffer_size: usize,
+    max_header_count: usize,
+}
+impl<C, F, T> Client<C>
+where
+    C: FnMut(&Key) -> F,
+    F: IntoFuture<Item = T, Error = Error>,
+    T: TransportStream,
+{
+    /// Makes a new `Client` instance.
+    pub fn new(connect: C) -> Self {
+        Client {
+            connect,
+            user_agent: Some(DEFAULT_USER_AGENT.to_owned()),
+            min_buffer_size: defaults::MIN_BUFFER_SIZE,
+            max_buffer_size: defaults::MAX_BUFFER_SIZE,
+            max_header_count: defaults::MAX_HEADER_COUNT,
+        }
+    }
+
+    /// Sets the value of the `User-Agent` header.
+    ///
+    /// If `None`, the header is not added.
+    pub fn set_user_agent(&mut self, user_agent: Option<&str>) {
+        self.user_agent = user_agent.map(|s| s.to_owned());
+    }
+
+    /// Sets the minimum and maximum sizes of the buffer of each connection.
+    pub fn set_buffer_size(&mut self, min: usize, max: usize) {
+        self.min_buffer_size = min;
+        self.max_buffer_size = max;
+    }
+
+    /// Sets the maximum number of headers in a response.
+    pub fn set_max_header_count(&mut self, max: usize) {
+        self.max_header_count = max;
+    }
+
+    /// Starts a request of `method` to `url`.
+    ///
+    /// If `url` is not an absolute `http` or `https` URL,
+    /// the resulting future will fail with `Status::BadRequest`.
+    pub fn request(&mut self, method: Method, url: &str) -> Call<T, F> {
+        let uri = url.parse::<Uri>().ok().filter(|u| u.is_absolute());
+        let origin = uri.as_ref().and_then(|u| u.origin());
+        let state = if let Some(ref origin) = origin {
+            State::Connect((self.connect)(origin).into_future())
+        } else {
+            let e = track!(Error::from(Status::BadRequest.cause(format!("Not an absolute URL: {:?}", url))));
+            State::Failed(e)
+        };
+        let mut headers = Vec::new();
+        if let Some(ref user_agent) = self.user_agent {
+            headers.push(("User-Agent".to_owned(), user_agent.clone().into_bytes()));
+        }
+        Call {
+            method,
+            target: uri.as_ref().map_or_else(String::new, |u| u.request_target()),
+            host: uri.and_then(|u| u.host_header()).unwrap_or_default(),
+            headers,
+            body: Body::Empty,
+            buffer_sizes: (self.min_buffer_size, self.max_buffer_size),
+            max_header_count: self.max_header_count,
+            state,
+        }
+    }
+
+    /// Starts a `GET` request to `url`.
+    pub fn get(&mut self, url: &str) -> Call<T, F> {
+        self.request(Method::Get, url)
+    }
+
+    /// Starts a `HEAD` request to `url`.
+    pub fn head(&mut self, url: &str) -> Call<T, F> {
+        self.request(Method::Head, url)
+    }
+
+    /// Starts a `POST` request to `url` with `body`.
+    pub fn post(&mut self, url: &str, body: Vec<u8>) -> Call<T, F> {
+        self.request(Method::Post, url).body(body)
+    }
+
+    /// Starts a `PUT` request to `url` with `body`.
+    pub fn put(&mut self, url: &str, body: Vec<u8>) -> Call<T, F> {
+        self.request(Method::Put, url).body(body)
+    }
+
+    /// Starts a `DELETE` request to `url`.
+    pub fn delete(&mut self, url: &str) -> Call<T, F> {
+        self.request(Method::Delete, url)
+    }
+}
+
+/// A future which sends a request started by `Client` and yields the response.
+///
+/// The response body can be read by `Response::read_all_bytes` or `Response::read_all_string`.
+#[derive(Debug)]
+pub struct Call<T, F: IntoFuture> {
+    method: Method,
+    target: String,
+    host: String,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Body,
+    buffer_sizes: (usize, usize),
+    max_header_count: usize,
+    state: State<T, F::Future>,
+}
+impl<T, F> Call<T, F>
+where
+    F: IntoFuture<Item = T, Error = Error>,
+    T: TransportStream,
+{
+    /// Adds a header.
+    ///
+    /// The automatically added headers are replaced by the headers which have the same names,
+    /// except for `Content-Length` and `Transfer-Encoding` which are always determined by the body.
+    pub fn add_raw_header(mut self, name: &str, value: &[u8]) -> Self {
+        if name.eq_ignore_ascii_case("User-Agent") {
+            self.headers.retain(|h| !h.0.eq_ignore_ascii_case("User-Agent"));
+        }
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Adds a header.
+    pub fn add_header<'a, H: Header<'a>>(self, header: &H) -> Self {
+        let mut value = Vec::new();
+        header.write_value(&mut value).expect("Never fails");
+        self.add_raw_header(H::name(), &value)
+    }
+
+    /// Sets the body of the request.
+    ///
+    /// The `Content-Length` header is added.
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = Body::Bytes(body, 0);
+        self
+    }
+
+    /// Sets the streaming body of the request.
+    ///
+    /// The body is sent with the chunked transfer coding until `reader` reaches EOF.
+    /// If `reader` returns `io::ErrorKind::WouldBlock`, the future returns `Async::NotReady`
+    /// (in that case, `reader` is responsible for notifying the current task).
+    pub fn stream_body<R>(mut self, reader: R) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        self.body = Body::Stream {
+            reader: Box::new(reader),
+            chunk: Vec::new(),
+            offset: 0,
+            eof: false,
+        };
+        self
+    }
+
+    fn build_request(&mut self, stream: T) -> Request<T> {
+        let (min, max) = self.buffer_sizes;
+        let connection = Connection::new(stream, min, max, self.max_header_count);
+        let mut request = connection.build_request(self.method, &self.target);
+        if !self.headers.iter().any(|h| h.0.eq_ignore_ascii_case("Host")) {
+            request.add_raw_header("Host", self.host.as_bytes());
+        }
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("Transfer-Encoding") {
+                request.add_raw_header(name, value);
+            }
+        }
+        match self.body {
+            Body::Empty => {}
+            Body::Bytes(ref body, _) => {
+                request.add_header(&ContentLength(body.len() as u64));
+            }
+            Body::Stream { .. } => {
+                request.add_raw_header("Transfer-Encoding", b"chunked");
+            }
+        }
+        request.finish()
+    }
+}
+impl<T, F> Future for Call<T, F>
+where
+    F: IntoFuture<Item = T, Error = Error>,
+    T: TransportStream,
+{
+    type Item = Response<T>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match mem::replace(&mut self.state, State::Done) {
+                State::Failed(e) => return Err(track!(e)),
+                State::Connect(mut f) => match track!(f.poll())? {
+                    Async::NotReady => {
+                        self.state = State::Connect(f);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready(stream) => {
+                        let request = self.build_request(stream);
+                        if let Body::Stream { .. } = self.body {
+                            State::SendChunked(ChunkedEncoder::new(request))
+                        } else {
+                            State::Send(request)
+                        }
+                    }
+                },
+                State::SendChunked(mut encoder) => {
+                    match self.body.write_to(&mut encoder).and_then(|()| encoder.try_finish()) {
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            self.state = State::SendChunked(encoder);
+                            return Ok(Async::NotReady);
+                        }
+                        Err(e) => return Err(track!(Error::from(e))),
+                        Ok(()) => {
+                            self.body = Body::Empty;
+                            State::Send(encoder.into_inner())
+                        }
+                    }
+                }
+                State::Send(mut request) => {
+                    let written = match self.body.write_to(&mut request) {
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => false,
+                        Err(e) => return Err(track!(Error::from(e))),
+                        Ok(()) => true,
+                    };
+                    let polled = if written {
+                        track!(request.poll())?
+                    } else {
+                        Async::NotReady
+                    };
+                    match polled {
+                        Async::NotReady => {
+                            self.state = State::Send(request);
+                            return Ok(Async::NotReady);
+                        }
+                        Async::Ready(connection) => State::Read(connection.read_response()),
+                    }
+                }
+                State::Read(mut f) => match track!(f.poll())? {
+                    Async::NotReady => {
+                        self.state = State::Read(f);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready(response) => return Ok(Async::Ready(response)),
+                },
+                State::Done => panic!("Cannot poll Call twice"),
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[derive(Debug)]
+enum State<T, F> {
+    Failed(Error),
+    Connect(F),
+    Send(Request<T>),
+    SendChunked(ChunkedEncoder<Request<T>>),
+    Read(ReadResponse<T>),
+    Done,
+}
+
+enum Body {
+    Empty,
+    Bytes(Vec<u8>, usize),
+    Stream {
+        reader: Box<dyn Read + Send>,
+        chunk: Vec<u8>,
+        offset: usize,
+        eof: bool,
+    },
+}
+impl Body {
+    fn write_to<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        match *self {
+            Body::Empty => Ok(()),
+            Body::Bytes(ref body, ref mut offset) => write_all(writer, body, offset),
+            Body::Stream {
+                ref mut reader,
+                ref mut chunk,
+                ref mut offset,
+                ref mut eof,
+            } => loop {
+                write_all(writer, chunk, offset)?;
+                if *eof {
+                    return Ok(());
+                }
+                let mut buf = [0; 8192];
+                let size = reader.read(&mut buf)?;
+                chunk.clear();
+                chunk.extend_from_slice(&buf[..size]);
+                *offset = 0;
+                *eof = size == 0;
+            },
+        }
+    }
+}
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Body::Empty => write!(f, "Empty"),
+            Body::Bytes(ref body, offset) => write!(f, "Bytes({} bytes, offset={})", body.len(), offset),
+            Body::Stream { eof, .. } => write!(f, "Stream {{ eof: {} }}", eof),
+        }
+    }
+}
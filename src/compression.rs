@@ -0,0 +1,376 @@
+//! `gzip` and `deflate` content codings ([RFC 7231 §3.1.2](https://tools.ietf.org/html/rfc7231#section-3.1.2)).
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::{Read, Write};
+//! use miasht::compression::{Coding, Decoder, Encoder};
+//!
+//! let mut encoder = Encoder::new(Vec::new(), Coding::Gzip);
+//! encoder.write_all(b"Hello, World!").unwrap();
+//! let compressed = encoder.finish().unwrap();
+//!
+//! let mut decoder = Decoder::new(&compressed[..], Some(Coding::Gzip), 1024);
+//! let mut decoded = String::new();
+//! decoder.read_to_string(&mut decoded).unwrap();
+//! assert_eq!(decoded, "Hello, World!");
+//!
+//! // Too large output is rejected
+//! let mut decoder = Decoder::new(&compressed[..], Some(Coding::Gzip), 5);
+//! assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+//! ```
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use flate2::Compression;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use futures::{Async, Future, Poll};
+
+use {Error, Status, TransportStream, Version};
+use chunked::ChunkedEncoder;
+use header::{Header, Headers, HeadersMut};
+use negotiation;
+use server;
+
+/// A content coding supported by this module.
+///
+/// `Deflate` is the "zlib" format (RFC 1950) as specified by RFC 7230 §4.2.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Coding {
+    Gzip,
+    Deflate,
+}
+impl Coding {
+    /// Returns the name of the coding.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+
+    /// Picks the coding for a response from the `Accept-Encoding` header of the request.
+    ///
+    /// `version` is the version of the request.
+    /// `None` means that the response should not be encoded.
+    /// It is always `None` for HTTP/1.0 requests, because an encoded response is sent
+    /// with the chunked transfer coding (see `add_headers`) which HTTP/1.0 clients do not support.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate httparse;
+    /// # extern crate miasht;
+    /// use miasht::Version;
+    /// use miasht::compression::Coding;
+    /// use miasht::header::Headers;
+    ///
+    /// # fn main() {
+    /// let fields = [httparse::Header { name: "Accept-Encoding", value: b"deflate, gzip;q=0.5" }];
+    /// let headers = Headers::new(&fields);
+    /// assert_eq!(Coding::negotiate(Version::Http1_1, &headers), Some(Coding::Deflate));
+    /// assert_eq!(Coding::negotiate(Version::Http1_0, &headers), None);
+    /// assert_eq!(Coding::negotiate(Version::Http1_1, &Headers::new(&[])), None);
+    /// # }
+    /// ```
+    pub fn negotiate(version: Version, request: &Headers) -> Option<Coding> {
+        if version == Version::Http1_0 {
+            return None;
+        }
+        // Responses are not encoded unless the client explicitly accepts a coding.
+        request.get(negotiation::AcceptEncoding::name())?;
+        // `identity` is not offered here, because it is implicitly acceptable with the highest quality.
+        let offered = ["gzip", "deflate"];
+        match negotiation::negotiate_encoding(request, &offered) {
+            Ok("gzip") => Some(Coding::Gzip),
+            Ok("deflate") => Some(Coding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Adds the headers for a response encoded with this coding.
+    ///
+    /// They are `Content-Encoding`, `Vary: Accept-Encoding` and `Transfer-Encoding: chunked`.
+    /// Thus `Content-Length` header must not be added.
+    /// Note that HTTP/1.0 clients do not support the chunked transfer coding
+    /// (`negotiate` never picks a coding for them).
+    pub fn add_headers(&self, headers: &mut HeadersMut) {
+        headers.add_header(&ContentEncoding(*self));
+        headers.add_raw_header("Vary", b"Accept-Encoding");
+        headers.add_raw_header("Transfer-Encoding", b"chunked");
+    }
+}
+impl fmt::Display for Coding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl FromStr for Coding {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(Coding::Gzip),
+            "deflate" => Ok(Coding::Deflate),
+            _ => track_panic!(Status::UnsupportedMediaType, "Unsupported content coding: {:?}", s),
+        }
+    }
+}
+
+/// `Content-Encoding` header (only a single coding is supported).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentEncoding(pub Coding);
+impl<'a> Header<'a> for ContentEncoding {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Content-Encoding"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.0)
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        track!(value.parse()).map(ContentEncoding)
+    }
+}
+
+#[derive(Debug)]
+enum EncoderInner<W: Write> {
+    Gzip(GzEncoder<W>),
+    Deflate(ZlibEncoder<W>),
+}
+
+/// A writer which compresses the written data.
+#[derive(Debug)]
+pub struct Encoder<W: Write>(EncoderInner<W>);
+impl<W: Write> Encoder<W> {
+    /// Makes a new `Encoder` instance with the default compression level.
+    pub fn new(inner: W, coding: Coding) -> Self {
+        Self::with_level(inner, coding, 6)
+    }
+
+    /// Makes a new `Encoder` instance with the compression level `level` (`0..=9`).
+    pub fn with_level(inner: W, coding: Coding, level: u32) -> Self {
+        let level = Compression::new(level);
+        Encoder(match coding {
+            Coding::Gzip => EncoderInner::Gzip(GzEncoder::new(inner, level)),
+            Coding::Deflate => EncoderInner::Deflate(ZlibEncoder::new(inner, level)),
+        })
+    }
+
+    /// Returns a mutable reference to the inner writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        match self.0 {
+            EncoderInner::Gzip(ref mut e) => e.get_mut(),
+            EncoderInner::Deflate(ref mut e) => e.get_mut(),
+        }
+    }
+
+    /// Writes the remaining compressed data to the inner writer.
+    ///
+    /// This can be called again if it failed with `WouldBlock`.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        match self.0 {
+            EncoderInner::Gzip(ref mut e) => e.try_finish(),
+            EncoderInner::Deflate(ref mut e) => e.try_finish(),
+        }
+    }
+
+    /// Writes the remaining compressed data and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self.0 {
+            EncoderInner::Gzip(e) => e.finish(),
+            EncoderInner::Deflate(e) => e.finish(),
+        }
+    }
+}
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0 {
+            EncoderInner::Gzip(ref mut e) => e.write(buf),
+            EncoderInner::Deflate(ref mut e) => e.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self.0 {
+            EncoderInner::Gzip(ref mut e) => e.flush(),
+            EncoderInner::Deflate(ref mut e) => e.flush(),
+        }
+    }
+}
+
+/// A writer which compresses a response body and sends it with the chunked transfer coding.
+///
+/// The headers of the response should have been added by `Coding::add_headers`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate futures;
+/// # extern crate miasht;
+/// use std::io::Write;
+/// use futures::Future;
+/// use miasht::{Status, TransportStream};
+/// use miasht::compression::{Coding, EncodingWriter};
+/// use miasht::header::ContentLength;
+/// use miasht::server::Request;
+///
+/// fn respond<T: TransportStream>(request: Request<T>, body: &[u8]) {
+///     let coding = Coding::negotiate(request.version(), request.headers());
+///     let mut response = request.finish().build_response(Status::Ok);
+///     if let Some(coding) = coding {
+///         coding.add_headers(&mut response.headers_mut());
+///         let mut writer = EncodingWriter::new(response.finish(), coding);
+///         writer.write_all(body).unwrap();
+///         let _connection = writer.finish().wait().unwrap();
+///     } else {
+///         response.add_header(&ContentLength(body.len() as u64));
+///         let mut response = response.finish();
+///         response.write_all(body).unwrap();
+///         let _connection = response.wait().unwrap();
+///     }
+/// }
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct EncodingWriter<T: TransportStream>(Encoder<ChunkedEncoder<server::Response<T>>>);
+impl<T: TransportStream> EncodingWriter<T> {
+    /// Makes a new `EncodingWriter` instance.
+    pub fn new(response: server::Response<T>, coding: Coding) -> Self {
+        EncodingWriter(Encoder::new(ChunkedEncoder::new(response), coding))
+    }
+
+    /// Makes a future which writes the rest of the body and
+    /// returns the connection when the response has been sent.
+    pub fn finish(self) -> FinishEncoding<T> {
+        FinishEncoding::new(self.0)
+    }
+}
+impl<T: TransportStream> Write for EncodingWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// A future which finishes an encoded response.
+///
+/// This is created by calling `EncodingWriter::finish` method.
+#[derive(Debug)]
+pub struct FinishEncoding<T: TransportStream>(FinishState<T>);
+impl<T: TransportStream> FinishEncoding<T> {
+    fn new(encoder: Encoder<ChunkedEncoder<server::Response<T>>>) -> Self {
+        FinishEncoding(FinishState::Encoding(Some(encoder)))
+    }
+}
+impl<T: TransportStream> Future for FinishEncoding<T> {
+    type Item = server::Connection<T>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.0 {
+                FinishState::Encoding(ref mut encoder) => {
+                    {
+                        let encoder = encoder.as_mut().expect("Never fails");
+                        let result = encoder.try_finish().and_then(|()| encoder.get_mut().try_finish());
+                        if let Err(e) = result {
+                            if e.kind() == io::ErrorKind::WouldBlock {
+                                return Ok(Async::NotReady);
+                            }
+                            return Err(track!(Error::from(e)));
+                        }
+                    }
+                    let encoder = encoder.take().expect("Never fails");
+                    let chunked = track!(encoder.finish().map_err(Error::from))?;
+                    FinishState::Flushing(chunked.into_inner())
+                }
+                FinishState::Flushing(ref mut response) => return track!(response.poll()),
+            };
+            self.0 = next;
+        }
+    }
+}
+
+#[derive(Debug)]
+enum FinishState<T: TransportStream> {
+    Encoding(Option<Encoder<ChunkedEncoder<server::Response<T>>>>),
+    Flushing(server::Response<T>),
+}
+
+#[derive(Debug)]
+enum DecoderInner<R: Read> {
+    Identity(R),
+    Gzip(GzDecoder<R>),
+    Deflate(ZlibDecoder<R>),
+}
+
+/// A reader which decompresses the data read from the inner reader.
+///
+/// If the size of the decompressed data exceeds the limit,
+/// reading fails with `io::ErrorKind::InvalidData` (this protects against decompression bombs).
+#[derive(Debug)]
+pub struct Decoder<R: Read> {
+    inner: DecoderInner<R>,
+    remaining: u64,
+}
+impl<R: Read> Decoder<R> {
+    /// Makes a new `Decoder` instance.
+    ///
+    /// If `coding` is `None`, the data is passed through (the size limit is still applied).
+    pub fn new(inner: R, coding: Option<Coding>, max_size: u64) -> Self {
+        let inner = match coding {
+            None => DecoderInner::Identity(inner),
+            Some(Coding::Gzip) => DecoderInner::Gzip(GzDecoder::new(inner)),
+            Some(Coding::Deflate) => DecoderInner::Deflate(ZlibDecoder::new(inner)),
+        };
+        Decoder {
+            inner,
+            remaining: max_size,
+        }
+    }
+
+    /// Returns a reference to the inner reader.
+    pub fn get_ref(&self) -> &R {
+        match self.inner {
+            DecoderInner::Identity(ref r) => r,
+            DecoderInner::Gzip(ref r) => r.get_ref(),
+            DecoderInner::Deflate(ref r) => r.get_ref(),
+        }
+    }
+
+    /// Returns the inner reader.
+    pub fn into_inner(self) -> R {
+        match self.inner {
+            DecoderInner::Identity(r) => r,
+            DecoderInner::Gzip(r) => r.into_inner(),
+            DecoderInner::Deflate(r) => r.into_inner(),
+        }
+    }
+}
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Reads one more byte than the limit to detect an oversized body.
+        let limit = if (buf.len() as u64) <= self.remaining {
+            buf.len()
+        } else {
+            self.remaining as usize + 1
+        };
+        let n = match self.inner {
+            DecoderInner::Identity(ref mut r) => r.read(&mut buf[..limit])?,
+            DecoderInner::Gzip(ref mut r) => r.read(&mut buf[..limit])?,
+            DecoderInner::Deflate(ref mut r) => r.read(&mut buf[..limit])?,
+        };
+        if n as u64 > self.remaining {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Too large decoded body",
+            ));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,320 @@
+//! Conditional requests ([RFC 7232](https://tools.ietf.org/html/rfc7232)).
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate httparse;
+//! extern crate miasht;
+//!
+//! use miasht::{Method, Status};
+//! use miasht::conditional::{self, EntityTag, Validators};
+//! use miasht::header::Headers;
+//!
+//! # fn main() {
+//! let validators = Validators::new().etag(EntityTag::strong("v1"));
+//!
+//! let headers = [httparse::Header { name: "If-None-Match", value: b"\"v0\", W/\"v1\"" }];
+//! let status = conditional::evaluate(Method::Get, &Headers::new(&headers), &validators);
+//! assert_eq!(status, Status::NotModified);
+//!
+//! // Weak entity-tags never match in the strong comparison
+//! let headers = [httparse::Header { name: "If-Match", value: b"W/\"v1\"" }];
+//! let status = conditional::evaluate(Method::Put, &Headers::new(&headers), &validators);
+//! assert_eq!(status, Status::PreconditionFailed);
+//! # }
+//! ```
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use {Error, Method, Status};
+use date::HttpDate;
+use header::{Header, Headers, HeadersMut};
+
+/// An entity-tag.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntityTag {
+    weak: bool,
+    tag: String,
+}
+impl EntityTag {
+    /// Makes a new strong entity-tag.
+    ///
+    /// # Panics
+    ///
+    /// If `tag` contains invalid characters (e.g., `'"'`), the calling thread will panic.
+    pub fn strong(tag: &str) -> Self {
+        assert!(is_etagc(tag), "Invalid entity-tag: {:?}", tag);
+        EntityTag {
+            weak: false,
+            tag: tag.to_owned(),
+        }
+    }
+
+    /// Makes a new weak entity-tag.
+    ///
+    /// # Panics
+    ///
+    /// If `tag` contains invalid characters (e.g., `'"'`), the calling thread will panic.
+    pub fn weak(tag: &str) -> Self {
+        assert!(is_etagc(tag), "Invalid entity-tag: {:?}", tag);
+        EntityTag {
+            weak: true,
+            tag: tag.to_owned(),
+        }
+    }
+
+    /// Returns `true` if this is a weak tag, otherwise `false`.
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Returns the opaque tag (without quotes).
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Strong comparison (RFC 7232 §2.3.2).
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Weak comparison (RFC 7232 §2.3.2).
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+}
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+impl FromStr for EntityTag {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (weak, rest) = if let Some(rest) = s.strip_prefix("W/") {
+            (true, rest)
+        } else {
+            (false, s)
+        };
+        track_assert!(
+            rest.len() >= 2 && rest.starts_with('"') && rest.ends_with('"'),
+            Status::BadRequest,
+            "Malformed entity-tag: {:?}",
+            s
+        );
+        let tag = &rest[1..rest.len() - 1];
+        track_assert!(is_etagc(tag), Status::BadRequest, "Malformed entity-tag: {:?}", s);
+        Ok(EntityTag {
+            weak,
+            tag: tag.to_owned(),
+        })
+    }
+}
+
+fn is_etagc(tag: &str) -> bool {
+    tag.bytes().all(|b| b == 0x21 || (b >= 0x23 && b != 0x7F))
+}
+
+/// A value of `If-Match` and `If-None-Match` headers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EntityTagList {
+    /// `*`
+    Any,
+    Tags(Vec<EntityTag>),
+}
+impl EntityTagList {
+    fn matches<F>(&self, current: Option<&EntityTag>, exists: bool, eq: F) -> bool
+    where
+        F: Fn(&EntityTag, &EntityTag) -> bool,
+    {
+        match *self {
+            EntityTagList::Any => exists,
+            EntityTagList::Tags(ref tags) => {
+                current.is_some_and(|current| tags.iter().any(|t| eq(t, current)))
+            }
+        }
+    }
+}
+impl fmt::Display for EntityTagList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EntityTagList::Any => write!(f, "*"),
+            EntityTagList::Tags(ref tags) => {
+                for (i, tag) in tags.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", tag)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+impl FromStr for EntityTagList {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "*" {
+            return Ok(EntityTagList::Any);
+        }
+        let mut tags = Vec::new();
+        for item in s.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+            tags.push(track!(item.parse())?);
+        }
+        Ok(EntityTagList::Tags(tags))
+    }
+}
+
+macro_rules! impl_header {
+    ($header:ident, $name:expr, $value:ty) => {
+        impl<'a> Header<'a> for $header {
+            type Error = Error;
+            fn name() -> &'static str {
+                $name
+            }
+            fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                write!(writer, "{}", self.0)
+            }
+            fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+                track!(value.trim().parse::<$value>()).map($header)
+            }
+        }
+    };
+}
+
+/// `ETag` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ETag(pub EntityTag);
+impl_header!(ETag, "ETag", EntityTag);
+
+/// `If-Match` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IfMatch(pub EntityTagList);
+impl_header!(IfMatch, "If-Match", EntityTagList);
+
+/// `If-None-Match` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IfNoneMatch(pub EntityTagList);
+impl_header!(IfNoneMatch, "If-None-Match", EntityTagList);
+
+/// `Last-Modified` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LastModified(pub HttpDate);
+impl_header!(LastModified, "Last-Modified", HttpDate);
+
+/// `If-Modified-Since` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IfModifiedSince(pub HttpDate);
+impl_header!(IfModifiedSince, "If-Modified-Since", HttpDate);
+
+/// `If-Unmodified-Since` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IfUnmodifiedSince(pub HttpDate);
+impl_header!(IfUnmodifiedSince, "If-Unmodified-Since", HttpDate);
+
+/// The validators of a resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    etag: Option<EntityTag>,
+    last_modified: Option<HttpDate>,
+    exists: bool,
+}
+impl Validators {
+    /// Makes a new `Validators` instance for an existing resource which has no validators.
+    pub fn new() -> Self {
+        Validators {
+            etag: None,
+            last_modified: None,
+            exists: true,
+        }
+    }
+
+    /// Makes a new `Validators` instance for a missing resource.
+    pub fn missing() -> Self {
+        Validators {
+            exists: false,
+            ..Validators::new()
+        }
+    }
+
+    /// Sets the entity-tag of the resource.
+    pub fn etag(mut self, etag: EntityTag) -> Self {
+        self.etag = Some(etag);
+        self
+    }
+
+    /// Sets the last modification time of the resource.
+    pub fn last_modified<T: Into<HttpDate>>(mut self, last_modified: T) -> Self {
+        self.last_modified = Some(last_modified.into());
+        self
+    }
+
+    /// Returns the entity-tag of the resource.
+    pub fn get_etag(&self) -> Option<&EntityTag> {
+        self.etag.as_ref()
+    }
+
+    /// Returns the last modification time of the resource.
+    pub fn get_last_modified(&self) -> Option<HttpDate> {
+        self.last_modified
+    }
+
+    /// Adds the `ETag` and `Last-Modified` headers.
+    pub fn add_headers(&self, headers: &mut HeadersMut) {
+        if let Some(ref etag) = self.etag {
+            headers.add_header(&ETag(etag.clone()));
+        }
+        if let Some(last_modified) = self.last_modified {
+            headers.add_header(&LastModified(last_modified));
+        }
+    }
+}
+impl Default for Validators {
+    fn default() -> Self {
+        Validators::new()
+    }
+}
+
+/// Evaluates the preconditions of a request in the order defined by RFC 7232 §6.
+///
+/// The result is one of `Status::Ok` (the request should be processed normally),
+/// `Status::NotModified` or `Status::PreconditionFailed`.
+/// Malformed precondition headers are ignored.
+pub fn evaluate(method: Method, headers: &Headers, validators: &Validators) -> Status {
+    let is_get_or_head = method == Method::Get || method == Method::Head;
+    let etag = validators.etag.as_ref();
+    if let Ok(Some(IfMatch(list))) = headers.parse::<IfMatch>() {
+        // Step 1
+        if !list.matches(etag, validators.exists, EntityTag::strong_eq) {
+            return Status::PreconditionFailed;
+        }
+    } else if let Ok(Some(IfUnmodifiedSince(date))) = headers.parse::<IfUnmodifiedSince>() {
+        // Step 2
+        if validators.last_modified.is_some_and(|t| t > date) {
+            return Status::PreconditionFailed;
+        }
+    }
+    if let Ok(Some(IfNoneMatch(list))) = headers.parse::<IfNoneMatch>() {
+        // Step 3
+        if list.matches(etag, validators.exists, EntityTag::weak_eq) {
+            return if is_get_or_head {
+                Status::NotModified
+            } else {
+                Status::PreconditionFailed
+            };
+        }
+    } else if is_get_or_head {
+        // Step 4
+        if let Ok(Some(IfModifiedSince(date))) = headers.parse::<IfModifiedSince>() {
+            if validators.last_modified.is_some_and(|t| t <= date) {
+                return Status::NotModified;
+            }
+        }
+    }
+    Status::Ok
+}
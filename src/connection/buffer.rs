@@ -72,7 +72,7 @@ impl Buffer {
 
             // Shift to front
             let len = self.bytes.len();
-            self.bytes.drain(..read_tail);
+            self.bytes.drain(..head);
             self.bytes.resize(len, 0);
 
             self.phase = Phase::Write {
@@ -82,6 +82,18 @@ impl Buffer {
             };
         }
     }
+    /// Moves the read position to the front of the buffer if there are no unread bytes,
+    /// so that the whole buffer can be used by the following reads.
+    ///
+    /// This must not be called while the parsed head of a message is in use.
+    pub fn rewind_if_empty(&mut self) {
+        if let Phase::Read { ref mut head, ref mut tail } = self.phase {
+            if *head == *tail {
+                *head = 0;
+                *tail = 0;
+            }
+        }
+    }
     pub fn fill_from<R: Read>(&mut self, reader: &mut R) -> io::Result<usize> {
         self.expand_if_needed();
         self.check_overflow()?;
@@ -91,9 +103,18 @@ impl Buffer {
         *tail += read_size;
         Ok(read_size)
     }
+    pub fn unread_bytes(&self) -> &[u8] {
+        match self.phase {
+            Phase::Read { .. } => self.as_slice(),
+            Phase::Write { read_tail, .. } => &self.bytes[..read_tail],
+        }
+    }
     pub fn as_slice(&self) -> &[u8] {
         &self.bytes[self.phase.head()..self.phase.tail()]
     }
+    pub fn is_full(&self) -> bool {
+        self.phase.tail() == self.max_len
+    }
     fn expand_if_needed(&mut self) {
         if self.phase.tail() == self.bytes.len() {
             let new_len = cmp::min(self.bytes.len() * 2, self.max_len);
@@ -1,3 +1,4 @@
+use std::cmp;
 use std::io::{self, BufRead, Read, Write};
 use httparse;
 
@@ -14,6 +15,12 @@ pub struct Connection<T> {
     pub stream: T,
     pub buffer: Buffer,
     headers: Vec<UnsafeHeader>,
+
+    // The buffer used for reading bodies.
+    //
+    // `buffer` cannot be refilled while a body is read, because the parsed head
+    // which is referred by `buffer_and_headers` would be overwritten.
+    body_buffer: Box<BodyBuffer>,
 }
 impl<T: TransportStream> Connection<T> {
     pub fn new(
@@ -27,6 +34,62 @@ impl<T: TransportStream> Connection<T> {
             stream: stream,
             buffer: buffer,
             headers: vec![httparse::EMPTY_HEADER; max_header_count],
+            body_buffer: Box::new(BodyBuffer::new(min_buffer_size)),
+        }
+    }
+    pub fn into_parts(self) -> (T, Vec<u8>) {
+        let mut unread = self.buffer.unread_bytes().to_vec();
+        unread.extend_from_slice(self.body_buffer.unread());
+        (self.stream, unread)
+    }
+
+    /// Returns `true` if there are buffered bytes which have not been read yet.
+    pub fn has_unread_bytes(&self) -> bool {
+        !self.buffer.is_empty() || !self.body_buffer.unread().is_empty()
+    }
+
+    /// Discards the buffered bytes which have not been read yet.
+    pub fn discard_unread_bytes(&mut self) {
+        let unread = self.buffer.as_slice().len();
+        self.buffer.consume(unread);
+        let unread = self.body_buffer.unread().len();
+        self.body_buffer.consume(unread);
+    }
+
+    /// Reads a part of the body of the current message.
+    pub fn read_body(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.buffer.is_empty() {
+            self.buffer.read(buf)
+        } else if !self.body_buffer.unread().is_empty() {
+            let size = cmp::min(buf.len(), self.body_buffer.unread().len());
+            buf[..size].copy_from_slice(&self.body_buffer.unread()[..size]);
+            self.body_buffer.consume(size);
+            Ok(size)
+        } else {
+            self.stream.read(buf)
+        }
+    }
+
+    /// Returns the buffered bytes of the body of the current message,
+    /// reading from the stream if there are none.
+    ///
+    /// An empty slice means that the stream reached EOF.
+    pub fn fill_body_buf(&mut self) -> io::Result<&[u8]> {
+        if !self.buffer.is_empty() {
+            return self.buffer.fill_buf();
+        }
+        if self.body_buffer.unread().is_empty() {
+            self.body_buffer.fill_from(&mut self.stream)?;
+        }
+        Ok(self.body_buffer.unread())
+    }
+
+    /// Consumes `amt` bytes of the slice returned by `fill_body_buf`.
+    pub fn consume_body(&mut self, amt: usize) {
+        if !self.buffer.is_empty() {
+            self.buffer.consume(amt);
+        } else {
+            self.body_buffer.consume(amt);
         }
     }
     pub fn flush_buffer(&mut self) -> io::Result<()> {
@@ -42,6 +105,14 @@ impl<T: TransportStream> Connection<T> {
         }
     }
     pub fn fill_buffer(&mut self) -> io::Result<bool> {
+        if !self.body_buffer.unread().is_empty() {
+            // The bytes after the previous body belong to the next message.
+            self.buffer.rewind_if_empty();
+            self.buffer.write_all(self.body_buffer.unread())?;
+            let unread = self.body_buffer.unread().len();
+            self.body_buffer.consume(unread);
+            return Ok(true);
+        }
         match self.buffer.fill_from(&mut self.stream) {
             Err(e) => {
                 if e.kind() == io::ErrorKind::WouldBlock {
@@ -66,3 +137,37 @@ impl<T: TransportStream> Connection<T> {
         )
     }
 }
+
+#[derive(Debug)]
+struct BodyBuffer {
+    bytes: Vec<u8>,
+    size: usize,
+    head: usize,
+    tail: usize,
+}
+impl BodyBuffer {
+    fn new(size: usize) -> Self {
+        // The bytes are allocated when they are needed first.
+        BodyBuffer {
+            bytes: Vec::new(),
+            size,
+            head: 0,
+            tail: 0,
+        }
+    }
+    fn unread(&self) -> &[u8] {
+        &self.bytes[self.head..self.tail]
+    }
+    fn consume(&mut self, amt: usize) {
+        self.head = cmp::min(self.head + amt, self.tail);
+    }
+    fn fill_from<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        if self.bytes.is_empty() {
+            self.bytes.resize(self.size, 0);
+        }
+        let size = reader.read(&mut self.bytes)?;
+        self.head = 0;
+        self.tail = size;
+        Ok(())
+    }
+}
@@ -0,0 +1,544 @@
+//! HTTP cookies ([RFC 6265](https://tools.ietf.org/html/rfc6265)).
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//! use miasht::cookie::{Cookie, CookieJar, SameSite, SetCookie};
+//! use miasht::header::Header;
+//!
+//! // Server side
+//! let cookie = Cookie::parse_value_str("sid=abc; lang=ja").unwrap();
+//! assert_eq!(cookie.get("lang"), Some("ja"));
+//! assert_eq!(Cookie::parse_value_str("sid=abc; ").unwrap().iter().count(), 1);
+//! assert!(Cookie::parse_value_str("sid=abc; ; lang=ja").is_err());
+//!
+//! let set_cookie = SetCookie::new("sid", "xyz").unwrap()
+//!     .path("/app").unwrap()
+//!     .max_age(Duration::from_secs(3600))
+//!     .secure(true)
+//!     .http_only(true)
+//!     .same_site(SameSite::Lax);
+//! let mut value = Vec::new();
+//! set_cookie.write_value(&mut value).unwrap();
+//! assert_eq!(value, b"sid=xyz; Max-Age=3600; Path=/app; Secure; HttpOnly; SameSite=Lax");
+//!
+//! // Client side
+//! let mut jar = CookieJar::new();
+//! jar.store("example.com", "/app/login", &SetCookie::parse_value_str("sid=xyz; Path=/app").unwrap());
+//! jar.store("example.com", "/", &SetCookie::parse_value_str("lang=ja").unwrap());
+//! assert_eq!(jar.cookie_header("example.com", "/app/a", false), Some("sid=xyz; lang=ja".to_owned()));
+//! assert_eq!(jar.cookie_header("example.com", "/", false), Some("lang=ja".to_owned()));
+//! assert_eq!(jar.cookie_header("example.org", "/", false), None);
+//! ```
+use std::fmt;
+use std::io::{self, Write};
+use std::cmp;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use {Error, Status};
+use date::HttpDate;
+use header::{Header, Headers};
+
+/// `Cookie` request header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cookie<'a>(&'a str);
+impl<'a> Cookie<'a> {
+    /// Returns an iterator over the name-value pairs of the cookies.
+    pub fn iter(&self) -> CookiePairs<'a> {
+        CookiePairs(self.0.split(';'))
+    }
+
+    /// Returns the value of the cookie named `name`.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.iter().find(|&(n, _)| n == name).map(|(_, v)| v)
+    }
+}
+impl<'a> Header<'a> for Cookie<'a> {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Cookie"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.0)
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        // Some user agents send a trailing `;` (e.g., `a=b; `)
+        let value = value.trim_end_matches(&[';', ' ', '\t'][..]);
+        for pair in value.split(';') {
+            let mut kv = pair.trim().splitn(2, '=');
+            let name = kv.next().unwrap_or("");
+            let value = track_assert_some!(
+                kv.next(),
+                Status::BadRequest,
+                "Malformed cookie pair: {:?}",
+                pair
+            );
+            track!(validate_name(name))?;
+            track!(validate_value(value))?;
+        }
+        Ok(Cookie(value.trim()))
+    }
+}
+
+/// An iterator over the name-value pairs of a `Cookie` header.
+#[derive(Debug, Clone)]
+pub struct CookiePairs<'a>(::std::str::Split<'a, char>);
+impl<'a> Iterator for CookiePairs<'a> {
+    type Item = (&'a str, &'a str);
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut kv = self.0.next()?.trim().splitn(2, '=');
+        let name = kv.next().unwrap_or("");
+        let value = kv.next().unwrap_or("");
+        Some((name, unquote(value)))
+    }
+}
+
+/// The value of `SameSite` attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SameSite::Strict => write!(f, "Strict"),
+            SameSite::Lax => write!(f, "Lax"),
+            SameSite::None => write!(f, "None"),
+        }
+    }
+}
+
+/// `Set-Cookie` response header.
+///
+/// When building a header, the name, the value and the attributes are
+/// validated according to the grammar defined in RFC 6265 §4.1.1.
+/// When parsing a header, the lenient algorithm described in RFC 6265 §5.2 is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetCookie<'a> {
+    name: &'a str,
+    value: &'a str,
+    expires: Option<HttpDate>,
+    max_age: Option<u64>,
+    domain: Option<&'a str>,
+    path: Option<&'a str>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+impl<'a> SetCookie<'a> {
+    /// Makes a new `SetCookie` instance.
+    pub fn new(name: &'a str, value: &'a str) -> ::Result<Self> {
+        track!(validate_name(name))?;
+        track!(validate_value(value))?;
+        Ok(SetCookie {
+            name,
+            value,
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        })
+    }
+
+    /// Sets the `Expires` attribute.
+    pub fn expires<T: Into<HttpDate>>(mut self, expires: T) -> Self {
+        self.expires = Some(expires.into());
+        self
+    }
+
+    /// Sets the `Max-Age` attribute.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age.as_secs());
+        self
+    }
+
+    /// Sets the `Domain` attribute.
+    pub fn domain(mut self, domain: &'a str) -> ::Result<Self> {
+        let valid = !domain.is_empty()
+            && domain.bytes().all(|b| {
+                b.is_ascii_alphanumeric() || b == b'-' || b == b'.'
+            });
+        track_assert!(valid, Status::InternalServerError, "Invalid cookie domain: {:?}", domain);
+        self.domain = Some(domain);
+        Ok(self)
+    }
+
+    /// Sets the `Path` attribute.
+    pub fn path(mut self, path: &'a str) -> ::Result<Self> {
+        let valid = path.bytes().all(|b| !b.is_ascii_control() && b != b';');
+        track_assert!(valid, Status::InternalServerError, "Invalid cookie path: {:?}", path);
+        self.path = Some(path);
+        Ok(self)
+    }
+
+    /// Sets the `Secure` attribute.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets the `HttpOnly` attribute.
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Sets the `SameSite` attribute.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Returns the name of the cookie.
+    pub fn cookie_name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Returns the value of the cookie.
+    pub fn cookie_value(&self) -> &'a str {
+        self.value
+    }
+
+    /// Returns the `Expires` attribute.
+    pub fn get_expires(&self) -> Option<HttpDate> {
+        self.expires
+    }
+
+    /// Returns the `Max-Age` attribute.
+    pub fn get_max_age(&self) -> Option<Duration> {
+        self.max_age.map(Duration::from_secs)
+    }
+
+    /// Returns the `Domain` attribute.
+    pub fn get_domain(&self) -> Option<&'a str> {
+        self.domain
+    }
+
+    /// Returns the `Path` attribute.
+    pub fn get_path(&self) -> Option<&'a str> {
+        self.path
+    }
+
+    /// Returns `true` if the `Secure` attribute is set, otherwise `false`.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Returns `true` if the `HttpOnly` attribute is set, otherwise `false`.
+    pub fn is_http_only(&self) -> bool {
+        self.http_only
+    }
+
+    /// Returns the `SameSite` attribute.
+    pub fn get_same_site(&self) -> Option<SameSite> {
+        self.same_site
+    }
+}
+impl<'a> Header<'a> for SetCookie<'a> {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Set-Cookie"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}={}", self.name, self.value)?;
+        if let Some(expires) = self.expires {
+            write!(writer, "; Expires={}", expires)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(writer, "; Max-Age={}", max_age)?;
+        }
+        if let Some(domain) = self.domain {
+            write!(writer, "; Domain={}", domain)?;
+        }
+        if let Some(path) = self.path {
+            write!(writer, "; Path={}", path)?;
+        }
+        if self.secure {
+            write!(writer, "; Secure")?;
+        }
+        if self.http_only {
+            write!(writer, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(writer, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        let mut attrs = value.split(';');
+        let pair = attrs.next().unwrap_or("");
+        let eq = track_assert_some!(
+            pair.find('='),
+            Status::BadRequest,
+            "Malformed Set-Cookie: {:?}",
+            value
+        );
+        let name = pair[..eq].trim();
+        track_assert!(!name.is_empty(), Status::BadRequest, "Empty cookie name");
+        let mut cookie = SetCookie {
+            name,
+            value: unquote(pair[eq + 1..].trim()),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        };
+        for attr in attrs {
+            let mut kv = attr.splitn(2, '=');
+            let k = kv.next().unwrap_or("").trim();
+            let v = kv.next().unwrap_or("").trim();
+            if k.eq_ignore_ascii_case("Expires") {
+                if let Ok(expires) = v.parse() {
+                    cookie.expires = Some(expires);
+                }
+            } else if k.eq_ignore_ascii_case("Max-Age") {
+                if let Ok(max_age) = v.parse::<i64>() {
+                    cookie.max_age = Some(if max_age < 0 { 0 } else { max_age as u64 });
+                }
+            } else if k.eq_ignore_ascii_case("Domain") {
+                let domain = v.trim_start_matches('.');
+                if !domain.is_empty() {
+                    cookie.domain = Some(domain);
+                }
+            } else if k.eq_ignore_ascii_case("Path") {
+                cookie.path = if v.starts_with('/') { Some(v) } else { None };
+            } else if k.eq_ignore_ascii_case("Secure") {
+                cookie.secure = true;
+            } else if k.eq_ignore_ascii_case("HttpOnly") {
+                cookie.http_only = true;
+            } else if k.eq_ignore_ascii_case("SameSite") {
+                cookie.same_site = if v.eq_ignore_ascii_case("Strict") {
+                    Some(SameSite::Strict)
+                } else if v.eq_ignore_ascii_case("Lax") {
+                    Some(SameSite::Lax)
+                } else if v.eq_ignore_ascii_case("None") {
+                    Some(SameSite::None)
+                } else {
+                    None
+                };
+            }
+        }
+        Ok(cookie)
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+fn validate_name(name: &str) -> ::Result<()> {
+    let valid = !name.is_empty() && name.bytes().all(|b| match b {
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
+        | b'`' | b'|' | b'~' => true,
+        _ => b.is_ascii_alphanumeric(),
+    });
+    track_assert!(valid, Status::BadRequest, "Invalid cookie name: {:?}", name);
+    Ok(())
+}
+
+fn validate_value(value: &str) -> ::Result<()> {
+    let valid = unquote(value).bytes().all(|b| {
+        matches!(b, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+    });
+    track_assert!(valid, Status::BadRequest, "Invalid cookie value: {:?}", value);
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct StoredCookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    expires: Option<SystemTime>,
+    secure: bool,
+}
+impl StoredCookie {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|t| t <= now)
+    }
+    fn matches(&self, host: &str, path: &str, secure: bool) -> bool {
+        let domain_ok = if self.host_only {
+            self.domain == host
+        } else {
+            domain_match(host, &self.domain)
+        };
+        domain_ok && path_match(path, &self.path) && (secure || !self.secure)
+    }
+}
+
+// 9999-12-31T23:59:59Z
+const LATEST_EXPIRY_SECS: u64 = 253_402_300_799;
+
+/// Client side cookie storage.
+///
+/// Third-party cookie restrictions and public suffix checks are out of the scope of this jar.
+#[derive(Debug, Default, Clone)]
+pub struct CookieJar {
+    cookies: Vec<StoredCookie>,
+}
+impl CookieJar {
+    /// Makes a new empty `CookieJar` instance.
+    pub fn new() -> Self {
+        CookieJar::default()
+    }
+
+    /// Returns the number of the stored cookies (including expired ones).
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    /// Returns `true` if the jar has no cookies, otherwise `false`.
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    /// Stores all the `Set-Cookie` headers in `headers`.
+    ///
+    /// `host` and `path` are those of the request which the response corresponds to.
+    /// Malformed headers are ignored.
+    pub fn store_headers(&mut self, host: &str, path: &str, headers: &Headers) {
+        for (name, value) in headers.iter() {
+            if !name.eq_ignore_ascii_case(SetCookie::name()) {
+                continue;
+            }
+            if let Ok(cookie) = SetCookie::parse_value_bytes(value) {
+                self.store(host, path, &cookie);
+            }
+        }
+    }
+
+    /// Stores the cookie set by the response of the request to `host` and `path`.
+    ///
+    /// If the cookie is rejected by the storage model of RFC 6265 §5.3, this method returns `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::header::Header;
+    /// use miasht::cookie::{CookieJar, SetCookie};
+    ///
+    /// let mut jar = CookieJar::new();
+    /// let cookie = SetCookie::parse_value_str("a=b; Max-Age=9223372036854775807").unwrap();
+    /// assert!(jar.store("example.com", "/", &cookie));
+    /// assert_eq!(jar.cookie_header("example.com", "/", false), Some("a=b".to_owned()));
+    /// ```
+    pub fn store(&mut self, host: &str, path: &str, cookie: &SetCookie) -> bool {
+        let host = host.to_ascii_lowercase();
+        let now = SystemTime::now();
+        let expires = if let Some(max_age) = cookie.max_age {
+            // Too large values are clamped to the latest representable date (RFC 6265 §5.2.2).
+            let expires = now.checked_add(Duration::from_secs(max_age))
+                .unwrap_or_else(|| UNIX_EPOCH + Duration::from_secs(LATEST_EXPIRY_SECS));
+            Some(cmp::min(expires, UNIX_EPOCH + Duration::from_secs(LATEST_EXPIRY_SECS)))
+        } else {
+            cookie.expires.map(|t| t.to_system_time())
+        };
+        let (domain, host_only) = if let Some(domain) = cookie.domain {
+            let domain = domain.to_ascii_lowercase();
+            if !domain_match(&host, &domain) {
+                return false;
+            }
+            (domain, false)
+        } else {
+            (host, true)
+        };
+        let path = cookie
+            .path
+            .map_or_else(|| default_path(path).to_owned(), |p| p.to_owned());
+        let stored = StoredCookie {
+            name: cookie.name.to_owned(),
+            value: cookie.value.to_owned(),
+            domain,
+            host_only,
+            path,
+            expires,
+            secure: cookie.secure,
+        };
+        self.cookies.retain(|c| {
+            !(c.name == stored.name && c.domain == stored.domain && c.path == stored.path)
+        });
+        if !stored.is_expired(now) {
+            self.cookies.push(stored);
+        }
+        true
+    }
+
+    /// Returns the value of the `Cookie` header for the request to `host` and `path`.
+    ///
+    /// `secure` indicates whether the request will be sent over a secure channel.
+    pub fn cookie_header(&self, host: &str, path: &str, secure: bool) -> Option<String> {
+        let host = host.to_ascii_lowercase();
+        let path = path.split(['?', '#']).next().unwrap_or("/");
+        let now = SystemTime::now();
+        let mut cookies = self.cookies
+            .iter()
+            .filter(|c| !c.is_expired(now) && c.matches(&host, path, secure))
+            .collect::<Vec<_>>();
+        if cookies.is_empty() {
+            return None;
+        }
+        // Longer paths first (the sort is stable, so older cookies come first among the same paths).
+        cookies.sort_by_key(|c| ::std::cmp::Reverse(c.path.len()));
+        let pairs = cookies
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>();
+        Some(pairs.join("; "))
+    }
+
+    /// Removes the expired cookies.
+    pub fn remove_expired(&mut self) {
+        let now = SystemTime::now();
+        self.cookies.retain(|c| !c.is_expired(now));
+    }
+
+    /// Removes all the cookies.
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+}
+
+// RFC 6265 §5.1.3
+fn domain_match(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    host.ends_with(domain)
+        && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+        && host.parse::<::std::net::IpAddr>().is_err()
+}
+
+// RFC 6265 §5.1.4
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/')
+                || request_path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+// RFC 6265 §5.1.4
+fn default_path(request_path: &str) -> &str {
+    let path = request_path.split(['?', '#']).next().unwrap_or("");
+    if !path.starts_with('/') {
+        return "/";
+    }
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
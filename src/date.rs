@@ -0,0 +1,244 @@
+//! HTTP-date ([RFC 7231 §7.1.1.1](https://tools.ietf.org/html/rfc7231#section-7.1.1.1)).
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use {Error, Status};
+use header::Header;
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const LONG_WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A timestamp represented in HTTP-date format.
+///
+/// The precision is one second and timestamps before the UNIX epoch are not supported.
+///
+/// When parsing, the obsolete RFC 850 and asctime formats are also accepted.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use miasht::date::HttpDate;
+///
+/// let date = HttpDate::from(UNIX_EPOCH + Duration::from_secs(784111777));
+/// assert_eq!(date.to_string(), "Sun, 06 Nov 1994 08:49:37 GMT");
+///
+/// assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT".parse::<HttpDate>().unwrap(), date);
+/// assert_eq!("Sunday, 06-Nov-94 08:49:37 GMT".parse::<HttpDate>().unwrap(), date);
+/// assert_eq!("Sun Nov  6 08:49:37 1994".parse::<HttpDate>().unwrap(), date);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HttpDate(u64);
+impl HttpDate {
+    /// Returns the current time.
+    pub fn now() -> Self {
+        HttpDate::from(SystemTime::now())
+    }
+
+    /// Converts to `SystemTime`.
+    pub fn to_system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.0)
+    }
+
+    /// Returns the number of seconds elapsed since the UNIX epoch.
+    pub fn as_secs(&self) -> u64 {
+        self.0
+    }
+}
+impl From<SystemTime> for HttpDate {
+    fn from(f: SystemTime) -> Self {
+        HttpDate(
+            f.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        )
+    }
+}
+impl From<HttpDate> for SystemTime {
+    fn from(f: HttpDate) -> Self {
+        f.to_system_time()
+    }
+}
+impl fmt::Display for HttpDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let days = (self.0 / 86_400) as i64;
+        let secs = self.0 % 86_400;
+        let (year, month, day) = civil_from_days(days);
+        // 1970-01-01 is Thursday.
+        let weekday = ((days + 3) % 7) as usize;
+        write!(
+            f,
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[weekday],
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+}
+impl FromStr for HttpDate {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = parse_imf_fixdate(s)
+            .or_else(|| parse_rfc850_date(s))
+            .or_else(|| parse_asctime_date(s));
+        let (year, month, day, secs) =
+            track_assert_some!(parsed, Status::BadRequest, "Malformed HTTP-date: {:?}", s);
+        track_assert!(year >= 1970, Status::BadRequest, "Too old HTTP-date: {:?}", s);
+        let days = days_from_civil(year, month, day);
+        Ok(HttpDate(days as u64 * 86_400 + secs))
+    }
+}
+
+/// `Date` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Date(pub HttpDate);
+impl<'a> Header<'a> for Date {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Date"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.0)
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        track!(value.trim().parse()).map(Date)
+    }
+}
+
+type Fields = (i64, u32, u32, u64);
+
+// e.g., "Sun, 06 Nov 1994 08:49:37 GMT"
+fn parse_imf_fixdate(s: &str) -> Option<Fields> {
+    let mut tokens = s.split(' ');
+    let weekday = tokens.next()?;
+    if weekday.len() != 4 || !weekday.ends_with(',') || !WEEKDAYS.contains(&&weekday[..3]) {
+        return None;
+    }
+    let day = parse_digits(tokens.next()?, 2)?;
+    let month = parse_month(tokens.next()?)?;
+    let year = parse_digits(tokens.next()?, 4)?;
+    let secs = parse_time(tokens.next()?)?;
+    if tokens.next()? != "GMT" || tokens.next().is_some() {
+        return None;
+    }
+    validate(year as i64, month, day, secs)
+}
+
+// e.g., "Sunday, 06-Nov-94 08:49:37 GMT"
+fn parse_rfc850_date(s: &str) -> Option<Fields> {
+    let mut tokens = s.split(' ');
+    let weekday = tokens.next()?;
+    if !weekday.ends_with(',') || !LONG_WEEKDAYS.contains(&&weekday[..weekday.len() - 1]) {
+        return None;
+    }
+    let mut date = tokens.next()?.split('-');
+    let day = parse_digits(date.next()?, 2)?;
+    let month = parse_month(date.next()?)?;
+    let year = parse_digits(date.next()?, 2)?;
+    if date.next().is_some() {
+        return None;
+    }
+    // RFC 7231: two-digit years which appear to be more than 50 years in the future
+    // are interpreted as the past. Here we simply use 1970 as the pivot.
+    let year = if year < 70 { 2000 + year } else { 1900 + year };
+    let secs = parse_time(tokens.next()?)?;
+    if tokens.next()? != "GMT" || tokens.next().is_some() {
+        return None;
+    }
+    validate(year as i64, month, day, secs)
+}
+
+// e.g., "Sun Nov  6 08:49:37 1994"
+fn parse_asctime_date(s: &str) -> Option<Fields> {
+    if s.len() != 24 || !WEEKDAYS.contains(&s.get(..3)?) || s.get(3..4)? != " " {
+        return None;
+    }
+    let month = parse_month(s.get(4..7)?)?;
+    if s.get(7..8)? != " " {
+        return None;
+    }
+    let day = parse_digits(s.get(8..10)?.trim_start(), 1).or_else(|| parse_digits(s.get(8..10)?, 2))?;
+    if s.get(10..11)? != " " || s.get(19..20)? != " " {
+        return None;
+    }
+    let secs = parse_time(s.get(11..19)?)?;
+    let year = parse_digits(s.get(20..)?, 4)?;
+    validate(year as i64, month, day, secs)
+}
+
+fn parse_digits(s: &str, len: usize) -> Option<u32> {
+    if s.len() != len || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+fn parse_month(s: &str) -> Option<u32> {
+    MONTHS.iter().position(|&m| m == s).map(|i| i as u32 + 1)
+}
+
+fn parse_time(s: &str) -> Option<u64> {
+    let mut hms = s.split(':');
+    let h = parse_digits(hms.next()?, 2)?;
+    let m = parse_digits(hms.next()?, 2)?;
+    let sec = parse_digits(hms.next()?, 2)?;
+    if hms.next().is_some() || h > 23 || m > 59 || sec > 60 {
+        return None;
+    }
+    Some(u64::from(h * 3600 + m * 60 + sec))
+}
+
+fn validate(year: i64, month: u32, day: u32, secs: u64) -> Option<Fields> {
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if day == 0 || day > days_in_month {
+        return None;
+    }
+    Some((year, month, day, secs))
+}
+
+// See: http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let m = i64::from(month);
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -11,6 +11,51 @@ use status::Status;
 #[derive(Debug, Clone)]
 pub struct Error(TrackableError<Status>);
 derive_traits_for_trackable_error_newtype!(Error, Status);
+impl Error {
+    /// Returns the class of the error.
+    ///
+    /// The class is determined by the cause of the error (if it is an I/O or parse error)
+    /// and the status (i.e., the kind) of the error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io;
+    /// use miasht::{Error, ErrorClass, Status};
+    ///
+    /// let e = Error::from(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+    /// assert_eq!(*e.kind(), Status::InternalServerError);
+    /// assert_eq!(e.class(), ErrorClass::ConnectionClosed);
+    ///
+    /// let e = Error::from(Status::PayloadTooLarge);
+    /// assert_eq!(e.class(), ErrorClass::BodyTooLarge);
+    /// ```
+    pub fn class(&self) -> ErrorClass {
+        if let Some(e) = self.concrete_cause::<io::Error>() {
+            return match e.kind() {
+                io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::NotConnected => ErrorClass::ConnectionClosed,
+                io::ErrorKind::TimedOut => ErrorClass::Timeout,
+                _ => ErrorClass::Other,
+            };
+        }
+        if let Some(&httparse::Error::TooManyHeaders) = self.concrete_cause::<httparse::Error>() {
+            return ErrorClass::TooManyHeaders;
+        }
+        match *self.kind() {
+            Status::BadRequest => ErrorClass::MalformedSyntax,
+            Status::RequestTimeout => ErrorClass::Timeout,
+            Status::PayloadTooLarge => ErrorClass::BodyTooLarge,
+            Status::UriTooLong | Status::RequestHeaderFieldsTooLarge => ErrorClass::HeaderTooLarge,
+            Status::HttpVersionNotSupported => ErrorClass::UnsupportedVersion,
+            Status::BadGateway | Status::GatewayTimeout => ErrorClass::Upstream,
+            _ => ErrorClass::Other,
+        }
+    }
+}
 impl From<io::Error> for Error {
     fn from(f: io::Error) -> Self {
         Status::InternalServerError.cause(f).into()
@@ -18,7 +63,12 @@ impl From<io::Error> for Error {
 }
 impl From<httparse::Error> for Error {
     fn from(f: httparse::Error) -> Self {
-        Status::BadRequest.cause(f).into()
+        let status = match f {
+            httparse::Error::TooManyHeaders => Status::RequestHeaderFieldsTooLarge,
+            httparse::Error::Version => Status::HttpVersionNotSupported,
+            _ => Status::BadRequest,
+        };
+        status.cause(f).into()
     }
 }
 impl<E: std::error::Error + Send + Sync + 'static> From<header::ParseValueError<E>> for Error {
@@ -28,8 +78,43 @@ impl<E: std::error::Error + Send + Sync + 'static> From<header::ParseValueError<
 }
 impl From<RecvError> for Error {
     fn from(f: RecvError) -> Self {
-        Status::BadRequest.cause(f).into()
+        // The sender has been dropped, which is not a fault of the peer.
+        Status::InternalServerError.cause(f).into()
     }
 }
 
+/// The class of an `Error` (see `Error::class`).
+///
+/// Unlike the status of an error, which is what a server should respond with,
+/// the class tells what happened (e.g., for metrics).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// The peer closed (or reset) the connection.
+    ConnectionClosed,
+
+    /// An operation timed out.
+    Timeout,
+
+    /// The request line or the header section is too large for the buffer.
+    HeaderTooLarge,
+
+    /// The message has more headers than the limit.
+    TooManyHeaders,
+
+    /// The message is syntactically malformed.
+    MalformedSyntax,
+
+    /// The body exceeds the limit.
+    BodyTooLarge,
+
+    /// The HTTP version of the message is not supported.
+    UnsupportedVersion,
+
+    /// An upstream server failed or sent an invalid response.
+    Upstream,
+
+    /// Other errors (e.g., I/O errors other than the above ones, or bugs).
+    Other,
+}
+
 impl ErrorKind for Status {}
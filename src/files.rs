@@ -0,0 +1,534 @@
+//! Static file serving.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # extern crate futures;
+//! # extern crate miasht;
+//! use futures::Future;
+//! use miasht::TransportStream;
+//! use miasht::files::StaticFiles;
+//! use miasht::server::Request;
+//!
+//! # fn main() {
+//! let files = StaticFiles::new("public").unwrap()
+//!     .index_file("index.html")
+//!     .directory_listing(true);
+//!
+//! fn handle<T: TransportStream>(files: &StaticFiles, request: Request<T>) {
+//!     let _connection = files.serve(request).wait();
+//! }
+//! # }
+//! ```
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use futures::{Async, Future, Poll};
+
+use {Error, Method, Status, TransportStream};
+use conditional::{EntityTag, Validators};
+use header::{ContentLength, ContentType};
+use range::{RangeBody, Resolution, Segment};
+use server::{Connection, Request, Response};
+
+/// The default number of bytes read from a file at a time.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Returns the media type associated with the extension of `path`.
+///
+/// If the extension is unknown, `application/octet-stream` is returned.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::files::mime_type;
+///
+/// assert_eq!(mime_type("index.HTML".as_ref()), "text/html; charset=utf-8");
+/// assert_eq!(mime_type("video.mp4".as_ref()), "video/mp4");
+/// assert_eq!(mime_type("README".as_ref()), "application/octet-stream");
+/// ```
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") => "application/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("tar") => "application/x-tar",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A handler which serves the files under a root directory.
+///
+/// Only `GET` and `HEAD` requests are handled.
+/// Request paths which contain `..` segments and files which
+/// (through symbolic links) reside outside of the root directory are rejected.
+///
+/// Responses carry `Content-Length`, `Last-Modified` and `ETag` headers, and
+/// support conditional requests (`conditional::evaluate`) and
+/// range requests (`range::evaluate`).
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index_file: Option<String>,
+    directory_listing: bool,
+    chunk_size: usize,
+}
+impl StaticFiles {
+    /// Makes a new `StaticFiles` instance which serves the files under `root`.
+    pub fn new<P: AsRef<Path>>(root: P) -> ::Result<Self> {
+        let root = track!(fs::canonicalize(root).map_err(Error::from))?;
+        track_assert!(
+            root.is_dir(),
+            Status::InternalServerError,
+            "Not a directory: {:?}",
+            root
+        );
+        Ok(StaticFiles {
+            root,
+            index_file: None,
+            directory_listing: false,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        })
+    }
+
+    /// Sets the name of the file which is served for directory requests.
+    pub fn index_file(mut self, name: &str) -> Self {
+        self.index_file = Some(name.to_owned());
+        self
+    }
+
+    /// Enables (or disables) HTML listings for directories which have no index file.
+    ///
+    /// The default value is `false`.
+    pub fn directory_listing(mut self, enabled: bool) -> Self {
+        self.directory_listing = enabled;
+        self
+    }
+
+    /// Sets the number of bytes read from a file at a time.
+    ///
+    /// The default value is `DEFAULT_CHUNK_SIZE`.
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = cmp::max(size, 1);
+        self
+    }
+
+    /// Returns the (canonicalized) root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Maps the path of a request target to a file system path under the root directory.
+    ///
+    /// The query part is ignored and percent-encoded octets are decoded.
+    ///
+    /// # Errors
+    ///
+    /// - `Status::BadRequest`: the path is not an absolute path or is not valid UTF-8 when decoded
+    /// - `Status::Forbidden`: the path escapes from the root directory
+    /// - `Status::NotFound`: the file does not exist
+    pub fn resolve(&self, request_path: &str) -> ::Result<PathBuf> {
+        let path = request_path.split(['?', '#']).next().unwrap_or("");
+        track_assert!(
+            path.starts_with('/'),
+            Status::BadRequest,
+            "Not an absolute path: {:?}",
+            request_path
+        );
+        let path = track!(percent_decode(path))?;
+
+        let mut resolved = self.root.clone();
+        for segment in path.split('/') {
+            track_assert!(
+                !segment.contains(['\\', '\0']),
+                Status::Forbidden,
+                "Invalid path segment: {:?}",
+                segment
+            );
+            match Path::new(segment).components().next() {
+                None | Some(Component::CurDir) => {}
+                Some(Component::Normal(_)) if !segment.contains(':') => resolved.push(segment),
+                _ => track_panic!(Status::Forbidden, "Invalid path segment: {:?}", segment),
+            }
+        }
+
+        track!(self.canonicalize(&resolved), "request_path={:?}", request_path)
+    }
+
+    // Symbolic links may point to files out of the root directory.
+    fn canonicalize(&self, path: &Path) -> ::Result<PathBuf> {
+        let canonical = track!(fs::canonicalize(path).map_err(io_error))?;
+        track_assert!(
+            canonical.starts_with(&self.root),
+            Status::Forbidden,
+            "Out of the root directory: {:?}",
+            path
+        );
+        Ok(canonical)
+    }
+
+    /// Serves the file specified by `request`.
+    ///
+    /// Errors are reported to the client as responses
+    /// (e.g., `404 Not Found`), so the returned future only fails
+    /// if the response could not be written.
+    pub fn serve<T: TransportStream>(&self, request: Request<T>) -> ServeFile<T> {
+        match self.plan(&request) {
+            Ok(plan) => self.respond(request, plan),
+            Err(e) => {
+                let status = *e.kind();
+                self.respond(request, Plan::Error(status))
+            }
+        }
+    }
+
+    fn plan<T>(&self, request: &Request<T>) -> ::Result<Plan> {
+        let method = request.method();
+        if method != Method::Get && method != Method::Head {
+            return Ok(Plan::MethodNotAllowed);
+        }
+        let path = track!(self.resolve(request.path()))?;
+        let metadata = track!(fs::metadata(&path).map_err(io_error))?;
+        if !metadata.is_dir() {
+            return track!(self.plan_file(request, path, &metadata));
+        }
+
+        let request_path = request.path().split(['?', '#']).next().unwrap_or("");
+        if !request_path.ends_with('/') {
+            // A leading "//" would make the location a network-path reference.
+            let location = format!("/{}/", request_path.trim_start_matches('/'));
+            return Ok(Plan::Redirect(location));
+        }
+        if let Some(ref index) = self.index_file {
+            let index_path = path.join(index);
+            match self.canonicalize(&index_path) {
+                Err(ref e) if *e.kind() == Status::NotFound => {}
+                Err(e) => return Err(track!(e)),
+                Ok(index_path) => {
+                    let metadata = track!(fs::metadata(&index_path).map_err(io_error))?;
+                    if metadata.is_file() {
+                        return track!(self.plan_file(request, index_path, &metadata));
+                    }
+                }
+            }
+        }
+        track_assert!(
+            self.directory_listing,
+            Status::Forbidden,
+            "Directory listing is disabled"
+        );
+        let html = track!(directory_listing(&path, request_path))?;
+        Ok(Plan::Listing(html))
+    }
+
+    fn plan_file<T>(&self, request: &Request<T>, path: PathBuf, metadata: &fs::Metadata) -> ::Result<Plan> {
+        track_assert!(metadata.is_file(), Status::Forbidden, "Not a regular file: {:?}", path);
+        let length = metadata.len();
+        let mut validators = Validators::new();
+        if let Ok(modified) = metadata.modified() {
+            let mtime = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+            let tag = format!("{:x}-{:x}.{:x}", length, mtime.as_secs(), mtime.subsec_nanos());
+            validators = validators.etag(EntityTag::strong(&tag)).last_modified(modified);
+        }
+
+        let status = request.evaluate_preconditions(&validators);
+        if status != Status::Ok {
+            return Ok(Plan::NotModified(status, validators));
+        }
+        let resolution = request.evaluate_range(&validators, length);
+        let content_type = mime_type(&path);
+        let body = RangeBody::new(&resolution, length, content_type);
+        let file = if request.method() == Method::Head || resolution == Resolution::NotSatisfiable {
+            None
+        } else {
+            Some(track!(fs::File::open(&path).map_err(io_error))?)
+        };
+        Ok(Plan::File {
+            file,
+            body,
+            content_type,
+            validators,
+            single_part: match resolution {
+                Resolution::Partial(ref ranges) => ranges.len() == 1,
+                _ => true,
+            },
+        })
+    }
+
+    fn respond<T: TransportStream>(&self, request: Request<T>, plan: Plan) -> ServeFile<T> {
+        let is_head = request.method() == Method::Head;
+        let connection = request.finish();
+        let mut file = None;
+        let mut segments = VecDeque::new();
+        let response = match plan {
+            Plan::File {
+                file: f,
+                body,
+                content_type,
+                validators,
+                single_part,
+            } => {
+                let mut response = connection.build_response(body.status());
+                body.add_headers(&mut response.headers_mut());
+                validators.add_headers(&mut response.headers_mut());
+                if single_part && body.status() != Status::RangeNotSatisfiable {
+                    response.add_header(&ContentType(content_type));
+                }
+                if !is_head {
+                    segments.extend(body.segments());
+                }
+                file = f;
+                response.finish()
+            }
+            Plan::NotModified(status, validators) => {
+                let mut response = connection.build_response(status);
+                validators.add_headers(&mut response.headers_mut());
+                if status != Status::NotModified {
+                    response.add_header(&ContentLength(0));
+                }
+                response.finish()
+            }
+            Plan::Listing(html) => {
+                let mut response = connection.build_response(Status::Ok);
+                response.add_header(&ContentType("text/html; charset=utf-8"));
+                response.add_header(&ContentLength(html.len() as u64));
+                if !is_head {
+                    segments.push_back(Segment::Bytes(html.into_bytes()));
+                }
+                response.finish()
+            }
+            Plan::Redirect(location) => {
+                let mut response = connection.build_response(Status::MovedPermanently);
+                response.add_raw_header("Location", location.as_bytes());
+                response.add_header(&ContentLength(0));
+                response.finish()
+            }
+            Plan::MethodNotAllowed => {
+                let mut response = connection.build_response(Status::MethodNotAllowed);
+                response.add_raw_header("Allow", b"GET, HEAD");
+                response.add_header(&ContentLength(0));
+                response.finish()
+            }
+            Plan::Error(status) => {
+                let text = format!("{}\n", status.reason_phrase());
+                let mut response = connection.build_response(status);
+                response.add_header(&ContentType("text/plain; charset=utf-8"));
+                response.add_header(&ContentLength(text.len() as u64));
+                if !is_head {
+                    segments.push_back(Segment::Bytes(text.into_bytes()));
+                }
+                response.finish()
+            }
+        };
+        ServeFile {
+            response,
+            file,
+            segments,
+            buf: Vec::new(),
+            offset: 0,
+            remaining: 0,
+            chunk_size: self.chunk_size,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Plan {
+    File {
+        file: Option<fs::File>,
+        body: RangeBody,
+        content_type: &'static str,
+        validators: Validators,
+        single_part: bool,
+    },
+    NotModified(Status, Validators),
+    Listing(String),
+    Redirect(String),
+    MethodNotAllowed,
+    Error(Status),
+}
+
+/// A future which writes a response of `StaticFiles`.
+///
+/// The file is read and written in chunks of `StaticFiles::chunk_size` bytes.
+#[derive(Debug)]
+pub struct ServeFile<T> {
+    response: Response<T>,
+    file: Option<fs::File>,
+    segments: VecDeque<Segment>,
+    buf: Vec<u8>,
+    offset: usize,
+    remaining: u64,
+    chunk_size: usize,
+}
+impl<T: TransportStream> Future for ServeFile<T> {
+    type Item = Connection<T>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if self.offset < self.buf.len() {
+                match self.response.write(&self.buf[self.offset..]) {
+                    Err(e) => {
+                        if e.kind() == io::ErrorKind::WouldBlock {
+                            return Ok(Async::NotReady);
+                        }
+                        return Err(track!(Error::from(e)));
+                    }
+                    Ok(0) => {
+                        track_panic!(Status::InternalServerError, "Unexpected EOS");
+                    }
+                    Ok(n) => self.offset += n,
+                }
+            } else if self.remaining > 0 {
+                let file = self.file.as_mut().expect("Never fails");
+                let size = cmp::min(self.remaining, self.chunk_size as u64) as usize;
+                self.buf.resize(size, 0);
+                let n = track!(file.read(&mut self.buf).map_err(Error::from))?;
+                track_assert_ne!(n, 0, Status::InternalServerError, "The file has been truncated");
+                self.buf.truncate(n);
+                self.offset = 0;
+                self.remaining -= n as u64;
+            } else if let Some(segment) = self.segments.pop_front() {
+                match segment {
+                    Segment::Bytes(bytes) => {
+                        self.buf = bytes;
+                        self.offset = 0;
+                    }
+                    Segment::Range(range) => {
+                        let file = self.file.as_mut().expect("Never fails");
+                        track!(file.seek(SeekFrom::Start(range.first)).map_err(Error::from))?;
+                        self.remaining = range.len();
+                    }
+                }
+            } else {
+                return track!(self.response.poll());
+            }
+        }
+    }
+}
+
+fn io_error(e: io::Error) -> Error {
+    use trackable::error::ErrorKindExt;
+    let status = match e.kind() {
+        io::ErrorKind::NotFound => Status::NotFound,
+        io::ErrorKind::PermissionDenied => Status::Forbidden,
+        _ => Status::InternalServerError,
+    };
+    status.cause(e).into()
+}
+
+fn percent_decode(s: &str) -> ::Result<String> {
+    let bytes = s.as_bytes();
+    let mut buf = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+            let b = hex.and_then(|h| u8::from_str_radix(h, 16).ok());
+            let b = track_assert_some!(b, Status::BadRequest, "Malformed path: {:?}", s);
+            buf.push(b);
+            i += 3;
+        } else {
+            buf.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(buf).map_err(|_| track!(Error::from(Status::BadRequest)))
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut buf = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => buf.push(b as char),
+            _ => {
+                let _ = write!(buf, "%{:02X}", b);
+            }
+        }
+    }
+    buf
+}
+
+fn html_escape(s: &str) -> String {
+    let mut buf = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' => buf.push_str("&quot;"),
+            '\'' => buf.push_str("&#39;"),
+            _ => buf.push(c),
+        }
+    }
+    buf
+}
+
+fn directory_listing(dir: &Path, request_path: &str) -> ::Result<String> {
+    let mut entries = Vec::new();
+    for entry in track!(fs::read_dir(dir).map_err(io_error))? {
+        let entry = track!(entry.map_err(io_error))?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        entries.push((!is_dir, name));
+    }
+    entries.sort();
+
+    let title = html_escape(&percent_decode(request_path).unwrap_or_default());
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n\
+         <body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    if request_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_file, name) in entries {
+        let suffix = if is_file { "" } else { "/" };
+        let _ = writeln!(
+            html,
+            "<li><a href=\"{}{}\">{}{}</a></li>",
+            percent_encode(&name),
+            suffix,
+            html_escape(&name),
+            suffix
+        );
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    Ok(html)
+}
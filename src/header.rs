@@ -3,6 +3,7 @@ use std::fmt;
 use std::str;
 use std::error;
 use std::slice;
+use std::num;
 use httparse;
 
 use connection::Buffer;
@@ -78,6 +79,132 @@ pub trait Header<'a>: Sized {
     fn parse_value_str(value: &'a str) -> Result<Self, Self::Error>;
 }
 
+/// `Content-Length` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentLength(pub u64);
+impl<'a> Header<'a> for ContentLength {
+    type Error = num::ParseIntError;
+    fn name() -> &'static str {
+        "Content-Length"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.0)
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        value.trim().parse().map(ContentLength)
+    }
+}
+
+/// `Content-Type` header.
+///
+/// The value is kept as is (i.e., media type parameters are not parsed).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentType<'a>(pub &'a str);
+impl<'a> ContentType<'a> {
+    /// Returns the media type part of the value (e.g., `"text/html"` for `"text/html; charset=utf-8"`).
+    pub fn media_type(&self) -> &'a str {
+        self.0.split(';').next().unwrap_or("").trim()
+    }
+
+    /// Returns the value of the parameter named `name` if it exists.
+    pub fn param(&self, name: &str) -> Option<&'a str> {
+        find_param(self.0, name)
+    }
+}
+impl<'a> Header<'a> for ContentType<'a> {
+    type Error = Never;
+    fn name() -> &'static str {
+        "Content-Type"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.0)
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        Ok(ContentType(value.trim()))
+    }
+}
+
+/// `Content-Disposition` header.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::header::{ContentDisposition, Header};
+///
+/// let value = r#"form-data; name="file"; filename="a;b.txt""#;
+/// let h = ContentDisposition::parse_value_str(value).unwrap();
+/// assert_eq!(h.disposition_type(), "form-data");
+/// assert_eq!(h.param("name"), Some("file"));
+/// assert_eq!(h.param("filename"), Some("a;b.txt"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentDisposition<'a>(pub &'a str);
+impl<'a> ContentDisposition<'a> {
+    /// Returns the disposition type (e.g., `"attachment"`, `"form-data"`).
+    pub fn disposition_type(&self) -> &'a str {
+        self.0.split(';').next().unwrap_or("").trim()
+    }
+
+    /// Returns the value of the parameter named `name` if it exists.
+    pub fn param(&self, name: &str) -> Option<&'a str> {
+        find_param(self.0, name)
+    }
+}
+impl<'a> Header<'a> for ContentDisposition<'a> {
+    type Error = Never;
+    fn name() -> &'static str {
+        "Content-Disposition"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.0)
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        Ok(ContentDisposition(value.trim()))
+    }
+}
+
+/// Finds the parameter named `name` from a `;` separated parameter list.
+///
+/// The first element of the list (e.g., media type) is skipped.
+/// Quotes surrounding the value are removed, but escape sequences are kept as is.
+fn find_param<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = value;
+    loop {
+        rest = &rest[rest.find(';')? + 1..];
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim();
+        rest = rest[eq + 1..].trim_start();
+        let (v, next) = if let Some(quoted) = rest.strip_prefix('"') {
+            let mut escaped = false;
+            quoted
+                .char_indices()
+                .find(|&(_, c)| {
+                    let found = !escaped && c == '"';
+                    escaped = !escaped && c == '\\';
+                    found
+                })
+                .map_or((quoted, ""), |(i, _)| (&quoted[..i], &quoted[i + 1..]))
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            (rest[..end].trim(), &rest[end..])
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(v);
+        }
+        rest = next;
+    }
+}
+
+/// An error type for headers which never fail to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Never {}
+impl fmt::Display for Never {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
+impl error::Error for Never {}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParseValueError<E> {
     InvalidUtf8 {
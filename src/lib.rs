@@ -1,25 +1,53 @@
+extern crate base64;
+extern crate flate2;
+#[macro_use]
 extern crate futures;
+extern crate getrandom;
 extern crate httparse;
+extern crate md5;
+extern crate sha1;
+extern crate sha2;
 #[macro_use]
 extern crate trackable;
 
-pub use method::Method;
+pub use method::{ExtensionMethod, Method, RequestBody};
 pub use status::Status;
 pub use traits::Metadata;
 pub use version::Version;
 pub use connection::TransportStream;
-pub use error::Error;
+pub use error::{Error, ErrorClass};
 
 pub mod header;
 pub mod client;
 pub mod server;
 pub mod status;
+pub mod query;
+pub mod multipart;
+pub mod cookie;
+pub mod date;
+pub mod auth;
+pub mod conditional;
+pub mod range;
+pub mod files;
+pub mod cache;
+pub mod negotiation;
+pub mod chunked;
+pub mod compression;
+pub mod upgrade;
+pub mod websocket;
+pub mod tunnel;
+pub mod proxy;
+pub mod pool;
+pub mod redirect;
+pub mod retry;
+pub mod uri;
 mod error;
 mod traits;
 mod method;
 mod version;
 mod connection;
 mod unsafe_types;
+mod util;
 
 // TODO
 pub mod defaults {
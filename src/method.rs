@@ -1,4 +1,8 @@
 use std::fmt;
+use std::str::{self, FromStr};
+
+use {Error, Status};
+use auth::is_token;
 
 /// HTTP Method.
 ///
@@ -17,6 +21,14 @@ use std::fmt;
 /// let method = Method::try_from_str("GET").unwrap();
 /// assert_eq!(method.as_str(), "GET");
 /// assert_eq!(method.to_string(), "GET");
+///
+/// // Unregistered methods are parsed as extension methods.
+/// let method: Method = "FETCH-STATS".parse().unwrap();
+/// assert!(method.is_extension());
+/// assert_eq!(method.to_string(), "FETCH-STATS");
+/// assert_eq!(method.to_string().parse::<Method>().unwrap(), method);
+/// assert_eq!("GET".parse::<Method>().unwrap(), Method::Get);
+/// assert!("BAD METHOD".parse::<Method>().is_err());
 /// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Method {
@@ -59,8 +71,14 @@ pub enum Method {
     Update,
     Updateredirectref,
     VersionControl,
+
+    /// A method which is not registered in the IANA registry.
+    Extension(ExtensionMethod),
 }
 impl Method {
+    /// Returns the registered method named `method`.
+    ///
+    /// Extension methods are not returned (use `str::parse` for them).
     pub fn try_from_str(method: &str) -> Option<Self> {
         Some(match method {
             "ACL" => Method::Acl,
@@ -146,6 +164,147 @@ impl Method {
             Method::Update => "UPDATE",
             Method::Updateredirectref => "UPDATEREDIRECTREF",
             Method::VersionControl => "VERSION-CONTROL",
+            Method::Extension(ref m) => m.as_str(),
+        }
+    }
+
+    /// Returns `true` if this is an extension method, otherwise `false`.
+    pub fn is_extension(&self) -> bool {
+        matches!(*self, Method::Extension(_))
+    }
+
+    /// Returns `true` if the method is safe (i.e., read-only), otherwise `false`.
+    ///
+    /// See [RFC 7231 §4.2.1](https://tools.ietf.org/html/rfc7231#section-4.2.1)
+    /// and the IANA registry. Extension methods are never regarded as safe.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::Method;
+    ///
+    /// assert!(Method::Get.is_safe());
+    /// assert!(Method::Propfind.is_safe());
+    /// assert!(!Method::Put.is_safe());
+    /// assert!(!"FETCH-STATS".parse::<Method>().unwrap().is_safe());
+    /// ```
+    pub fn is_safe(&self) -> bool {
+        matches!(
+            *self,
+            Method::Get | Method::Head | Method::Options | Method::Pri | Method::Propfind
+                | Method::Report | Method::Search | Method::Trace
+        )
+    }
+
+    /// Returns `true` if the method is idempotent, otherwise `false`.
+    ///
+    /// See [RFC 7231 §4.2.2](https://tools.ietf.org/html/rfc7231#section-4.2.2)
+    /// and the IANA registry. Extension methods are never regarded as idempotent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::Method;
+    ///
+    /// assert!(Method::Get.is_idempotent());
+    /// assert!(Method::Put.is_idempotent());
+    /// assert!(Method::Delete.is_idempotent());
+    /// assert!(!Method::Post.is_idempotent());
+    /// assert!(!Method::Lock.is_idempotent());
+    /// ```
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(
+            *self,
+            Method::Connect | Method::Lock | Method::Patch | Method::Post | Method::Extension(_)
+        )
+    }
+
+    /// Returns `true` if responses to the method are cacheable, otherwise `false`.
+    ///
+    /// Only `GET`, `HEAD` and `POST` are defined as cacheable by
+    /// [RFC 7231 §4.2.3](https://tools.ietf.org/html/rfc7231#section-4.2.3).
+    /// Note that responses to `POST` are reusable only if they have explicit freshness information
+    /// and a `Content-Location` header which is the same as the request target.
+    pub fn is_cacheable(&self) -> bool {
+        matches!(*self, Method::Get | Method::Head | Method::Post)
+    }
+
+    /// Returns the semantics of a body in a request of the method.
+    ///
+    /// Note that this does not affect the message framing:
+    /// the length of a request body is always determined by the headers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::{Method, RequestBody};
+    ///
+    /// assert_eq!(Method::Post.request_body(), RequestBody::Meaningful);
+    /// assert_eq!(Method::Get.request_body(), RequestBody::Undefined);
+    /// assert_eq!(Method::Trace.request_body(), RequestBody::Forbidden);
+    /// ```
+    pub fn request_body(&self) -> RequestBody {
+        match *self {
+            Method::Trace => RequestBody::Forbidden,
+            Method::Connect | Method::Copy | Method::Delete | Method::Get | Method::Head
+            | Method::Link | Method::Move | Method::Options | Method::Pri | Method::Unlink
+            | Method::Unlock => RequestBody::Undefined,
+            _ => RequestBody::Meaningful,
+        }
+    }
+
+    /// Returns `true` if a response which has `status` to a request of the method
+    /// has a body, otherwise `false`.
+    ///
+    /// Responses to `HEAD`, `1xx`, `204` and `304` responses,
+    /// and `2xx` responses to `CONNECT` never have a body
+    /// ([RFC 7230 §3.3.3](https://tools.ietf.org/html/rfc7230#section-3.3.3)).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::Method;
+    ///
+    /// assert!(Method::Get.has_response_body(200));
+    /// assert!(!Method::Get.has_response_body(304));
+    /// assert!(!Method::Head.has_response_body(200));
+    /// assert!(!Method::Connect.has_response_body(200));
+    /// assert!(Method::Connect.has_response_body(407));
+    /// ```
+    pub fn has_response_body(&self, status: u16) -> bool {
+        match *self {
+            Method::Head => false,
+            Method::Connect if status / 100 == 2 => false,
+            _ => status / 100 != 1 && status != 204 && status != 304,
+        }
+    }
+}
+
+/// The semantics of a request body (see `Method::request_body`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestBody {
+    /// The body has defined semantics for the method.
+    Meaningful,
+
+    /// The body has no defined semantics for the method
+    /// and may cause some implementations to reject the request.
+    Undefined,
+
+    /// The body must not be sent.
+    Forbidden,
+}
+impl FromStr for Method {
+    type Err = Error;
+
+    /// Parses a registered method or an extension method (case-sensitive).
+    ///
+    /// If `s` is not a token, `Status::BadRequest` error will be returned.
+    /// If `s` is too long to be an extension method, `Status::NotImplemented` error will be returned.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(method) = Method::try_from_str(s) {
+            Ok(method)
+        } else {
+            Ok(Method::Extension(track!(s.parse())?))
         }
     }
 }
@@ -154,3 +313,50 @@ impl fmt::Display for Method {
         write!(f, "{}", self.as_str())
     }
 }
+
+/// The name of an extension method.
+///
+/// The name is a token ([RFC 7230 §3.2.6](https://tools.ietf.org/html/rfc7230#section-3.2.6))
+/// of at most `MAX_LEN` bytes. It is stored inline, so that `Method` remains `Copy`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExtensionMethod {
+    bytes: [u8; ExtensionMethod::MAX_LEN],
+    len: u8,
+}
+impl ExtensionMethod {
+    /// The maximum length of an extension method name.
+    pub const MAX_LEN: usize = 32;
+
+    /// Returns the name of the method.
+    pub fn as_str(&self) -> &str {
+        str::from_utf8(&self.bytes[..self.len as usize]).expect("Never fails")
+    }
+}
+impl FromStr for ExtensionMethod {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        track_assert!(is_token(s), Status::BadRequest, "Malformed method: {:?}", s);
+        track_assert!(
+            s.len() <= Self::MAX_LEN,
+            Status::NotImplemented,
+            "Too long method: {:?}",
+            s
+        );
+        let mut bytes = [0; Self::MAX_LEN];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(ExtensionMethod {
+            bytes,
+            len: s.len() as u8,
+        })
+    }
+}
+impl fmt::Debug for ExtensionMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+impl fmt::Display for ExtensionMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
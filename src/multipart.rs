@@ -0,0 +1,579 @@
+//! `multipart/form-data` support ([RFC 7578](https://tools.ietf.org/html/rfc7578)).
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::Read;
+//! use miasht::multipart::{Builder, Multipart};
+//!
+//! let mut builder = Builder::new("xyz");
+//! builder.add_text("title", "hello");
+//! builder.add_file("file", "a.txt", "text/plain", b"foo\r\nbar");
+//! let body = builder.finish();
+//!
+//! let mut multipart = Multipart::new(&body[..], "xyz");
+//!
+//! let part = multipart.next_part().unwrap().unwrap();
+//! assert_eq!(part.name(), Some("title"));
+//! let mut value = String::new();
+//! multipart.read_to_string(&mut value).unwrap();
+//! assert_eq!(value, "hello");
+//!
+//! let part = multipart.next_part().unwrap().unwrap();
+//! assert_eq!(part.name(), Some("file"));
+//! assert_eq!(part.filename(), Some("a.txt"));
+//! assert_eq!(part.headers().get("Content-Type"), Some(&b"text/plain"[..]));
+//! let mut content = Vec::new();
+//! multipart.read_to_end(&mut content).unwrap();
+//! assert_eq!(content, b"foo\r\nbar");
+//!
+//! assert!(multipart.next_part().unwrap().is_none());
+//! ```
+use std::cmp;
+use std::io::{self, Read, Write};
+use httparse;
+use futures::{Async, Poll};
+
+use {Error, Status};
+use header::{ContentDisposition, ContentType, Header, Headers};
+use unsafe_types::UnsafeHeader;
+use util::random_bytes;
+
+/// The media type of multipart form bodies.
+pub const FORM_DATA: &str = "multipart/form-data";
+
+// The maximum length of the transport padding which may follow a delimiter.
+const MAX_DELIMITER_PADDING: usize = 64;
+
+/// Returns the boundary parameter of `content_type` if it is a `multipart/*` media type.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::header::ContentType;
+/// use miasht::multipart;
+///
+/// assert_eq!(multipart::boundary(&ContentType("Multipart/Form-Data; boundary=a")), Some("a"));
+/// assert_eq!(multipart::boundary(&ContentType("multipart/mixed; boundary=")), None);
+/// assert_eq!(multipart::boundary(&ContentType("text/plain; boundary=a")), None);
+/// assert_eq!(multipart::boundary(&ContentType("multipartéx; boundary=a")), None);
+/// ```
+pub fn boundary<'a>(content_type: &ContentType<'a>) -> Option<&'a str> {
+    let media_type = content_type.media_type();
+    if !media_type
+        .get(..10)
+        .is_some_and(|t| t.eq_ignore_ascii_case("multipart/"))
+    {
+        return None;
+    }
+    content_type.param("boundary").filter(|b| !b.is_empty())
+}
+
+/// Size limits applied by `Multipart`.
+///
+/// If a limit is exceeded, `Multipart` fails with `Status::PayloadTooLarge`
+/// (or `Status::RequestHeaderFieldsTooLarge` for `max_header_count`).
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Read;
+/// use miasht::Status;
+/// use miasht::multipart::{Builder, Limits, Multipart};
+///
+/// let mut builder = Builder::new("xyz");
+/// builder.add_text("a", "0123456789");
+/// builder.add_file("b", "b.txt", "text/plain", b"0123456789");
+/// let body = builder.finish();
+///
+/// let read_all = |limits: Limits| -> miasht::Result<()> {
+///     let mut multipart = Multipart::with_limits(&body[..], "xyz", limits);
+///     while multipart.next_part()?.is_some() {
+///         let mut buf = Vec::new();
+///         multipart.read_to_end(&mut buf).map_err(miasht::Error::from)?;
+///     }
+///     Ok(())
+/// };
+/// assert!(read_all(Limits::default()).is_ok());
+///
+/// let check = |limits: Limits, status: Status| {
+///     assert_eq!(*read_all(limits).unwrap_err().kind(), status);
+/// };
+/// check(Limits { max_header_size: 16, ..Limits::default() }, Status::PayloadTooLarge);
+/// check(Limits { max_header_count: 1, ..Limits::default() }, Status::RequestHeaderFieldsTooLarge);
+/// check(Limits { max_total_size: 32, ..Limits::default() }, Status::PayloadTooLarge);
+/// check(Limits { max_parts: 1, ..Limits::default() }, Status::PayloadTooLarge);
+///
+/// // Errors in reading a part body are reported as `io::ErrorKind::InvalidData`
+/// let limits = Limits { max_part_size: 9, ..Limits::default() };
+/// let mut multipart = Multipart::with_limits(&body[..], "xyz", limits);
+/// multipart.next_part().unwrap();
+/// let e = multipart.read_to_end(&mut Vec::new()).unwrap_err();
+/// assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+/// assert!(multipart.next_part().is_err());
+///
+/// // A truncated body
+/// let mut multipart = Multipart::new(&body[..body.len() - 10], "xyz");
+/// assert!(multipart.next_part().unwrap().is_some());
+/// assert!(multipart.next_part().unwrap().is_some());
+/// assert_eq!(*multipart.next_part().unwrap_err().kind(), Status::BadRequest);
+///
+/// // Only a short transport padding may follow a delimiter
+/// let mut padded = b"--xyz".to_vec();
+/// padded.extend_from_slice(&[b' '; 100_000]);
+/// padded.extend_from_slice(&body[5..]);
+/// let mut multipart = Multipart::new(&padded[..], "xyz");
+/// assert_eq!(*multipart.next_part().unwrap_err().kind(), Status::BadRequest);
+///
+/// let mut padded = b"--xyz \t ".to_vec();
+/// padded.extend_from_slice(&body[5..]);
+/// let mut multipart = Multipart::new(&padded[..], "xyz");
+/// assert!(multipart.next_part().unwrap().is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Maximum byte size of the header block of a part.
+    pub max_header_size: usize,
+
+    /// Maximum number of the headers of a part.
+    pub max_header_count: usize,
+
+    /// Maximum byte size of the body of a part.
+    pub max_part_size: u64,
+
+    /// Maximum byte size of the whole multipart body.
+    pub max_total_size: u64,
+
+    /// Maximum number of parts.
+    pub max_parts: usize,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_header_size: 8 * 1024,
+            max_header_count: 16,
+            max_part_size: u64::MAX,
+            max_total_size: u64::MAX,
+            max_parts: usize::MAX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Delimiter,
+    Headers,
+    Body,
+    Finished,
+}
+
+/// Streaming `multipart/*` body parser.
+///
+/// Parts are obtained by `poll_part` (or `next_part`) method and
+/// the body of the current part can be read via `poll_read` (or `Read` trait).
+/// Each part body is streamed, so it is never buffered entirely.
+///
+/// The reader `R` is expected to reach EOF at the end of the multipart body
+/// (e.g., `Read::take` can be used to limit the reader to `Content-Length`).
+#[derive(Debug)]
+pub struct Multipart<R> {
+    reader: R,
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    eof: bool,
+    state: State,
+    limits: Limits,
+    total_size: u64,
+    part_size: u64,
+    parts: usize,
+}
+impl<R: Read> Multipart<R> {
+    /// Makes a new `Multipart` instance with the default limits.
+    pub fn new(reader: R, boundary: &str) -> Self {
+        Self::with_limits(reader, boundary, Limits::default())
+    }
+
+    /// Makes a new `Multipart` instance with the given limits.
+    pub fn with_limits(reader: R, boundary: &str, limits: Limits) -> Self {
+        // The leading CRLF of the first delimiter is optional,
+        // so we prepend it to treat all delimiters uniformly.
+        Multipart {
+            reader,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            buf: b"\r\n".to_vec(),
+            eof: false,
+            state: State::Delimiter,
+            limits,
+            total_size: 0,
+            part_size: 0,
+            parts: 0,
+        }
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Converts into the underlying reader.
+    ///
+    /// Note that the data buffered by this parser will be lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Polls the next part.
+    ///
+    /// The remaining body of the current part will be skipped.
+    /// `Async::Ready(None)` means that the last part has been consumed.
+    pub fn poll_part(&mut self) -> Poll<Option<Part>, Error> {
+        loop {
+            match self.state {
+                State::Finished => return Ok(Async::Ready(None)),
+                State::Body => {
+                    let mut buf = [0; 1024];
+                    while try_ready!(self.poll_read(&mut buf)) != 0 {}
+                }
+                State::Delimiter => {
+                    if !try_ready!(self.poll_delimiter()) {
+                        return Ok(Async::Ready(None));
+                    }
+                }
+                State::Headers => {
+                    let part = try_ready!(self.poll_headers());
+                    return Ok(Async::Ready(Some(part)));
+                }
+            }
+        }
+    }
+
+    /// Reads the next part in a blocking manner.
+    pub fn next_part(&mut self) -> ::Result<Option<Part>> {
+        loop {
+            if let Async::Ready(part) = track!(self.poll_part())? {
+                return Ok(part);
+            }
+        }
+    }
+
+    /// Reads the body of the current part.
+    ///
+    /// `Async::Ready(0)` means that the end of the part has been reached.
+    pub fn poll_read(&mut self, buf: &mut [u8]) -> Poll<usize, Error> {
+        if self.state != State::Body || buf.is_empty() {
+            return Ok(Async::Ready(0));
+        }
+        loop {
+            let available = match find(&self.buf, &self.delimiter) {
+                Some(0) => {
+                    self.state = State::Delimiter;
+                    return Ok(Async::Ready(0));
+                }
+                Some(i) => i,
+                None => self.buf.len().saturating_sub(self.delimiter.len() - 1),
+            };
+            if available > 0 {
+                let size = cmp::min(available, buf.len());
+                self.part_size += size as u64;
+                track_assert!(
+                    self.part_size <= self.limits.max_part_size,
+                    Status::PayloadTooLarge,
+                    "Too large multipart part: max_part_size={}",
+                    self.limits.max_part_size
+                );
+                buf[..size].copy_from_slice(&self.buf[..size]);
+                self.buf.drain(..size);
+                return Ok(Async::Ready(size));
+            }
+            track_assert!(
+                !self.eof,
+                Status::BadRequest,
+                "Unexpected EOF in a multipart body"
+            );
+            try_ready!(self.poll_fill());
+        }
+    }
+
+    fn poll_delimiter(&mut self) -> Poll<bool, Error> {
+        loop {
+            if let Some(i) = find(&self.buf, &self.delimiter) {
+                let rest = i + self.delimiter.len();
+                if self.buf.len() >= rest + 2 {
+                    if &self.buf[rest..rest + 2] == b"--" {
+                        self.state = State::Finished;
+                        self.buf.clear();
+                        return Ok(Async::Ready(false));
+                    }
+                    let line = &self.buf[rest..];
+                    let eol = find(line, b"\r\n");
+                    let padding = match eol {
+                        Some(eol) => &line[..eol],
+                        None if line.ends_with(b"\r") => &line[..line.len() - 1],
+                        None => line,
+                    };
+                    track_assert!(
+                        padding.len() <= MAX_DELIMITER_PADDING
+                            && padding.iter().all(|&b| b == b' ' || b == b'\t'),
+                        Status::BadRequest,
+                        "Malformed multipart delimiter"
+                    );
+                    if let Some(eol) = eol {
+                        self.buf.drain(..rest + eol + 2);
+                        self.state = State::Headers;
+                        return Ok(Async::Ready(true));
+                    }
+                }
+            } else {
+                // Discards preamble
+                let keep = cmp::min(self.buf.len(), self.delimiter.len() - 1);
+                let n = self.buf.len() - keep;
+                self.buf.drain(..n);
+            }
+            track_assert!(
+                !self.eof,
+                Status::BadRequest,
+                "Unexpected EOF in a multipart body"
+            );
+            try_ready!(self.poll_fill());
+        }
+    }
+
+    fn poll_headers(&mut self) -> Poll<Part, Error> {
+        loop {
+            let end = if self.buf.starts_with(b"\r\n") {
+                Some(2)
+            } else {
+                find(&self.buf, b"\r\n\r\n").map(|i| i + 4)
+            };
+            if let Some(end) = end {
+                track_assert!(
+                    end <= self.limits.max_header_size,
+                    Status::PayloadTooLarge,
+                    "Too large multipart headers: max_header_size={}",
+                    self.limits.max_header_size
+                );
+                self.parts += 1;
+                track_assert!(
+                    self.parts <= self.limits.max_parts,
+                    Status::PayloadTooLarge,
+                    "Too many multipart parts: max_parts={}",
+                    self.limits.max_parts
+                );
+                let raw = self.buf.drain(..end).collect();
+                let part = track!(Part::parse(raw, self.limits.max_header_count))?;
+                self.state = State::Body;
+                self.part_size = 0;
+                return Ok(Async::Ready(part));
+            }
+            track_assert!(
+                self.buf.len() <= self.limits.max_header_size,
+                Status::PayloadTooLarge,
+                "Too large multipart headers: max_header_size={}",
+                self.limits.max_header_size
+            );
+            track_assert!(
+                !self.eof,
+                Status::BadRequest,
+                "Unexpected EOF in a multipart body"
+            );
+            try_ready!(self.poll_fill());
+        }
+    }
+
+    fn poll_fill(&mut self) -> Poll<(), Error> {
+        let mut buf = [0; 4096];
+        match self.reader.read(&mut buf) {
+            Err(e) => {
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    Ok(Async::NotReady)
+                } else {
+                    Err(track!(Error::from(e)))
+                }
+            }
+            Ok(0) => {
+                self.eof = true;
+                Ok(Async::Ready(()))
+            }
+            Ok(size) => {
+                self.total_size += size as u64;
+                track_assert!(
+                    self.total_size <= self.limits.max_total_size,
+                    Status::PayloadTooLarge,
+                    "Too large multipart body: max_total_size={}",
+                    self.limits.max_total_size
+                );
+                self.buf.extend_from_slice(&buf[..size]);
+                Ok(Async::Ready(()))
+            }
+        }
+    }
+}
+impl<R: Read> Read for Multipart<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.poll_read(buf) {
+            Ok(Async::Ready(size)) => Ok(size),
+            Ok(Async::NotReady) => Err(io::Error::new(io::ErrorKind::WouldBlock, "Would block")),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// A part of a multipart body.
+///
+/// This only holds the headers of the part.
+/// The body is read via `Multipart`.
+#[derive(Debug)]
+pub struct Part {
+    headers: Vec<UnsafeHeader>,
+    _raw: Vec<u8>, // The storage referred by `headers`
+}
+impl Part {
+    fn parse(raw: Vec<u8>, max_header_count: usize) -> ::Result<Self> {
+        let mut headers = vec![httparse::EMPTY_HEADER; max_header_count];
+        let count = {
+            // The heap buffer of `raw` is never modified or moved while `Part` is alive.
+            let bytes: &'static [u8] = unsafe { &*(&raw[..] as *const _) };
+            match track!(httparse::parse_headers(bytes, &mut headers).map_err(Error::from))? {
+                httparse::Status::Complete((_, parsed)) => parsed.len(),
+                httparse::Status::Partial => {
+                    track_panic!(Status::BadRequest, "Incomplete multipart headers")
+                }
+            }
+        };
+        headers.truncate(count);
+        Ok(Part { headers, _raw: raw })
+    }
+
+    /// Returns the headers of the part.
+    pub fn headers(&self) -> Headers<'_> {
+        Headers::new(&self.headers)
+    }
+
+    /// Returns the `Content-Disposition` header of the part.
+    pub fn content_disposition(&self) -> Option<ContentDisposition<'_>> {
+        self.headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(ContentDisposition::name()))
+            .and_then(|h| ContentDisposition::parse_value_bytes(h.value).ok())
+    }
+
+    /// Returns the `name` parameter of the `Content-Disposition` header.
+    pub fn name(&self) -> Option<&str> {
+        self.content_disposition().and_then(|h| h.param("name"))
+    }
+
+    /// Returns the `filename` parameter of the `Content-Disposition` header.
+    pub fn filename(&self) -> Option<&str> {
+        self.content_disposition().and_then(|h| h.param("filename"))
+    }
+}
+
+/// `multipart/form-data` body builder.
+#[derive(Debug)]
+pub struct Builder {
+    boundary: String,
+    body: Vec<u8>,
+}
+impl Builder {
+    /// Makes a new `Builder` instance which uses `boundary` as the delimiter.
+    ///
+    /// The caller must ensure that `boundary` does not appear in the contents of the parts.
+    pub fn new(boundary: &str) -> Self {
+        Builder {
+            boundary: boundary.to_owned(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Makes a new `Builder` instance with an automatically generated boundary.
+    ///
+    /// The boundary is generated from random bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::multipart::Builder;
+    ///
+    /// let a = Builder::with_random_boundary();
+    /// let b = Builder::with_random_boundary();
+    /// assert!(a.boundary().starts_with("miasht-"));
+    /// assert_ne!(a.boundary(), b.boundary());
+    /// ```
+    pub fn with_random_boundary() -> Self {
+        let mut boundary = "miasht-".to_owned();
+        for b in &random_bytes() {
+            boundary.push_str(&format!("{:02x}", b));
+        }
+        Self::new(&boundary)
+    }
+
+    /// Returns the boundary.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Returns the value of the `Content-Type` header for the body.
+    pub fn content_type(&self) -> String {
+        format!("{}; boundary={}", FORM_DATA, self.boundary)
+    }
+
+    /// Adds a text field.
+    pub fn add_text(&mut self, name: &str, value: &str) -> &mut Self {
+        self.add_part(name, None, None, value.as_bytes())
+    }
+
+    /// Adds a file field.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        filename: &str,
+        content_type: &str,
+        content: &[u8],
+    ) -> &mut Self {
+        self.add_part(name, Some(filename), Some(content_type), content)
+    }
+
+    fn add_part(
+        &mut self,
+        name: &str,
+        filename: Option<&str>,
+        content_type: Option<&str>,
+        content: &[u8],
+    ) -> &mut Self {
+        let _ = write!(self.body, "--{}\r\n", self.boundary);
+        let _ = write!(
+            self.body,
+            "Content-Disposition: form-data; name=\"{}\"",
+            escape_quoted(name)
+        );
+        if let Some(filename) = filename {
+            let _ = write!(self.body, "; filename=\"{}\"", escape_quoted(filename));
+        }
+        let _ = write!(self.body, "\r\n");
+        if let Some(content_type) = content_type {
+            let _ = write!(self.body, "Content-Type: {}\r\n", content_type);
+        }
+        let _ = write!(self.body, "\r\n");
+        self.body.extend_from_slice(content);
+        let _ = write!(self.body, "\r\n");
+        self
+    }
+
+    /// Finishes the body and returns it.
+    ///
+    /// The `Content-Length` of the body is the length of the resulting bytes.
+    pub fn finish(mut self) -> Vec<u8> {
+        let _ = write!(self.body, "--{}--\r\n", self.boundary);
+        self.body
+    }
+}
+
+fn escape_quoted(s: &str) -> String {
+    s.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
@@ -0,0 +1,417 @@
+//! Proactive content negotiation ([RFC 7231 §5.3](https://tools.ietf.org/html/rfc7231#section-5.3)).
+//!
+//! The `negotiate_*` functions pick the best of the offered values
+//! (listed in the server's order of preference) for a request.
+//! If the request has no corresponding header (or it is malformed), the first offered value is picked.
+//! If no offered value is acceptable, they fail with `Status::NotAcceptable`.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate httparse;
+//! extern crate miasht;
+//!
+//! use miasht::Status;
+//! use miasht::header::Headers;
+//! use miasht::negotiation;
+//!
+//! # fn main() {
+//! let headers = [
+//!     httparse::Header { name: "Accept", value: b"text/*;q=0.5, application/json" },
+//!     httparse::Header { name: "Accept-Language", value: b"ja, en;q=0.8" },
+//!     httparse::Header { name: "Accept-Encoding", value: b"gzip;q=0, *" },
+//! ];
+//! let headers = Headers::new(&headers);
+//!
+//! let offered = ["text/html", "application/json"];
+//! assert_eq!(negotiation::negotiate_media_type(&headers, &offered).unwrap(), "application/json");
+//!
+//! let offered = ["en-US", "fr"];
+//! assert_eq!(negotiation::negotiate_language(&headers, &offered).unwrap(), "en-US");
+//!
+//! let offered = ["gzip", "deflate"];
+//! assert_eq!(negotiation::negotiate_encoding(&headers, &offered).unwrap(), "deflate");
+//!
+//! let offered = ["image/png"];
+//! let e = negotiation::negotiate_media_type(&headers, &offered).err().unwrap();
+//! assert_eq!(*e.kind(), Status::NotAcceptable);
+//! # }
+//! ```
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use {Error, Status};
+use header::{Header, Headers};
+
+/// A quality value (`qvalue`) in thousandths.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::negotiation::QValue;
+///
+/// assert_eq!("0.5".parse::<QValue>().unwrap(), QValue::new(500));
+/// assert_eq!("1".parse::<QValue>().unwrap().to_string(), "1");
+/// assert_eq!(QValue::new(250).to_string(), "0.25");
+/// assert!("1.5".parse::<QValue>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QValue(u16);
+impl QValue {
+    /// `q=1`
+    pub const ONE: QValue = QValue(1000);
+
+    /// `q=0` (not acceptable)
+    pub const ZERO: QValue = QValue(0);
+
+    /// Makes a new `QValue` instance.
+    ///
+    /// # Panics
+    ///
+    /// If `thousandths` is greater than `1000`, the calling thread will panic.
+    pub fn new(thousandths: u16) -> Self {
+        assert!(thousandths <= 1000, "Too large qvalue: {}", thousandths);
+        QValue(thousandths)
+    }
+
+    /// Returns the value in thousandths.
+    pub fn thousandths(&self) -> u16 {
+        self.0
+    }
+}
+impl Default for QValue {
+    fn default() -> Self {
+        QValue::ONE
+    }
+}
+impl fmt::Display for QValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 1000 {
+            write!(f, "1")
+        } else if self.0 == 0 {
+            write!(f, "0")
+        } else {
+            let s = format!("{:03}", self.0);
+            write!(f, "0.{}", s.trim_end_matches('0'))
+        }
+    }
+}
+impl FromStr for QValue {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (int, frac) = match s.find('.') {
+            None => (s, ""),
+            Some(i) => (&s[..i], &s[i + 1..]),
+        };
+        track_assert!(
+            (int == "0" || int == "1") && frac.len() <= 3 && frac.bytes().all(|b| b.is_ascii_digit()),
+            Status::BadRequest,
+            "Malformed qvalue: {:?}",
+            s
+        );
+        let mut thousandths = if int == "1" { 1000 } else { 0 };
+        for (i, b) in frac.bytes().enumerate() {
+            thousandths += u16::from(b - b'0') * [100, 10, 1][i];
+        }
+        track_assert!(thousandths <= 1000, Status::BadRequest, "Malformed qvalue: {:?}", s);
+        Ok(QValue(thousandths))
+    }
+}
+
+/// An element of `Accept-Charset`, `Accept-Encoding` and `Accept-Language` headers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Preference {
+    /// A charset, a content-coding or a language range (`"*"` means any value).
+    pub value: String,
+    pub quality: QValue,
+}
+impl fmt::Display for Preference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)?;
+        if self.quality != QValue::ONE {
+            write!(f, ";q={}", self.quality)?;
+        }
+        Ok(())
+    }
+}
+impl FromStr for Preference {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut items = s.split(';').map(|t| t.trim());
+        let value = items.next().unwrap_or("");
+        track_assert!(::auth::is_token(value), Status::BadRequest, "Malformed preference: {:?}", s);
+        let mut quality = QValue::ONE;
+        for param in items {
+            if let Some(q) = param.strip_prefix("q=").or_else(|| param.strip_prefix("Q=")) {
+                quality = track!(q.parse())?;
+            }
+        }
+        Ok(Preference {
+            value: value.to_owned(),
+            quality,
+        })
+    }
+}
+
+/// An element of `Accept` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MediaRange {
+    /// The type (e.g., `"text"` or `"*"`).
+    pub type_: String,
+
+    /// The subtype (e.g., `"html"` or `"*"`).
+    pub subtype: String,
+
+    /// The media type parameters (e.g., `charset`) in lower-case names.
+    pub params: Vec<(String, String)>,
+    pub quality: QValue,
+}
+impl MediaRange {
+    // Returns the precedence (RFC 7231 §5.3.2) if this range matches `media_type`.
+    fn precedence(&self, media_type: &str) -> Option<usize> {
+        let offered = media_type.parse::<MediaRange>().ok()?;
+        if self.type_ == "*" {
+            return Some(0);
+        }
+        if !self.type_.eq_ignore_ascii_case(&offered.type_) {
+            return None;
+        }
+        if self.subtype == "*" {
+            return Some(1);
+        }
+        if !self.subtype.eq_ignore_ascii_case(&offered.subtype) {
+            return None;
+        }
+        let params_match = self.params.iter().all(|(name, value)| {
+            offered
+                .params
+                .iter()
+                .any(|(n, v)| n == name && v.eq_ignore_ascii_case(value))
+        });
+        if params_match {
+            Some(2 + self.params.len())
+        } else {
+            None
+        }
+    }
+}
+impl fmt::Display for MediaRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.type_, self.subtype)?;
+        for (name, value) in &self.params {
+            if ::auth::is_token(value) {
+                write!(f, ";{}={}", name, value)?;
+            } else {
+                write!(f, ";{}={}", name, ::auth::quote(value))?;
+            }
+        }
+        if self.quality != QValue::ONE {
+            write!(f, ";q={}", self.quality)?;
+        }
+        Ok(())
+    }
+}
+impl FromStr for MediaRange {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut items = s.split(';').map(|t| t.trim());
+        let media_type = items.next().unwrap_or("");
+        let slash = track_assert_some!(media_type.find('/'), Status::BadRequest, "Malformed media range: {:?}", s);
+        let type_ = &media_type[..slash];
+        let subtype = &media_type[slash + 1..];
+        track_assert!(
+            ::auth::is_token(type_) && ::auth::is_token(subtype) && (type_ != "*" || subtype == "*"),
+            Status::BadRequest,
+            "Malformed media range: {:?}",
+            s
+        );
+        let mut params = Vec::new();
+        let mut quality = QValue::ONE;
+        for param in items.filter(|p| !p.is_empty()) {
+            let eq = track_assert_some!(param.find('='), Status::BadRequest, "Malformed parameter: {:?}", s);
+            let name = param[..eq].trim().to_ascii_lowercase();
+            let value = param[eq + 1..].trim();
+            if name == "q" {
+                // Parameters after the weight are accept extensions which are ignored.
+                quality = track!(value.parse())?;
+                break;
+            }
+            let value = if value.starts_with('"') {
+                track!(::auth::unquote(value))?
+            } else {
+                value.to_owned()
+            };
+            params.push((name, value));
+        }
+        Ok(MediaRange {
+            type_: type_.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            params,
+            quality,
+        })
+    }
+}
+
+fn parse_list<T: FromStr<Err = Error>>(value: &str) -> ::Result<Vec<T>> {
+    let mut list = Vec::new();
+    for item in ::auth::split_list(value) {
+        list.push(track!(item.parse())?);
+    }
+    Ok(list)
+}
+
+fn write_list<W: Write, T: fmt::Display>(writer: &mut W, list: &[T]) -> io::Result<()> {
+    for (i, item) in list.iter().enumerate() {
+        if i != 0 {
+            write!(writer, ", ")?;
+        }
+        write!(writer, "{}", item)?;
+    }
+    Ok(())
+}
+
+macro_rules! impl_header {
+    ($header:ident, $name:expr) => {
+        impl<'a> Header<'a> for $header {
+            type Error = Error;
+            fn name() -> &'static str {
+                $name
+            }
+            fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                write_list(writer, &self.0)
+            }
+            fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+                track!(parse_list(value)).map($header)
+            }
+        }
+    };
+}
+
+/// `Accept` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Accept(pub Vec<MediaRange>);
+impl_header!(Accept, "Accept");
+impl Accept {
+    /// Returns the quality of `media_type` (e.g., `"text/html; charset=utf-8"`).
+    ///
+    /// The most specific matching media range is used.
+    pub fn quality(&self, media_type: &str) -> QValue {
+        self.0
+            .iter()
+            .filter_map(|r| r.precedence(media_type).map(|p| (p, r.quality)))
+            .max_by_key(|&(p, _)| p)
+            .map_or(QValue::ZERO, |(_, q)| q)
+    }
+}
+
+/// `Accept-Charset` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AcceptCharset(pub Vec<Preference>);
+impl_header!(AcceptCharset, "Accept-Charset");
+impl AcceptCharset {
+    /// Returns the quality of `charset`.
+    pub fn quality(&self, charset: &str) -> QValue {
+        exact_quality(&self.0, charset).unwrap_or(QValue::ZERO)
+    }
+}
+
+/// `Accept-Encoding` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AcceptEncoding(pub Vec<Preference>);
+impl_header!(AcceptEncoding, "Accept-Encoding");
+impl AcceptEncoding {
+    /// Returns the quality of `coding`.
+    ///
+    /// `identity` is acceptable unless it is excluded explicitly (RFC 7231 §5.3.4).
+    pub fn quality(&self, coding: &str) -> QValue {
+        match exact_quality(&self.0, coding) {
+            Some(q) => q,
+            None if coding.eq_ignore_ascii_case("identity") => QValue::ONE,
+            None => QValue::ZERO,
+        }
+    }
+}
+
+/// `Accept-Language` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AcceptLanguage(pub Vec<Preference>);
+impl_header!(AcceptLanguage, "Accept-Language");
+impl AcceptLanguage {
+    /// Returns the quality of `language` (e.g., `"en-US"`).
+    ///
+    /// The longest matching language range is used
+    /// (i.e., the "Basic Filtering" scheme of [RFC 4647](https://tools.ietf.org/html/rfc4647#section-3.3.1)).
+    pub fn quality(&self, language: &str) -> QValue {
+        self.0
+            .iter()
+            .filter(|p| {
+                let range = &p.value;
+                range == "*" || language.eq_ignore_ascii_case(range)
+                    || (language.len() > range.len() && language.as_bytes()[range.len()] == b'-'
+                        && language[..range.len()].eq_ignore_ascii_case(range))
+            })
+            .max_by_key(|p| if p.value == "*" { 0 } else { p.value.len() })
+            .map_or(QValue::ZERO, |p| p.quality)
+    }
+}
+
+fn exact_quality(preferences: &[Preference], value: &str) -> Option<QValue> {
+    preferences
+        .iter()
+        .find(|p| p.value.eq_ignore_ascii_case(value))
+        .or_else(|| preferences.iter().find(|p| p.value == "*"))
+        .map(|p| p.quality)
+}
+
+fn negotiate<'a, F>(offered: &[&'a str], kind: &str, quality: F) -> ::Result<&'a str>
+where
+    F: Fn(&str) -> QValue,
+{
+    let mut best: Option<(&'a str, QValue)> = None;
+    for &value in offered {
+        let q = quality(value);
+        if q > QValue::ZERO && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((value, q));
+        }
+    }
+    let (value, _) = track_assert_some!(best, Status::NotAcceptable, "No acceptable {}: offered={:?}", kind, offered);
+    Ok(value)
+}
+
+fn negotiate_with<'a, 'b, H, F>(headers: &'b Headers, offered: &[&'a str], kind: &str, quality: F) -> ::Result<&'a str>
+where
+    H: Header<'b>,
+    F: Fn(&H, &str) -> QValue,
+{
+    match headers.parse::<H>() {
+        Ok(Some(header)) => track!(negotiate(offered, kind, |v| quality(&header, v))),
+        _ => {
+            let first = offered.first().cloned();
+            let first = track_assert_some!(first, Status::NotAcceptable, "No {} is offered", kind);
+            Ok(first)
+        }
+    }
+}
+
+/// Picks the best media type of `offered` for the `Accept` header of a request.
+pub fn negotiate_media_type<'a>(headers: &Headers, offered: &[&'a str]) -> ::Result<&'a str> {
+    track!(negotiate_with(headers, offered, "media type", Accept::quality))
+}
+
+/// Picks the best charset of `offered` for the `Accept-Charset` header of a request.
+pub fn negotiate_charset<'a>(headers: &Headers, offered: &[&'a str]) -> ::Result<&'a str> {
+    track!(negotiate_with(headers, offered, "charset", AcceptCharset::quality))
+}
+
+/// Picks the best content-coding of `offered` for the `Accept-Encoding` header of a request.
+///
+/// Note that `identity` should usually be included in `offered`.
+pub fn negotiate_encoding<'a>(headers: &Headers, offered: &[&'a str]) -> ::Result<&'a str> {
+    track!(negotiate_with(headers, offered, "content-coding", AcceptEncoding::quality))
+}
+
+/// Picks the best language tag of `offered` for the `Accept-Language` header of a request.
+pub fn negotiate_language<'a>(headers: &Headers, offered: &[&'a str]) -> ::Result<&'a str> {
+    track!(negotiate_with(headers, offered, "language", AcceptLanguage::quality))
+}
@@ -0,0 +1,347 @@
+//! Client connection pool.
+//!
+//! `Pool` keeps idle `client::Connection`s per origin (scheme, host and port).
+//! It does not open connections by itself. Instead, a client asks it for a connection
+//! before sending a request:
+//!
+//! 1. `Pool::checkout` returns an idle connection, or permits opening a new one
+//!    if the limits are not exceeded.
+//! 2. After the response body has been read to the end,
+//!    the connection is returned by `Pool::release`
+//!    (`is_reusable` tells whether the connection can be kept).
+//!    If the request failed, `Pool::discard` should be called instead.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::io::{self, Read, Write};
+//! use std::time::{Duration, UNIX_EPOCH};
+//! use miasht::TransportStream;
+//! use miasht::cache::ManualClock;
+//! use miasht::client::Connection;
+//! use miasht::pool::{Checkout, Key, Pool};
+//!
+//! #[derive(Debug)]
+//! struct Stream;
+//! impl Read for Stream {
+//!     fn read(&mut self, _: &mut [u8]) -> io::Result<usize> { Ok(0) }
+//! }
+//! impl Write for Stream {
+//!     fn write(&mut self, buf: &[u8]) -> io::Result<usize> { Ok(buf.len()) }
+//!     fn flush(&mut self) -> io::Result<()> { Ok(()) }
+//! }
+//! impl TransportStream for Stream {}
+//!
+//! let clock = ManualClock::new(UNIX_EPOCH);
+//! let mut pool = Pool::with_clock(clock.clone());
+//! pool.set_max_per_host(1);
+//! let key = Key::new("http", "example.com", 80);
+//!
+//! assert!(pool.checkout(&key).is_connect());
+//! assert!(pool.checkout(&key).is_full());
+//!
+//! pool.release(&key, Connection::new(Stream, 1024, 8192, 32), true);
+//! assert_eq!(pool.idle_count(&key), 1);
+//! assert!(pool.checkout(&key).is_reuse());
+//! pool.release(&key, Connection::new(Stream, 1024, 8192, 32), true);
+//!
+//! // Idle connections are evicted after the timeout.
+//! clock.advance(Pool::<Stream>::DEFAULT_IDLE_TIMEOUT);
+//! assert_eq!(pool.evict_idle(), 1);
+//! assert!(pool.checkout(&key).is_connect());
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use {TransportStream, Version};
+use cache::{Clock, SystemClock};
+use client;
+use header::Headers;
+use upgrade::has_token;
+
+/// The key of a pool, i.e., the origin of the requests.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub scheme: String,
+    pub host: String,
+    pub port: u16,
+}
+impl Key {
+    /// Makes a new `Key` instance.
+    ///
+    /// The scheme and the host are converted to lowercase.
+    pub fn new(scheme: &str, host: &str, port: u16) -> Self {
+        Key {
+            scheme: scheme.to_ascii_lowercase(),
+            host: host.to_ascii_lowercase(),
+            port,
+        }
+    }
+}
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "{}://[{}]:{}", self.scheme, self.host, self.port)
+        } else {
+            write!(f, "{}://{}:{}", self.scheme, self.host, self.port)
+        }
+    }
+}
+
+/// The result of `Pool::checkout`.
+#[derive(Debug)]
+pub enum Checkout<T> {
+    /// An idle connection is reused.
+    Reuse(client::Connection<T>),
+
+    /// A new connection should be opened by the caller.
+    Connect,
+
+    /// The limits of the pool are reached.
+    Full,
+}
+impl<T> Checkout<T> {
+    /// Returns `true` if this is `Checkout::Reuse`, otherwise `false`.
+    pub fn is_reuse(&self) -> bool {
+        matches!(*self, Checkout::Reuse(_))
+    }
+
+    /// Returns `true` if this is `Checkout::Connect`, otherwise `false`.
+    pub fn is_connect(&self) -> bool {
+        matches!(*self, Checkout::Connect)
+    }
+
+    /// Returns `true` if this is `Checkout::Full`, otherwise `false`.
+    pub fn is_full(&self) -> bool {
+        matches!(*self, Checkout::Full)
+    }
+}
+
+/// Returns `true` if the connection persists after the message
+/// which has `version` and `headers`, otherwise `false`.
+///
+/// HTTP/1.1 connections persist unless `Connection: close` is specified.
+/// HTTP/1.0 connections persist only if `Connection: keep-alive` is specified.
+pub fn is_persistent(version: Version, headers: &Headers) -> bool {
+    match version {
+        Version::Http1_1 => !has_token(headers, "Connection", "close"),
+        Version::Http1_0 => has_token(headers, "Connection", "keep-alive"),
+    }
+}
+
+/// Returns `true` if the connection of `response` can be reused
+/// after the body has been read to the end, otherwise `false`.
+///
+/// The connection must be persistent (see `is_persistent`) and
+/// the end of the body must be determined without closing the connection.
+pub fn is_reusable<T>(response: &client::Response<T>) -> bool {
+    if !is_persistent(response.version(), response.headers()) {
+        return false;
+    }
+    !response.request_method().has_response_body(response.status().code())
+        || response.headers().get("Transfer-Encoding").is_some()
+        || response.headers().get("Content-Length").is_some()
+}
+
+#[derive(Debug)]
+struct Idle<T> {
+    connection: client::Connection<T>,
+    since: SystemTime,
+}
+
+/// A pool of client connections.
+///
+/// The number of connections (both checked out and idle) is limited per key and in total.
+/// Idle connections are evicted after the idle timeout.
+#[derive(Debug)]
+pub struct Pool<T, C = SystemClock> {
+    clock: C,
+    idle: HashMap<Key, Vec<Idle<T>>>,
+    active: HashMap<Key, usize>,
+    idle_total: usize,
+    active_total: usize,
+    max_per_host: usize,
+    max_total: usize,
+    idle_timeout: Duration,
+}
+impl<T: TransportStream> Pool<T, SystemClock> {
+    /// Makes a new `Pool` instance which uses the system clock.
+    pub fn new() -> Self {
+        Pool::with_clock(SystemClock)
+    }
+}
+impl<T: TransportStream> Default for Pool<T, SystemClock> {
+    fn default() -> Self {
+        Pool::new()
+    }
+}
+impl<T: TransportStream, C: Clock> Pool<T, C> {
+    /// The default value of the maximum number of connections per key.
+    pub const DEFAULT_MAX_PER_HOST: usize = 8;
+
+    /// The default value of the maximum number of connections in total.
+    pub const DEFAULT_MAX_TOTAL: usize = 64;
+
+    /// The default value of the idle timeout.
+    pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+    /// Makes a new `Pool` instance which uses `clock`.
+    pub fn with_clock(clock: C) -> Self {
+        Pool {
+            clock,
+            idle: HashMap::new(),
+            active: HashMap::new(),
+            idle_total: 0,
+            active_total: 0,
+            max_per_host: Self::DEFAULT_MAX_PER_HOST,
+            max_total: Self::DEFAULT_MAX_TOTAL,
+            idle_timeout: Self::DEFAULT_IDLE_TIMEOUT,
+        }
+    }
+
+    /// Sets the maximum number of connections per key.
+    pub fn set_max_per_host(&mut self, max: usize) {
+        self.max_per_host = max;
+    }
+
+    /// Sets the maximum number of connections in total.
+    pub fn set_max_total(&mut self, max: usize) {
+        self.max_total = max;
+    }
+
+    /// Sets the duration after which idle connections are evicted.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Returns the number of idle connections for `key`.
+    pub fn idle_count(&self, key: &Key) -> usize {
+        self.idle.get(key).map_or(0, |v| v.len())
+    }
+
+    /// Returns the number of checked out connections for `key`.
+    pub fn active_count(&self, key: &Key) -> usize {
+        self.active.get(key).cloned().unwrap_or(0)
+    }
+
+    /// Returns the number of connections (both checked out and idle) in total.
+    pub fn len(&self) -> usize {
+        self.idle_total + self.active_total
+    }
+
+    /// Returns `true` if the pool has no connection, otherwise `false`.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks out a connection for `key`.
+    ///
+    /// The most recently used idle connection is preferred.
+    /// If there is no idle connection and the limits are not reached, `Checkout::Connect` is returned.
+    /// When the total limit is reached, the oldest idle connection of another key is evicted
+    /// to make room if possible.
+    ///
+    /// The result counts as a checked out connection unless it is `Checkout::Full`.
+    pub fn checkout(&mut self, key: &Key) -> Checkout<T> {
+        self.evict_idle();
+        let reused = self.idle.get_mut(key).and_then(|v| v.pop());
+        if let Some(idle) = reused {
+            self.idle_total -= 1;
+            self.activate(key);
+            return Checkout::Reuse(idle.connection);
+        }
+
+        if self.active_count(key) + self.idle_count(key) >= self.max_per_host {
+            return Checkout::Full;
+        }
+        if self.len() >= self.max_total && !self.evict_oldest() {
+            return Checkout::Full;
+        }
+        self.activate(key);
+        Checkout::Connect
+    }
+
+    /// Returns a checked out connection for `key`.
+    ///
+    /// If `reusable` is `false` (see `is_reusable`), the connection is dropped.
+    /// The connection should be returned after the response body has been read to the end.
+    pub fn release(&mut self, key: &Key, connection: client::Connection<T>, reusable: bool) {
+        self.deactivate(key);
+        if !reusable || self.idle_timeout == Duration::from_secs(0) {
+            return;
+        }
+        if self.idle_count(key) + self.active_count(key) >= self.max_per_host || self.len() >= self.max_total {
+            return;
+        }
+        let since = self.clock.now();
+        self.idle.entry(key.clone()).or_default().push(Idle { connection, since });
+        self.idle_total += 1;
+    }
+
+    /// Notifies that a checked out connection for `key` will not be returned
+    /// (e.g., it failed or the caller could not open it).
+    pub fn discard(&mut self, key: &Key) {
+        self.deactivate(key);
+    }
+
+    /// Evicts the idle connections which have exceeded the idle timeout.
+    ///
+    /// Returns the number of evicted connections.
+    pub fn evict_idle(&mut self) -> usize {
+        let now = self.clock.now();
+        let timeout = self.idle_timeout;
+        let mut evicted = 0;
+        for connections in self.idle.values_mut() {
+            let before = connections.len();
+            connections.retain(|c| now.duration_since(c.since).unwrap_or_default() < timeout);
+            evicted += before - connections.len();
+        }
+        self.idle.retain(|_, v| !v.is_empty());
+        self.idle_total -= evicted;
+        evicted
+    }
+
+    /// Drops all idle connections.
+    pub fn clear(&mut self) {
+        self.idle.clear();
+        self.idle_total = 0;
+    }
+
+    fn activate(&mut self, key: &Key) {
+        *self.active.entry(key.clone()).or_insert(0) += 1;
+        self.active_total += 1;
+    }
+
+    fn deactivate(&mut self, key: &Key) {
+        let remove = if let Some(n) = self.active.get_mut(key) {
+            *n -= 1;
+            self.active_total -= 1;
+            *n == 0
+        } else {
+            false
+        };
+        if remove {
+            self.active.remove(key);
+        }
+    }
+
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = self.idle
+            .iter()
+            .filter_map(|(k, v)| v.first().map(|c| (k.clone(), c.since)))
+            .min_by_key(|x| x.1)
+            .map(|x| x.0);
+        if let Some(key) = oldest {
+            let connections = self.idle.get_mut(&key).expect("Never fails");
+            connections.remove(0);
+            if connections.is_empty() {
+                self.idle.remove(&key);
+            }
+            self.idle_total -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,630 @@
+//! Reverse proxy.
+//!
+//! `ReverseProxy` forwards a server request to an upstream server through a `client::Connection`
+//! and relays the upstream response back.
+//! The bodies are streamed in both directions (they are never buffered as a whole).
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # extern crate futures;
+//! # extern crate miasht;
+//! use futures::Future;
+//! use miasht::TransportStream;
+//! use miasht::client;
+//! use miasht::proxy::ReverseProxy;
+//! use miasht::server::Request;
+//!
+//! fn relay<T, U>(request: Request<T>, upstream: client::Connection<U>)
+//! where
+//!     T: TransportStream,
+//!     U: TransportStream,
+//! {
+//!     let proxy = ReverseProxy::new("backend.local:8080").strip_prefix("/api");
+//!     let client_addr = "192.0.2.43".parse().ok();
+//!     let (_downstream, _upstream) = proxy.forward(request, upstream, client_addr).wait().unwrap();
+//! }
+//! # fn main() {}
+//! ```
+use std::io::{self, BufRead, Read, Write};
+use std::mem;
+use std::net::IpAddr;
+use std::str;
+use futures::{Async, Future, Poll};
+use trackable::error::ErrorKindExt;
+
+use {Error, Method, Status, TransportStream, Version};
+use auth::{is_token, quote};
+use chunked::{ChunkedDecoder, ChunkedEncoder};
+use client;
+use header::{ContentLength, Headers};
+use server;
+use status::RawStatus;
+use upgrade::{has_token, list};
+
+/// The hop-by-hop headers which are never forwarded.
+///
+/// In addition, the headers listed in the `Connection` header are not forwarded.
+pub const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Returns `true` if the header `name` in `headers` is a hop-by-hop header, otherwise `false`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate httparse;
+/// # extern crate miasht;
+/// use miasht::header::Headers;
+/// use miasht::proxy::is_hop_by_hop;
+///
+/// # fn main() {
+/// let fields = [httparse::Header { name: "Connection", value: b"close, X-Secret" }];
+/// let headers = Headers::new(&fields);
+/// assert!(is_hop_by_hop(&headers, "keep-alive"));
+/// assert!(is_hop_by_hop(&headers, "x-secret"));
+/// assert!(!is_hop_by_hop(&headers, "Content-Type"));
+/// # }
+/// ```
+pub fn is_hop_by_hop(headers: &Headers, name: &str) -> bool {
+    HOP_BY_HOP_HEADERS
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(name)) || has_token(headers, "Connection", name)
+}
+
+/// The default size of the buffer used to relay bodies.
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// A reverse proxy.
+///
+/// The request forwarded to the upstream server is modified as follows:
+///
+/// - The request target is rewritten by `rewrite_target`
+/// - The `Host` header is replaced with the upstream host
+/// - Hop-by-hop headers are removed
+/// - The `Forwarded` and `X-Forwarded-For` headers are appended (or added)
+/// - The `X-Forwarded-Host` and `X-Forwarded-Proto` headers are added if absent
+///
+/// Hop-by-hop headers are also removed from the relayed response.
+/// The chunked transfer coding is decoded and encoded again on each hop,
+/// and the other transfer codings are forwarded as they are.
+///
+/// Protocol upgrades are not supported: the `Upgrade` header is not forwarded,
+/// and a `101 Switching Protocols` response is answered with `502 Bad Gateway`.
+#[derive(Debug, Clone)]
+pub struct ReverseProxy {
+    host: String,
+    strip_prefix: String,
+    add_prefix: String,
+    proto: String,
+    buffer_size: usize,
+}
+impl ReverseProxy {
+    /// Makes a new `ReverseProxy` instance which forwards requests to `upstream_host`.
+    ///
+    /// `upstream_host` is used as the value of the `Host` header of forwarded requests.
+    pub fn new(upstream_host: &str) -> Self {
+        ReverseProxy {
+            host: upstream_host.to_owned(),
+            strip_prefix: String::new(),
+            add_prefix: String::new(),
+            proto: "http".to_owned(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+
+    /// Sets the path prefix removed from request targets.
+    pub fn strip_prefix(mut self, prefix: &str) -> Self {
+        self.strip_prefix = prefix.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Sets the path prefix prepended to request targets (after `strip_prefix` is applied).
+    pub fn add_prefix(mut self, prefix: &str) -> Self {
+        self.add_prefix = prefix.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Sets the protocol reported by the `Forwarded` and `X-Forwarded-Proto` headers.
+    ///
+    /// The default value is `"http"`.
+    pub fn proto(mut self, proto: &str) -> Self {
+        self.proto = proto.to_owned();
+        self
+    }
+
+    /// Sets the size of the buffer used to relay bodies.
+    ///
+    /// The default value is `DEFAULT_BUFFER_SIZE`.
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size.max(1);
+        self
+    }
+
+    /// Rewrites the request target `target` for the upstream server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::proxy::ReverseProxy;
+    ///
+    /// let proxy = ReverseProxy::new("backend").strip_prefix("/api").add_prefix("/v1");
+    /// assert_eq!(proxy.rewrite_target("/api/users?id=3"), "/v1/users?id=3");
+    /// assert_eq!(proxy.rewrite_target("/api"), "/v1/");
+    /// assert_eq!(proxy.rewrite_target("/apix"), "/v1/apix");
+    /// ```
+    pub fn rewrite_target(&self, target: &str) -> String {
+        let mut rest = target;
+        if !self.strip_prefix.is_empty() && target.starts_with(&self.strip_prefix) {
+            let tail = &target[self.strip_prefix.len()..];
+            if tail.is_empty() || tail.starts_with('/') || tail.starts_with('?') {
+                rest = tail;
+            }
+        }
+        if rest.starts_with('/') {
+            format!("{}{}", self.add_prefix, rest)
+        } else {
+            format!("{}/{}", self.add_prefix, rest)
+        }
+    }
+
+    /// Forwards `request` to the upstream server connected by `upstream`.
+    ///
+    /// `client_addr` is the address of the downstream client
+    /// which is reported by the `Forwarded` and `X-Forwarded-For` headers.
+    ///
+    /// The resulting future yields the downstream and upstream connections
+    /// after the response has been relayed.
+    /// If the upstream server fails before the response is relayed,
+    /// a `502 Bad Gateway` (or `504 Gateway Timeout` if the upstream timed out) response
+    /// with `Connection: close` is sent and the future fails with the error of the same kind.
+    pub fn forward<T, U>(
+        &self,
+        request: server::Request<T>,
+        upstream: client::Connection<U>,
+        client_addr: Option<IpAddr>,
+    ) -> Forward<T, U>
+    where
+        T: TransportStream,
+        U: TransportStream,
+    {
+        let version = request.version();
+        let method = request.method();
+        let framing = match request_framing(request.headers()) {
+            Ok(framing) => framing,
+            Err(e) => {
+                let response = error_response(request.finish(), *e.kind());
+                return Forward::new(State::Fail(response, e), version, method, 0);
+            }
+        };
+
+        let target = self.rewrite_target(request.path());
+        let mut builder = upstream.build_request(method, &target);
+        {
+            let headers = request.headers();
+            let mut forwarded = Vec::new();
+            let mut forwarded_for = Vec::new();
+            let mut has_forwarded_host = false;
+            let mut has_forwarded_proto = false;
+            for (name, value) in headers.iter() {
+                if is_hop_by_hop(headers, name) || name.eq_ignore_ascii_case("Host") {
+                    continue;
+                }
+                if name.eq_ignore_ascii_case("Content-Length") && framing == Framing::Chunked {
+                    continue;
+                }
+                if name.eq_ignore_ascii_case("Forwarded") {
+                    forwarded.push(String::from_utf8_lossy(value).into_owned());
+                    continue;
+                }
+                if name.eq_ignore_ascii_case("X-Forwarded-For") {
+                    forwarded_for.push(String::from_utf8_lossy(value).into_owned());
+                    continue;
+                }
+                has_forwarded_host |= name.eq_ignore_ascii_case("X-Forwarded-Host");
+                has_forwarded_proto |= name.eq_ignore_ascii_case("X-Forwarded-Proto");
+                builder.add_raw_header(name, value);
+            }
+            builder.add_raw_header("Host", self.host.as_bytes());
+
+            let host = headers.get("Host").and_then(|v| str::from_utf8(v).ok());
+            let mut element = Vec::new();
+            if let Some(addr) = client_addr {
+                let node = match addr {
+                    IpAddr::V4(a) => a.to_string(),
+                    IpAddr::V6(a) => quote(&format!("[{}]", a)),
+                };
+                element.push(format!("for={}", node));
+                forwarded_for.push(addr.to_string());
+            }
+            if let Some(host) = host {
+                let host = if is_token(host) { host.to_owned() } else { quote(host) };
+                element.push(format!("host={}", host));
+            }
+            element.push(format!("proto={}", self.proto));
+            forwarded.push(element.join(";"));
+            builder.add_raw_header("Forwarded", forwarded.join(", ").as_bytes());
+            if !forwarded_for.is_empty() {
+                builder.add_raw_header("X-Forwarded-For", forwarded_for.join(", ").as_bytes());
+            }
+            if let (Some(host), false) = (host, has_forwarded_host) {
+                builder.add_raw_header("X-Forwarded-Host", host.as_bytes());
+            }
+            if !has_forwarded_proto {
+                builder.add_raw_header("X-Forwarded-Proto", self.proto.as_bytes());
+            }
+            if framing == Framing::Chunked {
+                let codings = list(headers, "Transfer-Encoding").join(", ");
+                builder.add_raw_header("Transfer-Encoding", codings.as_bytes());
+            }
+        }
+
+        let reader = BodyReader::new(request, framing);
+        let writer = BodyWriter::new(builder.finish(), framing == Framing::Chunked);
+        Forward::new(State::Request(reader, writer), version, method, self.buffer_size)
+    }
+}
+
+/// A future which forwards a request to an upstream server and relays the response.
+///
+/// This is created by calling `ReverseProxy::forward` method.
+#[derive(Debug)]
+pub struct Forward<T, U> {
+    state: State<T, U>,
+    version: Version,
+    method: Method,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    eof: bool,
+}
+impl<T, U> Forward<T, U> {
+    fn new(state: State<T, U>, version: Version, method: Method, buffer_size: usize) -> Self {
+        Forward {
+            state,
+            version,
+            method,
+            buf: vec![0; buffer_size],
+            pos: 0,
+            len: 0,
+            eof: false,
+        }
+    }
+}
+impl<T: TransportStream, U: TransportStream> Forward<T, U> {
+    fn copy<R: Read, W: Write>(&mut self, reader: &mut R, writer: &mut BodyWriter<W>) -> Poll<(), Copy> {
+        loop {
+            if self.pos < self.len {
+                match writer.write(&self.buf[self.pos..self.len]) {
+                    Ok(0) => return Err(Copy::Write(io::ErrorKind::WriteZero.into())),
+                    Ok(n) => self.pos += n,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                    Err(e) => return Err(Copy::Write(e)),
+                }
+            } else if self.eof {
+                return match writer.try_finish() {
+                    Ok(()) => {
+                        self.pos = 0;
+                        self.len = 0;
+                        self.eof = false;
+                        Ok(Async::Ready(()))
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+                    Err(e) => Err(Copy::Write(e)),
+                };
+            } else {
+                match reader.read(&mut self.buf) {
+                    Ok(0) => self.eof = true,
+                    Ok(n) => {
+                        self.pos = 0;
+                        self.len = n;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                    Err(e) => return Err(Copy::Read(e)),
+                }
+            }
+        }
+    }
+
+    fn relay(&self, downstream: server::Connection<T>, response: client::Response<U>) -> State<T, U> {
+        let code = response.status().code();
+        if code == 101 {
+            let e = track!(Error::from(Status::BadGateway.cause("Protocol upgrades are not supported")));
+            return State::Fail(error_response(downstream, *e.kind()), e);
+        }
+        let framing = if !self.method.has_response_body(code) {
+            Framing::Length(0)
+        } else {
+            match response_framing(response.headers()) {
+                Ok(framing) => framing,
+                Err(e) => return State::Fail(error_response(downstream, *e.kind()), e),
+            }
+        };
+        let chunked = framing == Framing::Chunked || (framing == Framing::Eof && self.version == Version::Http1_1);
+        let mut codings = if framing == Framing::Length(0) {
+            Vec::new()
+        } else {
+            list(response.headers(), "Transfer-Encoding")
+        };
+        if framing == Framing::Chunked {
+            codings.pop();
+        }
+        if !codings.is_empty() && !chunked {
+            let e = track!(Error::from(Status::BadGateway.cause(format!(
+                "Cannot relay the transfer codings {:?} to an HTTP/1.0 client",
+                codings
+            ))));
+            return State::Fail(error_response(downstream, *e.kind()), e);
+        }
+        codings.push("chunked");
+        let codings = codings.join(", ");
+
+        let mut builder =
+            downstream.build_response(RawStatus::new(code, response.status().reason()));
+        {
+            let headers = response.headers();
+            for (name, value) in headers.iter() {
+                if is_hop_by_hop(headers, name) {
+                    continue;
+                }
+                if name.eq_ignore_ascii_case("Content-Length") && chunked {
+                    continue;
+                }
+                builder.add_raw_header(name, value);
+            }
+            if chunked {
+                builder.add_raw_header("Transfer-Encoding", codings.as_bytes());
+            } else if framing == Framing::Eof {
+                builder.add_raw_header("Connection", b"close");
+            }
+        }
+        let reader = BodyReader::new(response, framing);
+        let writer = BodyWriter::new(builder.finish(), chunked);
+        State::Response(reader, writer)
+    }
+}
+impl<T: TransportStream, U: TransportStream> Future for Forward<T, U> {
+    type Item = (server::Connection<T>, client::Connection<U>);
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match mem::replace(&mut self.state, State::Done) {
+                State::Request(mut reader, mut writer) => match self.copy(&mut reader, &mut writer) {
+                    Ok(Async::NotReady) => {
+                        self.state = State::Request(reader, writer);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(())) => {
+                        State::SendRequest(reader.into_inner().finish(), writer.into_inner())
+                    }
+                    Err(Copy::Read(e)) => return Err(track!(Error::from(e))),
+                    Err(Copy::Write(e)) => {
+                        let e = upstream_error(track!(Error::from(e)));
+                        let response = error_response(reader.into_inner().finish(), *e.kind());
+                        State::Fail(response, e)
+                    }
+                },
+                State::SendRequest(downstream, mut request) => match track!(request.poll()) {
+                    Ok(Async::NotReady) => {
+                        self.state = State::SendRequest(downstream, request);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(upstream)) => State::ReadResponse(downstream, upstream.read_response()),
+                    Err(e) => {
+                        let e = upstream_error(e);
+                        State::Fail(error_response(downstream, *e.kind()), e)
+                    }
+                },
+                State::ReadResponse(downstream, mut future) => match track!(future.poll()) {
+                    Ok(Async::NotReady) => {
+                        self.state = State::ReadResponse(downstream, future);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(response)) => {
+                        let code = response.status().code();
+                        if code / 100 == 1 && code != 101 {
+                            // Interim responses are not relayed.
+                            State::ReadResponse(downstream, response.finish().read_response())
+                        } else {
+                            self.relay(downstream, response)
+                        }
+                    }
+                    Err(e) => {
+                        let e = upstream_error(e);
+                        State::Fail(error_response(downstream, *e.kind()), e)
+                    }
+                },
+                State::Response(mut reader, mut writer) => match self.copy(&mut reader, &mut writer) {
+                    Ok(Async::NotReady) => {
+                        self.state = State::Response(reader, writer);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(())) => {
+                        State::FlushResponse(writer.into_inner(), reader.into_inner().finish())
+                    }
+                    Err(Copy::Read(e)) => return Err(upstream_error(track!(Error::from(e)))),
+                    Err(Copy::Write(e)) => return Err(track!(Error::from(e))),
+                },
+                State::FlushResponse(mut response, upstream) => match track!(response.poll())? {
+                    Async::NotReady => {
+                        self.state = State::FlushResponse(response, upstream);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready(downstream) => return Ok(Async::Ready((downstream, upstream))),
+                },
+                State::Fail(mut response, e) => match track!(response.poll())? {
+                    Async::NotReady => {
+                        self.state = State::Fail(response, e);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready(_) => return Err(e),
+                },
+                State::Done => panic!("Cannot poll Forward twice"),
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[derive(Debug)]
+enum State<T, U> {
+    Request(BodyReader<server::Request<T>>, BodyWriter<client::Request<U>>),
+    SendRequest(server::Connection<T>, client::Request<U>),
+    ReadResponse(server::Connection<T>, client::ReadResponse<U>),
+    Response(BodyReader<client::Response<U>>, BodyWriter<server::Response<T>>),
+    FlushResponse(server::Response<T>, client::Connection<U>),
+    Fail(server::Response<T>, Error),
+    Done,
+}
+
+#[derive(Debug)]
+enum Copy {
+    Read(io::Error),
+    Write(io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    Length(u64),
+    Chunked,
+    Eof,
+}
+
+fn request_framing(headers: &Headers) -> ::Result<Framing> {
+    if headers.get("Transfer-Encoding").is_some() {
+        let codings = list(headers, "Transfer-Encoding");
+        track_assert!(
+            codings.last().is_some_and(|c| c.eq_ignore_ascii_case("chunked")),
+            Status::BadRequest,
+            "Unsupported transfer coding: {:?}",
+            codings
+        );
+        return Ok(Framing::Chunked);
+    }
+    let length = track!(headers.parse::<ContentLength>().map_err(Error::from))?;
+    Ok(Framing::Length(length.map_or(0, |h| h.0)))
+}
+
+fn response_framing(headers: &Headers) -> ::Result<Framing> {
+    if headers.get("Transfer-Encoding").is_some() {
+        let codings = list(headers, "Transfer-Encoding");
+        if codings.last().is_some_and(|c| c.eq_ignore_ascii_case("chunked")) {
+            return Ok(Framing::Chunked);
+        }
+        return Ok(Framing::Eof);
+    }
+    match headers.parse::<ContentLength>() {
+        Ok(Some(n)) => Ok(Framing::Length(n.0)),
+        Ok(None) => Ok(Framing::Eof),
+        Err(e) => Err(track!(Error::from(Status::BadGateway.cause(e)))),
+    }
+}
+
+#[derive(Debug)]
+enum BodyReader<R> {
+    Length(io::Take<R>),
+    Chunked(ChunkedDecoder<R>),
+    Eof(R),
+}
+impl<R: BufRead> BodyReader<R> {
+    fn new(inner: R, framing: Framing) -> Self {
+        match framing {
+            Framing::Length(n) => BodyReader::Length(inner.take(n)),
+            Framing::Chunked => BodyReader::Chunked(ChunkedDecoder::new(inner)),
+            Framing::Eof => BodyReader::Eof(inner),
+        }
+    }
+    fn into_inner(self) -> R {
+        match self {
+            BodyReader::Length(r) => r.into_inner(),
+            BodyReader::Chunked(r) => r.into_inner(),
+            BodyReader::Eof(r) => r,
+        }
+    }
+}
+impl<R: BufRead> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            BodyReader::Length(ref mut r) => {
+                let n = r.read(buf)?;
+                if n == 0 && r.limit() > 0 && !buf.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Unexpected EOF in a body",
+                    ));
+                }
+                Ok(n)
+            }
+            BodyReader::Chunked(ref mut r) => r.read(buf),
+            BodyReader::Eof(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum BodyWriter<W> {
+    Plain(W),
+    Chunked(ChunkedEncoder<W>),
+}
+impl<W: Write> BodyWriter<W> {
+    fn new(inner: W, chunked: bool) -> Self {
+        if chunked {
+            BodyWriter::Chunked(ChunkedEncoder::new(inner))
+        } else {
+            BodyWriter::Plain(inner)
+        }
+    }
+    fn try_finish(&mut self) -> io::Result<()> {
+        match *self {
+            BodyWriter::Plain(_) => Ok(()),
+            BodyWriter::Chunked(ref mut w) => w.try_finish(),
+        }
+    }
+    fn into_inner(self) -> W {
+        match self {
+            BodyWriter::Plain(w) => w,
+            BodyWriter::Chunked(w) => w.into_inner(),
+        }
+    }
+}
+impl<W: Write> Write for BodyWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            BodyWriter::Plain(ref mut w) => w.write(buf),
+            BodyWriter::Chunked(ref mut w) => w.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            BodyWriter::Plain(ref mut w) => w.flush(),
+            BodyWriter::Chunked(ref mut w) => w.flush(),
+        }
+    }
+}
+
+fn upstream_error(e: Error) -> Error {
+    let timed_out = e.concrete_cause::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::TimedOut);
+    let status = if timed_out {
+        Status::GatewayTimeout
+    } else {
+        Status::BadGateway
+    };
+    track!(Error::from(status.cause(e)))
+}
+
+fn error_response<T: TransportStream>(connection: server::Connection<T>, status: Status) -> server::Response<T> {
+    let mut response = connection.build_response(status);
+    response.add_raw_header("Connection", b"close");
+    response.add_header(&ContentLength(0));
+    response.finish()
+}
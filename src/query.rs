@@ -0,0 +1,351 @@
+//! Query string and `application/x-www-form-urlencoded` support.
+//!
+//! # Examples
+//!
+//! ```
+//! use miasht::query;
+//!
+//! let pairs = query::parse("a=1&b=x+y&c=%E3%81%82").collect::<Vec<_>>();
+//! assert_eq!(pairs[0], ("a".into(), "1".into()));
+//! assert_eq!(pairs[1], ("b".into(), "x y".into()));
+//! assert_eq!(pairs[2], ("c".into(), "あ".into()));
+//!
+//! let mut encoder = query::Encoder::new();
+//! encoder.append("name", "foo bar").append("q", "a&b=c");
+//! assert_eq!(encoder.as_str(), "name=foo+bar&q=a%26b%3Dc");
+//! ```
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::str;
+use futures::{Async, Future, Poll};
+
+use {Error, Status};
+
+/// The media type of form bodies.
+pub const FORM_URLENCODED: &str = "application/x-www-form-urlencoded";
+
+/// Parses `query` as a sequence of `application/x-www-form-urlencoded` pairs.
+///
+/// A leading `'?'` is ignored.
+/// Malformed percent-encoded sequences are kept as is and
+/// invalid UTF-8 sequences are replaced with `U+FFFD`.
+pub fn parse(query: &str) -> Pairs<'_> {
+    let query = query.strip_prefix('?').unwrap_or(query);
+    Pairs(query.split('&'))
+}
+
+/// Decodes a component of `application/x-www-form-urlencoded` string.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::query;
+///
+/// assert_eq!(query::decode("foo"), "foo");
+/// assert_eq!(query::decode("a+b%21"), "a b!");
+/// assert_eq!(query::decode("100%"), "100%");
+/// ```
+pub fn decode(s: &str) -> Cow<'_, str> {
+    if !s.bytes().any(|b| b == b'+' || b == b'%') {
+        return Cow::Borrowed(s);
+    }
+    let bytes = decode_bytes(s.as_bytes());
+    Cow::Owned(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Encodes `s` as a component of `application/x-www-form-urlencoded` string.
+pub fn encode(s: &str) -> String {
+    let mut buf = String::with_capacity(s.len());
+    encode_into(s, &mut buf);
+    buf
+}
+
+fn decode_bytes(s: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        match s[i] {
+            b'+' => buf.push(b' '),
+            b'%' if i + 2 < s.len() && hex(s[i + 1]).is_some() && hex(s[i + 2]).is_some() => {
+                buf.push(hex(s[i + 1]).unwrap() << 4 | hex(s[i + 2]).unwrap());
+                i += 2;
+            }
+            b => buf.push(b),
+        }
+        i += 1;
+    }
+    buf
+}
+
+fn hex(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn encode_into(s: &str, buf: &mut String) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    for b in s.bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => {
+                buf.push(b as char)
+            }
+            b' ' => buf.push('+'),
+            _ => {
+                buf.push('%');
+                buf.push(HEX[(b >> 4) as usize] as char);
+                buf.push(HEX[(b & 0xF) as usize] as char);
+            }
+        }
+    }
+}
+
+/// An iterator over the decoded key-value pairs of a query string.
+///
+/// This is created by calling `parse` function.
+#[derive(Debug, Clone)]
+pub struct Pairs<'a>(str::Split<'a, char>);
+impl<'a> Pairs<'a> {
+    /// Returns the decoded value of the first pair which has the key `key`.
+    pub fn get(mut self, key: &str) -> Option<Cow<'a, str>> {
+        self.find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+impl<'a> Iterator for Pairs<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let pair = self.0.next()?;
+            if pair.is_empty() {
+                continue;
+            }
+            let mut kv = pair.splitn(2, '=');
+            let k = kv.next().unwrap_or("");
+            let v = kv.next().unwrap_or("");
+            return Some((decode(k), decode(v)));
+        }
+    }
+}
+
+/// `application/x-www-form-urlencoded` encoder.
+///
+/// The resulting string can be used as the query part of a request target
+/// (e.g., `client::Connection::build_request_with_query`) or as a form body
+/// (e.g., `client::RequestBuilder::add_form_headers`).
+#[derive(Debug, Default, Clone)]
+pub struct Encoder {
+    buf: String,
+}
+impl Encoder {
+    /// Makes a new `Encoder` instance.
+    pub fn new() -> Self {
+        Encoder::default()
+    }
+
+    /// Appends a key-value pair.
+    pub fn append(&mut self, key: &str, value: &str) -> &mut Self {
+        if !self.buf.is_empty() {
+            self.buf.push('&');
+        }
+        encode_into(key, &mut self.buf);
+        self.buf.push('=');
+        encode_into(value, &mut self.buf);
+        self
+    }
+
+    /// Returns `true` if no pairs have been appended, otherwise `false`.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Returns the byte length of the encoded string.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns the encoded string.
+    pub fn as_str(&self) -> &str {
+        &self.buf
+    }
+
+    /// Converts into the encoded string.
+    pub fn into_string(self) -> String {
+        self.buf
+    }
+}
+
+/// Incremental decoder for `application/x-www-form-urlencoded` bodies.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::query::FormDecoder;
+///
+/// let mut decoder = FormDecoder::new(1024);
+/// decoder.feed(b"a=1&b=h").unwrap();
+/// assert_eq!(decoder.pop(), Some(("a".to_string(), "1".to_string())));
+/// assert_eq!(decoder.pop(), None);
+///
+/// decoder.feed(b"ello").unwrap();
+/// decoder.finish().unwrap();
+/// assert_eq!(decoder.pop(), Some(("b".to_string(), "hello".to_string())));
+///
+/// let mut decoder = FormDecoder::new(4);
+/// assert!(decoder.feed(b"a=123").is_err());
+/// ```
+#[derive(Debug)]
+pub struct FormDecoder {
+    pending: Vec<u8>,
+    pairs: VecDeque<(String, String)>,
+    size: u64,
+    max_size: u64,
+    pair_count: usize,
+    max_pairs: usize,
+}
+impl FormDecoder {
+    /// Makes a new `FormDecoder` instance which accepts bodies up to `max_size` bytes.
+    pub fn new(max_size: u64) -> Self {
+        FormDecoder {
+            pending: Vec::new(),
+            pairs: VecDeque::new(),
+            size: 0,
+            max_size,
+            pair_count: 0,
+            max_pairs: usize::MAX,
+        }
+    }
+
+    /// Sets the maximum number of the pairs contained in a body.
+    pub fn set_max_pairs(&mut self, max_pairs: usize) -> &mut Self {
+        self.max_pairs = max_pairs;
+        self
+    }
+
+    /// Feeds the next chunk of the body.
+    ///
+    /// If the body exceeds the limits, `Status::PayloadTooLarge` error will be returned.
+    pub fn feed(&mut self, bytes: &[u8]) -> ::Result<()> {
+        self.size += bytes.len() as u64;
+        track_assert!(
+            self.size <= self.max_size,
+            Status::PayloadTooLarge,
+            "Too large form body: max_size={}",
+            self.max_size
+        );
+        for (i, chunk) in bytes.split(|&b| b == b'&').enumerate() {
+            if i > 0 {
+                track!(self.flush_pending())?;
+            }
+            self.pending.extend_from_slice(chunk);
+        }
+        Ok(())
+    }
+
+    /// Notifies that the body has been fed entirely.
+    pub fn finish(&mut self) -> ::Result<()> {
+        track!(self.flush_pending())
+    }
+
+    /// Pops the next decoded pair.
+    pub fn pop(&mut self) -> Option<(String, String)> {
+        self.pairs.pop_front()
+    }
+
+    fn flush_pending(&mut self) -> ::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.pair_count += 1;
+        track_assert!(
+            self.pair_count <= self.max_pairs,
+            Status::PayloadTooLarge,
+            "Too many form pairs: max_pairs={}",
+            self.max_pairs
+        );
+        let (k, v) = {
+            let mut kv = self.pending.splitn(2, |&b| b == b'=');
+            let k = decode_bytes(kv.next().unwrap_or(b""));
+            let v = decode_bytes(kv.next().unwrap_or(b""));
+            (k, v)
+        };
+        self.pending.clear();
+        self.pairs.push_back((
+            String::from_utf8_lossy(&k).into_owned(),
+            String::from_utf8_lossy(&v).into_owned(),
+        ));
+        Ok(())
+    }
+}
+
+/// A future which reads a form body from `R` and decodes it.
+///
+/// If `content_length` is `None`, the body is read until the stream reaches EOF.
+#[derive(Debug)]
+pub struct ReadForm<R> {
+    reader: Option<R>,
+    decoder: FormDecoder,
+    remaining: Option<u64>,
+    pairs: Vec<(String, String)>,
+}
+impl<R: Read> ReadForm<R> {
+    /// Makes a new `ReadForm` future.
+    pub fn new(reader: R, content_length: Option<u64>, decoder: FormDecoder) -> Self {
+        ReadForm {
+            reader: Some(reader),
+            decoder,
+            remaining: content_length,
+            pairs: Vec::new(),
+        }
+    }
+}
+impl<R: Read> Future for ReadForm<R> {
+    type Item = (R, Vec<(String, String)>);
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut buf = [0; 1024];
+        loop {
+            if self.remaining == Some(0) {
+                break;
+            }
+            let size = {
+                let reader = self.reader.as_mut().expect("Cannot poll ReadForm twice");
+                let limit = self.remaining
+                    .map_or(buf.len(), |n| ::std::cmp::min(n, buf.len() as u64) as usize);
+                match reader.read(&mut buf[..limit]) {
+                    Err(e) => {
+                        if e.kind() == io::ErrorKind::WouldBlock {
+                            return Ok(Async::NotReady);
+                        }
+                        return Err(track!(Error::from(e)));
+                    }
+                    Ok(size) => size,
+                }
+            };
+            if size == 0 {
+                track_assert!(
+                    self.remaining.is_none(),
+                    Status::BadRequest,
+                    "Unexpected EOF while reading a form body"
+                );
+                break;
+            }
+            if let Some(ref mut n) = self.remaining {
+                *n -= size as u64;
+            }
+            track!(self.decoder.feed(&buf[..size]))?;
+            while let Some(pair) = self.decoder.pop() {
+                self.pairs.push(pair);
+            }
+        }
+        track!(self.decoder.finish())?;
+        while let Some(pair) = self.decoder.pop() {
+            self.pairs.push(pair);
+        }
+        let reader = self.reader.take().expect("Never fails");
+        Ok(Async::Ready((reader, ::std::mem::take(&mut self.pairs))))
+    }
+}
@@ -0,0 +1,721 @@
+//! Range requests ([RFC 7233](https://tools.ietf.org/html/rfc7233)).
+//!
+//! # Examples
+//!
+//! ```
+//! use miasht::Status;
+//! use miasht::header::Header;
+//! use miasht::range::{ByteRange, Range, RangeBody, Resolution};
+//!
+//! let range = Range::parse_value_str("bytes=0-1, 3-, -2").unwrap();
+//! let resolution = range.resolve(10);
+//! assert_eq!(
+//!     resolution,
+//!     Resolution::Partial(vec![ByteRange::new(0, 1), ByteRange::new(3, 9)])
+//! );
+//!
+//! let body = RangeBody::new(&resolution, 10, "text/plain");
+//! assert_eq!(body.status(), Status::PartialContent);
+//!
+//! let mut src = std::io::Cursor::new(b"0123456789".to_vec());
+//! let mut dst = Vec::new();
+//! body.write_to(&mut src, &mut dst).unwrap();
+//! assert_eq!(dst.len() as u64, body.content_length());
+//!
+//! assert_eq!(Range::parse_value_str("bytes=20-").unwrap().resolve(10), Resolution::NotSatisfiable);
+//! ```
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+use {Error, Method, Status};
+use conditional::{EntityTag, Validators};
+use date::HttpDate;
+use header::{ContentLength, ContentType, Header, Headers, HeadersMut};
+use multipart;
+
+/// The maximum number of ranges in a `Range` header.
+///
+/// Headers which have more ranges are rejected (see [RFC 7233 §6.1]
+/// (https://tools.ietf.org/html/rfc7233#section-6.1)).
+pub const MAX_RANGES: usize = 64;
+
+/// An inclusive byte range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ByteRange {
+    pub first: u64,
+    pub last: u64,
+}
+impl ByteRange {
+    /// Makes a new `ByteRange` instance.
+    ///
+    /// # Panics
+    ///
+    /// If `first` is greater than `last`, the calling thread will panic.
+    pub fn new(first: u64, last: u64) -> Self {
+        assert!(first <= last, "Invalid byte range: first={}, last={}", first, last);
+        ByteRange { first, last }
+    }
+
+    /// Returns the number of bytes in the range.
+    ///
+    /// The result is saturated at `u64::MAX` (i.e., for the range `0-18446744073709551615`).
+    pub fn len(&self) -> u64 {
+        (self.last - self.first).saturating_add(1)
+    }
+
+    /// Always returns `false` (a byte range has at least one byte).
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+/// A `byte-range-spec` or a `suffix-byte-range-spec`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ByteRangeSpec {
+    /// `first-last`
+    FromTo(u64, u64),
+
+    /// `first-`
+    From(u64),
+
+    /// `-suffix_length`
+    Suffix(u64),
+}
+impl ByteRangeSpec {
+    fn resolve(&self, length: u64) -> Option<ByteRange> {
+        if length == 0 {
+            return None;
+        }
+        match *self {
+            ByteRangeSpec::FromTo(first, last) if first < length && first <= last => {
+                Some(ByteRange::new(first, cmp::min(last, length - 1)))
+            }
+            ByteRangeSpec::From(first) if first < length => {
+                Some(ByteRange::new(first, length - 1))
+            }
+            ByteRangeSpec::Suffix(n) if n > 0 => {
+                Some(ByteRange::new(length.saturating_sub(n), length - 1))
+            }
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for ByteRangeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ByteRangeSpec::FromTo(first, last) => write!(f, "{}-{}", first, last),
+            ByteRangeSpec::From(first) => write!(f, "{}-", first),
+            ByteRangeSpec::Suffix(n) => write!(f, "-{}", n),
+        }
+    }
+}
+impl FromStr for ByteRangeSpec {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let hyphen = track_assert_some!(s.find('-'), Status::BadRequest, "Malformed range: {:?}", s);
+        let first = &s[..hyphen];
+        let last = &s[hyphen + 1..];
+        let parse = |n: &str| -> ::Result<u64> {
+            track_assert!(
+                !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()),
+                Status::BadRequest,
+                "Malformed range: {:?}",
+                s
+            );
+            n.parse().map_err(|_| track!(Error::from(Status::BadRequest)))
+        };
+        if first.is_empty() {
+            Ok(ByteRangeSpec::Suffix(track!(parse(last))?))
+        } else if last.is_empty() {
+            Ok(ByteRangeSpec::From(track!(parse(first))?))
+        } else {
+            let first = track!(parse(first))?;
+            let last = track!(parse(last))?;
+            track_assert!(first <= last, Status::BadRequest, "Malformed range: {:?}", s);
+            Ok(ByteRangeSpec::FromTo(first, last))
+        }
+    }
+}
+
+/// The result of resolving a `Range` against a representation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    /// The whole representation should be sent (`200 OK`).
+    Full,
+
+    /// The ranges should be sent (`206 Partial Content`).
+    ///
+    /// The ranges are sorted and never overlap.
+    Partial(Vec<ByteRange>),
+
+    /// No range is satisfiable (`416 Range Not Satisfiable`).
+    NotSatisfiable,
+}
+
+/// `Range` request header (only the `bytes` unit is supported).
+///
+/// # Examples
+///
+/// ```
+/// use miasht::Status;
+/// use miasht::header::Header;
+/// use miasht::range::{ByteRangeSpec, Range, MAX_RANGES};
+///
+/// let range = Range::parse_value_str("BYTES= 0-1,,-5").unwrap();
+/// assert_eq!(range.0, [ByteRangeSpec::FromTo(0, 1), ByteRangeSpec::Suffix(5)]);
+///
+/// for malformed in &[
+///     "bytes=", "bytes=1", "bytes=2-1", "bytes=a-1", "bytes=0-1x", "bytes=--1",
+///     "items=0-1", "bytesé=0-1", "é", "bytes=18446744073709551616-",
+/// ] {
+///     let e = Range::parse_value_str(malformed).unwrap_err();
+///     assert_eq!(*e.kind(), Status::BadRequest);
+/// }
+///
+/// let many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+/// assert!(Range::parse_value_str(&many).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Range(pub Vec<ByteRangeSpec>);
+impl Range {
+    /// `Range: bytes=first-last`
+    pub fn from_to(first: u64, last: u64) -> Self {
+        Range(vec![ByteRangeSpec::FromTo(first, last)])
+    }
+
+    /// `Range: bytes=first-`
+    pub fn from_offset(first: u64) -> Self {
+        Range(vec![ByteRangeSpec::From(first)])
+    }
+
+    /// `Range: bytes=-suffix_length`
+    pub fn suffix(suffix_length: u64) -> Self {
+        Range(vec![ByteRangeSpec::Suffix(suffix_length)])
+    }
+
+    /// Resolves the ranges against a representation of `length` bytes.
+    ///
+    /// Overlapping or adjacent ranges are coalesced.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::range::{ByteRange, ByteRangeSpec, Range, Resolution};
+    ///
+    /// let range = Range(vec![
+    ///     ByteRangeSpec::FromTo(5, 7),
+    ///     ByteRangeSpec::FromTo(0, 2),
+    ///     ByteRangeSpec::FromTo(1, 3),
+    ///     ByteRangeSpec::FromTo(4, 4),
+    ///     ByteRangeSpec::Suffix(1),
+    /// ]);
+    /// assert_eq!(
+    ///     range.resolve(10),
+    ///     Resolution::Partial(vec![ByteRange::new(0, 7), ByteRange::new(9, 9)])
+    /// );
+    ///
+    /// // Unsatisfiable ranges are ignored unless all of them are unsatisfiable
+    /// let range = Range(vec![ByteRangeSpec::From(10), ByteRangeSpec::FromTo(8, 100)]);
+    /// assert_eq!(range.resolve(10), Resolution::Partial(vec![ByteRange::new(8, 9)]));
+    /// assert_eq!(Range::suffix(0).resolve(10), Resolution::NotSatisfiable);
+    /// assert_eq!(Range::from_offset(0).resolve(0), Resolution::NotSatisfiable);
+    /// assert_eq!(Range::from_to(3, 2).resolve(10), Resolution::NotSatisfiable);
+    /// ```
+    pub fn resolve(&self, length: u64) -> Resolution {
+        let mut ranges = self.0
+            .iter()
+            .filter_map(|s| s.resolve(length))
+            .collect::<Vec<_>>();
+        if ranges.is_empty() {
+            return Resolution::NotSatisfiable;
+        }
+        ranges.sort();
+        let mut coalesced: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+        for r in ranges {
+            if let Some(prev) = coalesced.last_mut() {
+                if r.first <= prev.last.saturating_add(1) {
+                    prev.last = cmp::max(prev.last, r.last);
+                    continue;
+                }
+            }
+            coalesced.push(r);
+        }
+        Resolution::Partial(coalesced)
+    }
+}
+impl<'a> Header<'a> for Range {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Range"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "bytes=")?;
+        for (i, spec) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(writer, ",")?;
+            }
+            write!(writer, "{}", spec)?;
+        }
+        Ok(())
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        track_assert!(
+            value.get(..6).is_some_and(|p| p.eq_ignore_ascii_case("bytes=")),
+            Status::BadRequest,
+            "Unsupported range unit: {:?}",
+            value
+        );
+        let mut specs = Vec::new();
+        for item in value[6..].split(',').filter(|s| !s.trim().is_empty()) {
+            track_assert!(specs.len() < MAX_RANGES, Status::BadRequest, "Too many ranges");
+            specs.push(track!(item.parse())?);
+        }
+        track_assert!(!specs.is_empty(), Status::BadRequest, "Empty range");
+        Ok(Range(specs))
+    }
+}
+
+/// `Content-Range` header (only the `bytes` unit is supported).
+///
+/// # Examples
+///
+/// ```
+/// use miasht::header::Header;
+/// use miasht::range::{ByteRange, ContentRange};
+///
+/// let range = ContentRange::parse_value_str("bytes */10").unwrap();
+/// assert_eq!(range, ContentRange { range: None, complete_length: Some(10) });
+/// assert_eq!(
+///     ContentRange::parse_value_str("bytes 0-18446744073709551615/*").unwrap().range.unwrap().len(),
+///     u64::MAX
+/// );
+///
+/// for malformed in &["bytes */*", "bytes 0-/10", "bytes 2-1/10", "bytes 0-1", "bytesé0-1/2", "é"] {
+///     assert!(ContentRange::parse_value_str(malformed).is_err());
+/// }
+/// # let _ = ByteRange::new(0, 0);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentRange {
+    /// `None` means an unsatisfied range (i.e., `bytes */complete_length`).
+    pub range: Option<ByteRange>,
+
+    /// `None` means that the complete length is unknown (i.e., `*`).
+    pub complete_length: Option<u64>,
+}
+impl<'a> Header<'a> for ContentRange {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Content-Range"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "bytes ")?;
+        if let Some(r) = self.range {
+            write!(writer, "{}-{}/", r.first, r.last)?;
+        } else {
+            write!(writer, "*/")?;
+        }
+        if let Some(n) = self.complete_length {
+            write!(writer, "{}", n)
+        } else {
+            write!(writer, "*")
+        }
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        track_assert!(
+            value.len() > 6 && value.get(..6).is_some_and(|p| p.eq_ignore_ascii_case("bytes ")),
+            Status::BadRequest,
+            "Unsupported range unit: {:?}",
+            value
+        );
+        let rest = value[6..].trim();
+        let slash = track_assert_some!(rest.find('/'), Status::BadRequest, "No '/': {:?}", value);
+        let range = if &rest[..slash] == "*" {
+            None
+        } else {
+            match track!(rest[..slash].parse())? {
+                ByteRangeSpec::FromTo(first, last) => Some(ByteRange::new(first, last)),
+                _ => track_panic!(Status::BadRequest, "Malformed Content-Range: {:?}", value),
+            }
+        };
+        let complete_length = if &rest[slash + 1..] == "*" {
+            None
+        } else {
+            let n = rest[slash + 1..]
+                .parse()
+                .map_err(|_| track!(Error::from(Status::BadRequest)))?;
+            Some(n)
+        };
+        track_assert!(
+            range.is_some() || complete_length.is_some(),
+            Status::BadRequest,
+            "Malformed Content-Range: {:?}",
+            value
+        );
+        Ok(ContentRange {
+            range,
+            complete_length,
+        })
+    }
+}
+
+/// `Accept-Ranges` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AcceptRanges<'a>(pub &'a str);
+impl<'a> AcceptRanges<'a> {
+    /// `Accept-Ranges: bytes`
+    pub fn bytes() -> Self {
+        AcceptRanges("bytes")
+    }
+
+    /// `Accept-Ranges: none`
+    pub fn none() -> Self {
+        AcceptRanges("none")
+    }
+}
+impl<'a> Header<'a> for AcceptRanges<'a> {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Accept-Ranges"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self.0)
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        Ok(AcceptRanges(value.trim()))
+    }
+}
+
+/// `If-Range` header.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IfRange {
+    ETag(EntityTag),
+    Date(HttpDate),
+}
+impl IfRange {
+    /// Returns `true` if the validator matches the current representation.
+    ///
+    /// Entity-tags are compared with the strong comparison and
+    /// dates should exactly match the last modification time.
+    pub fn matches(&self, validators: &Validators) -> bool {
+        match *self {
+            IfRange::ETag(ref etag) => validators.get_etag().is_some_and(|e| e.strong_eq(etag)),
+            IfRange::Date(date) => validators.get_last_modified() == Some(date),
+        }
+    }
+}
+impl<'a> Header<'a> for IfRange {
+    type Error = Error;
+    fn name() -> &'static str {
+        "If-Range"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match *self {
+            IfRange::ETag(ref etag) => write!(writer, "{}", etag),
+            IfRange::Date(ref date) => write!(writer, "{}", date),
+        }
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        if value.starts_with('"') || value.starts_with("W/") {
+            track!(value.parse()).map(IfRange::ETag)
+        } else {
+            track!(value.parse()).map(IfRange::Date)
+        }
+    }
+}
+
+/// Evaluates the `Range` and `If-Range` headers of a request
+/// for a representation of `length` bytes (RFC 7233 §3).
+///
+/// Ranges are only applied to `GET` requests.
+/// If the `Range` header is malformed (or has more than `MAX_RANGES` ranges)
+/// or the `If-Range` condition is false, `Resolution::Full` is returned.
+///
+/// # Examples
+///
+/// ```
+/// extern crate httparse;
+/// extern crate miasht;
+///
+/// use miasht::{Method, Status};
+/// use miasht::conditional::Validators;
+/// use miasht::header::Headers;
+/// use miasht::range::{self, RangeBody, Resolution};
+///
+/// # fn main() {
+/// let validators = Validators::new();
+/// let evaluate = |method, value: &[u8]| {
+///     let headers = [httparse::Header { name: "Range", value }];
+///     range::evaluate(method, &Headers::new(&headers), &validators, 10)
+/// };
+///
+/// let resolution = evaluate(Method::Get, b"bytes=10-");
+/// assert_eq!(resolution, Resolution::NotSatisfiable);
+/// let body = RangeBody::new(&resolution, 10, "text/plain");
+/// assert_eq!(body.status(), Status::RangeNotSatisfiable);
+/// assert_eq!(body.content_length(), 0);
+///
+/// assert_eq!(evaluate(Method::Get, "bytesé=0-1".as_bytes()), Resolution::Full);
+/// assert_eq!(evaluate(Method::Get, b"bytes=1-0"), Resolution::Full);
+/// assert_eq!(evaluate(Method::Head, b"bytes=0-1"), Resolution::Full);
+/// # }
+/// ```
+pub fn evaluate(method: Method, headers: &Headers, validators: &Validators, length: u64) -> Resolution {
+    if method != Method::Get {
+        return Resolution::Full;
+    }
+    let range = match headers.parse::<Range>() {
+        Ok(Some(range)) => range,
+        _ => return Resolution::Full,
+    };
+    if headers.get(IfRange::name()).is_some() {
+        match headers.parse::<IfRange>() {
+            Ok(Some(ref if_range)) if if_range.matches(validators) => {}
+            _ => return Resolution::Full,
+        }
+    }
+    range.resolve(length)
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Full,
+    Single(ByteRange),
+    Multi {
+        ranges: Vec<ByteRange>,
+        boundary: String,
+        content_type: String,
+    },
+    NotSatisfiable,
+}
+
+/// The framing information of a (partial) response body.
+///
+/// This decides the status code and the headers of a response, and
+/// copies the selected ranges from a source to the response body.
+#[derive(Debug, Clone)]
+pub struct RangeBody {
+    kind: Kind,
+    length: u64,
+}
+impl RangeBody {
+    /// Makes a new `RangeBody` instance.
+    ///
+    /// `length` is the length of the complete representation and
+    /// `content_type` is its media type (used in `multipart/byteranges` parts).
+    pub fn new(resolution: &Resolution, length: u64, content_type: &str) -> Self {
+        let kind = match *resolution {
+            Resolution::Full => Kind::Full,
+            Resolution::NotSatisfiable => Kind::NotSatisfiable,
+            Resolution::Partial(ref ranges) if ranges.len() == 1 => Kind::Single(ranges[0]),
+            Resolution::Partial(ref ranges) => Kind::Multi {
+                ranges: ranges.clone(),
+                boundary: multipart::Builder::with_random_boundary()
+                    .boundary()
+                    .to_owned(),
+                content_type: content_type.to_owned(),
+            },
+        };
+        RangeBody { kind, length }
+    }
+
+    /// Returns the status code of the response.
+    pub fn status(&self) -> Status {
+        match self.kind {
+            Kind::Full => Status::Ok,
+            Kind::Single(_) | Kind::Multi { .. } => Status::PartialContent,
+            Kind::NotSatisfiable => Status::RangeNotSatisfiable,
+        }
+    }
+
+    /// Returns the length of the response body.
+    pub fn content_length(&self) -> u64 {
+        match self.kind {
+            Kind::Full => self.length,
+            Kind::Single(r) => r.len(),
+            Kind::NotSatisfiable => 0,
+            Kind::Multi { ref ranges, .. } => {
+                let headers: u64 = (0..ranges.len())
+                    .map(|i| self.part_header(i).len() as u64)
+                    .sum();
+                let bodies: u64 = ranges.iter().map(|r| r.len()).sum();
+                headers + bodies + self.trailer().len() as u64
+            }
+        }
+    }
+
+    /// Adds the `Accept-Ranges`, `Content-Length` and `Content-Range` headers
+    /// (and `Content-Type` for `multipart/byteranges`).
+    ///
+    /// The `Content-Type` of single part responses should be added by the caller.
+    pub fn add_headers(&self, headers: &mut HeadersMut) {
+        headers.add_header(&AcceptRanges::bytes());
+        headers.add_header(&ContentLength(self.content_length()));
+        match self.kind {
+            Kind::Full => {}
+            Kind::Single(r) => {
+                headers.add_header(&ContentRange {
+                    range: Some(r),
+                    complete_length: Some(self.length),
+                });
+            }
+            Kind::NotSatisfiable => {
+                headers.add_header(&ContentRange {
+                    range: None,
+                    complete_length: Some(self.length),
+                });
+            }
+            Kind::Multi { ref boundary, .. } => {
+                let content_type = format!("multipart/byteranges; boundary={}", boundary);
+                headers.add_header(&ContentType(&content_type));
+            }
+        }
+    }
+
+    /// Copies the response body from `src` (the complete representation) to `dst`.
+    pub fn write_to<R, W>(&self, src: &mut R, dst: &mut W) -> io::Result<()>
+    where
+        R: Read + Seek,
+        W: Write,
+    {
+        for segment in self.segments() {
+            match segment {
+                Segment::Bytes(bytes) => dst.write_all(&bytes)?,
+                Segment::Range(r) => copy_range(src, dst, r)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the segments which make up the response body in order.
+    pub(crate) fn segments(&self) -> Vec<Segment> {
+        match self.kind {
+            Kind::Full if self.length == 0 => Vec::new(),
+            Kind::Full => vec![Segment::Range(ByteRange::new(0, self.length - 1))],
+            Kind::Single(r) => vec![Segment::Range(r)],
+            Kind::NotSatisfiable => Vec::new(),
+            Kind::Multi { ref ranges, .. } => {
+                let mut segments = Vec::with_capacity(ranges.len() * 2 + 1);
+                for (i, r) in ranges.iter().enumerate() {
+                    segments.push(Segment::Bytes(self.part_header(i)));
+                    segments.push(Segment::Range(*r));
+                }
+                segments.push(Segment::Bytes(self.trailer()));
+                segments
+            }
+        }
+    }
+
+    fn part_header(&self, i: usize) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if let Kind::Multi {
+            ref ranges,
+            ref boundary,
+            ref content_type,
+        } = self.kind
+        {
+            let _ = write!(buf, "\r\n--{}\r\n", boundary);
+            let _ = write!(buf, "Content-Type: {}\r\n", content_type);
+            let _ = write!(buf, "Content-Range: ");
+            let _ = ContentRange {
+                range: Some(ranges[i]),
+                complete_length: Some(self.length),
+            }.write_value(&mut buf);
+            let _ = write!(buf, "\r\n\r\n");
+        }
+        buf
+    }
+
+    fn trailer(&self) -> Vec<u8> {
+        if let Kind::Multi { ref boundary, .. } = self.kind {
+            format!("\r\n--{}--\r\n", boundary).into_bytes()
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// A piece of a response body.
+#[derive(Debug)]
+pub(crate) enum Segment {
+    /// Literal bytes (e.g., the headers of a `multipart/byteranges` part).
+    Bytes(Vec<u8>),
+
+    /// A range of the source representation.
+    Range(ByteRange),
+}
+
+fn copy_range<R, W>(src: &mut R, dst: &mut W, range: ByteRange) -> io::Result<()>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    src.seek(SeekFrom::Start(range.first))?;
+    let copied = io::copy(&mut src.take(range.len()), dst)?;
+    if copied != range.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "The source is shorter than expected",
+        ));
+    }
+    Ok(())
+}
+
+/// The framing of a `206 Partial Content` response received by a client.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PartialContent {
+    /// A single part response.
+    Single(ContentRange),
+
+    /// A `multipart/byteranges` response.
+    ///
+    /// The body can be parsed by `multipart::Multipart` and
+    /// each part has its own `Content-Range` header.
+    Multipart { boundary: String },
+}
+impl PartialContent {
+    /// Determines the framing of a `206 Partial Content` response from its headers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate httparse;
+    /// extern crate miasht;
+    ///
+    /// use miasht::header::Headers;
+    /// use miasht::range::{ByteRange, ContentRange, PartialContent};
+    ///
+    /// # fn main() {
+    /// let headers = [httparse::Header { name: "Content-Range", value: b"bytes 0-9/100" }];
+    /// let expected = ContentRange {
+    ///     range: Some(ByteRange::new(0, 9)),
+    ///     complete_length: Some(100),
+    /// };
+    /// assert_eq!(
+    ///     PartialContent::from_headers(&Headers::new(&headers)).unwrap(),
+    ///     PartialContent::Single(expected)
+    /// );
+    /// # }
+    /// ```
+    pub fn from_headers(headers: &Headers) -> ::Result<Self> {
+        if let Some(range) = track!(headers.parse::<ContentRange>().map_err(Error::from))? {
+            return Ok(PartialContent::Single(range));
+        }
+        let content_type = track!(headers.parse::<ContentType>().map_err(Error::from))?;
+        let boundary = content_type.as_ref().and_then(multipart::boundary);
+        let boundary = track_assert_some!(
+            boundary,
+            Status::BadGateway,
+            "Neither Content-Range nor multipart/byteranges"
+        );
+        Ok(PartialContent::Multipart {
+            boundary: boundary.to_owned(),
+        })
+    }
+}
@@ -0,0 +1,462 @@
+//! Following redirects ([RFC 7231 §6.4](https://tools.ietf.org/html/rfc7231#section-6.4)).
+//!
+//! `client::ReadResponse` returns redirect responses as they are.
+//! `Redirects` decides whether and where such a response is followed,
+//! and `FollowRedirects` is a future which sends a request and follows the redirects
+//! with the decisions made by `Redirects`.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate httparse;
+//! # extern crate miasht;
+//! use miasht::Method;
+//! use miasht::header::Headers;
+//! use miasht::redirect::Redirects;
+//!
+//! # fn main() {
+//! let mut redirects = Redirects::new(Method::Post, "http://example.com/a/b").unwrap();
+//!
+//! // `303 See Other` switches to `GET`.
+//! let fields = [httparse::Header { name: "Location", value: b"../c?d=1" }];
+//! assert!(redirects.follow(303, &Headers::new(&fields)).unwrap());
+//! assert_eq!(redirects.method(), Method::Get);
+//! assert_eq!(redirects.url().to_string(), "http://example.com/c?d=1");
+//! assert_eq!(redirects.request_target(), "/c?d=1");
+//! assert!(!redirects.is_body_allowed());
+//!
+//! // Credentials such as `Authorization` and `Cookie` must not be sent to another origin.
+//! let fields = [httparse::Header { name: "Location", value: b"https://example.org/" }];
+//! assert!(redirects.follow(307, &Headers::new(&fields)).unwrap());
+//! assert_eq!(redirects.host(), "example.org");
+//! assert!(!redirects.is_authorization_allowed());
+//!
+//! // Non redirect responses are not followed.
+//! assert!(!redirects.follow(200, &Headers::new(&[])).unwrap());
+//!
+//! // Loops are detected.
+//! let fields = [httparse::Header { name: "Location", value: b"http://example.com/c?d=1" }];
+//! assert!(redirects.follow(302, &Headers::new(&fields)).is_err());
+//! # }
+//! ```
+use std::io;
+use std::mem;
+use std::str;
+use futures::{Async, Future, IntoFuture, Poll};
+
+use {Error, Method, Status, TransportStream};
+use client;
+use header::{ContentLength, Headers};
+use pool::{self, Key};
+use uri::Uri;
+use util;
+
+/// A redirect hop, which is passed to the policy of `Redirects`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hop<'a> {
+    /// The status code of the redirect response.
+    pub status: u16,
+
+    /// The URL of the request which received the redirect response.
+    pub from: &'a Uri,
+
+    /// The URL of the next request.
+    pub to: &'a Uri,
+
+    /// The method of the next request.
+    pub method: Method,
+}
+
+/// The default policy of `Redirects`, which allows every hop.
+pub fn always(_: &Hop) -> bool {
+    true
+}
+
+/// The decision maker of redirects.
+///
+/// The responses with `301`, `302`, `303`, `307` and `308` are followed
+/// if they have the `Location` header:
+///
+/// - `303 See Other` changes the method to `GET` (except `HEAD`)
+/// - `301` and `302` change the method `POST` to `GET`
+/// - `307` and `308` keep the method and the body
+///
+/// The number of hops is limited and redirect loops are detected.
+/// The policy can veto any hop.
+#[derive(Debug, Clone)]
+pub struct Redirects<P = fn(&Hop) -> bool> {
+    policy: P,
+    max_hops: usize,
+    method: Method,
+    url: Uri,
+    initial_origin: Key,
+    body_allowed: bool,
+    visited: Vec<(Method, Uri)>,
+}
+impl Redirects {
+    /// The default value of the maximum number of hops.
+    pub const DEFAULT_MAX_HOPS: usize = 10;
+
+    /// Makes a new `Redirects` instance for the request of `method` to `url`.
+    ///
+    /// If `url` is not an absolute `http` or `https` URL, `Status::BadRequest` error will be returned.
+    pub fn new(method: Method, url: &str) -> ::Result<Self> {
+        let url = track!(url.parse::<Uri>())?.normalize().without_fragment();
+        track_assert!(is_http(&url), Status::BadRequest, "Not an absolute HTTP URL: {}", url);
+        let initial_origin = url.origin().expect("Never fails");
+        Ok(Redirects {
+            policy: always,
+            max_hops: Self::DEFAULT_MAX_HOPS,
+            method,
+            url,
+            initial_origin,
+            body_allowed: true,
+            visited: Vec::new(),
+        })
+    }
+}
+impl<P: FnMut(&Hop) -> bool> Redirects<P> {
+    /// Sets the maximum number of hops.
+    pub fn max_hops(mut self, max_hops: usize) -> Self {
+        self.max_hops = max_hops;
+        self
+    }
+
+    /// Sets the policy which decides whether each hop is followed.
+    ///
+    /// If it returns `false`, the redirect response is returned to the caller as it is.
+    pub fn policy<Q>(self, policy: Q) -> Redirects<Q>
+    where
+        Q: FnMut(&Hop) -> bool,
+    {
+        Redirects {
+            policy,
+            max_hops: self.max_hops,
+            method: self.method,
+            url: self.url,
+            initial_origin: self.initial_origin,
+            body_allowed: self.body_allowed,
+            visited: self.visited,
+        }
+    }
+
+    /// Returns the method of the current request.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// Returns the normalized URL of the current request (the fragment is removed).
+    pub fn url(&self) -> &Uri {
+        &self.url
+    }
+
+    /// Returns the origin of the current request.
+    pub fn origin(&self) -> Key {
+        self.url.origin().expect("Never fails")
+    }
+
+    /// Returns the request target of the current request (i.e., the path and the query).
+    pub fn request_target(&self) -> String {
+        self.url.request_target()
+    }
+
+    /// Returns the value of the `Host` header of the current request.
+    pub fn host(&self) -> String {
+        self.url.host_header().expect("Never fails")
+    }
+
+    /// Returns the number of hops which have been followed.
+    pub fn hops(&self) -> usize {
+        self.visited.len()
+    }
+
+    /// Returns `true` if the body of the original request should be sent, otherwise `false`.
+    ///
+    /// This becomes `false` once the method is changed to `GET`.
+    pub fn is_body_allowed(&self) -> bool {
+        self.body_allowed
+    }
+
+    /// Returns `true` if the current request has the same origin as the original one, otherwise `false`.
+    ///
+    /// The `Authorization`, `Cookie` and `Proxy-Authorization` headers
+    /// must be sent only if this is `true`.
+    pub fn is_authorization_allowed(&self) -> bool {
+        self.origin() == self.initial_origin
+    }
+
+    /// Decides whether the response which has `status` and `headers` is followed.
+    ///
+    /// If it is, the state is updated for the next request and `true` is returned.
+    /// `false` is returned for non redirect responses, responses without `Location`
+    /// and the hops vetoed by the policy.
+    ///
+    /// If the maximum number of hops is exceeded or a loop is detected,
+    /// `Status::LoopDetected` error will be returned.
+    /// If the `Location` header is malformed or refers to a non `http(s)` URL,
+    /// `Status::BadGateway` error will be returned.
+    pub fn follow(&mut self, status: u16, headers: &Headers) -> ::Result<bool> {
+        let method = match status {
+            303 if self.method != Method::Head => Method::Get,
+            301 | 302 if self.method == Method::Post => Method::Get,
+            301 | 302 | 303 | 307 | 308 => self.method,
+            _ => return Ok(false),
+        };
+        let location = if let Some(location) = headers.get("Location") {
+            track_assert_some!(
+                str::from_utf8(location).ok(),
+                Status::BadGateway,
+                "Non UTF-8 Location header"
+            )
+        } else {
+            return Ok(false);
+        };
+        let reference = track_assert_some!(
+            location.trim().parse::<Uri>().ok(),
+            Status::BadGateway,
+            "Malformed Location header: {:?}",
+            location
+        );
+        let to = track!(self.url.resolve(&reference))?.normalize().without_fragment();
+        track_assert!(is_http(&to), Status::BadGateway, "Unsupported redirect target: {}", to);
+
+        let allowed = (self.policy)(&Hop {
+            status,
+            from: &self.url,
+            to: &to,
+            method,
+        });
+        if !allowed {
+            return Ok(false);
+        }
+        track_assert!(
+            self.visited.len() < self.max_hops,
+            Status::LoopDetected,
+            "Too many redirects: max_hops={}",
+            self.max_hops
+        );
+        let from = mem::replace(&mut self.url, to);
+        self.visited.push((self.method, from));
+        track_assert!(
+            !self.visited.iter().any(|v| v.0 == method && v.1 == self.url),
+            Status::LoopDetected,
+            "Redirect loop: method={}, url={}",
+            method,
+            self.url
+        );
+        if method != self.method {
+            self.body_allowed = false;
+        }
+        self.method = method;
+        Ok(true)
+    }
+}
+
+/// A future which sends a request and follows the redirects.
+///
+/// Each request is built from the state of `Redirects`: the method, the request target
+/// and the `Host` header are taken from it, and the headers added by `add_raw_header`
+/// are sent with every request with the following exceptions:
+///
+/// - `Authorization`, `Cookie` and `Proxy-Authorization` are removed once the origin changes
+/// - `Content-*` headers are removed (with the body) once the method is changed to `GET`
+///
+/// `Host` and `Content-Length` are added automatically.
+///
+/// The connection is reused if the next request has the same origin and
+/// the body of the redirect response can be discarded.
+/// Otherwise `connect` is called with the origin of the next request,
+/// and the future returned by it should yield a new connection.
+///
+/// The final response is yielded together with its URL.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate futures;
+/// # extern crate miasht;
+/// use futures::Future;
+/// use miasht::{Error, Method, TransportStream};
+/// use miasht::client::Connection;
+/// use miasht::pool::Key;
+/// use miasht::redirect::{FollowRedirects, Redirects};
+///
+/// fn get<T, F>(mut connect: F)
+/// where
+///     T: TransportStream,
+///     F: FnMut(&Key) -> Result<Connection<T>, Error>,
+/// {
+///     let redirects = Redirects::new(Method::Get, "http://example.com/").unwrap();
+///     let connection = connect(&redirects.origin()).unwrap();
+///     let (response, url) = FollowRedirects::new(redirects, connection, connect)
+///         .add_raw_header("Accept", b"text/html")
+///         .wait()
+///         .unwrap();
+///     println!("{}: {}", url, response.status().code());
+/// }
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct FollowRedirects<T, P, C, F>
+where
+    F: IntoFuture,
+{
+    redirects: Redirects<P>,
+    connect: C,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+    state: State<T, F::Future>,
+}
+#[derive(Debug)]
+enum State<T, F> {
+    Start(client::Connection<T>),
+    Connect(F),
+    Send(client::Request<T>, usize),
+    Read(client::ReadResponse<T>),
+    Discard(client::DiscardBody<T>),
+    Done,
+}
+impl<T, P, C, F> FollowRedirects<T, P, C, F>
+where
+    T: TransportStream,
+    P: FnMut(&Hop) -> bool,
+    C: FnMut(&Key) -> F,
+    F: IntoFuture<Item = client::Connection<T>, Error = Error>,
+{
+    /// Makes a new `FollowRedirects` future.
+    ///
+    /// `connection` should be connected to the origin of `redirects`.
+    pub fn new(redirects: Redirects<P>, connection: client::Connection<T>, connect: C) -> Self {
+        FollowRedirects {
+            redirects,
+            connect,
+            headers: Vec::new(),
+            body: Vec::new(),
+            state: State::Start(connection),
+        }
+    }
+
+    /// Adds a header which is sent with the requests.
+    pub fn add_raw_header(mut self, name: &str, value: &[u8]) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Sets the body of the request.
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    fn build_request(&self, connection: client::Connection<T>) -> client::Request<T> {
+        let target = self.redirects.request_target();
+        let mut request = connection.build_request(self.redirects.method(), &target);
+        request.add_raw_header("Host", self.redirects.host().as_bytes());
+
+        let authorization = self.redirects.is_authorization_allowed();
+        let body = self.redirects.is_body_allowed();
+        for (name, value) in &self.headers {
+            let skip = name.eq_ignore_ascii_case("Host")
+                || name.eq_ignore_ascii_case("Content-Length")
+                || (!authorization && is_credential(name))
+                || (!body && name.get(..8).is_some_and(|p| p.eq_ignore_ascii_case("Content-")));
+            if !skip {
+                request.add_raw_header(name, value);
+            }
+        }
+        if body && !self.body.is_empty() {
+            request.add_header(&ContentLength(self.body.len() as u64));
+        }
+        request.finish()
+    }
+}
+impl<T, P, C, F> Future for FollowRedirects<T, P, C, F>
+where
+    T: TransportStream,
+    P: FnMut(&Hop) -> bool,
+    C: FnMut(&Key) -> F,
+    F: IntoFuture<Item = client::Connection<T>, Error = Error>,
+{
+    type Item = (client::Response<T>, Uri);
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match mem::replace(&mut self.state, State::Done) {
+                State::Start(connection) => State::Send(self.build_request(connection), 0),
+                State::Connect(mut f) => match track!(f.poll())? {
+                    Async::NotReady => {
+                        self.state = State::Connect(f);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready(connection) => State::Send(self.build_request(connection), 0),
+                },
+                State::Send(mut request, mut written) => {
+                    let body: &[u8] = if self.redirects.is_body_allowed() {
+                        &self.body
+                    } else {
+                        &[]
+                    };
+                    match util::write_all(&mut request, body, &mut written) {
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            self.state = State::Send(request, written);
+                            return Ok(Async::NotReady);
+                        }
+                        Err(e) => return Err(track!(Error::from(e))),
+                        Ok(()) => {}
+                    }
+                    match track!(request.poll())? {
+                        Async::NotReady => {
+                            self.state = State::Send(request, written);
+                            return Ok(Async::NotReady);
+                        }
+                        Async::Ready(connection) => State::Read(connection.read_response()),
+                    }
+                }
+                State::Read(mut f) => {
+                    let response = match track!(f.poll())? {
+                        Async::NotReady => {
+                            self.state = State::Read(f);
+                            return Ok(Async::NotReady);
+                        }
+                        Async::Ready(response) => response,
+                    };
+                    let from = self.redirects.origin();
+                    if !track!(self.redirects.follow(response.status().code(), response.headers()))? {
+                        let url = self.redirects.url().clone();
+                        return Ok(Async::Ready((response, url)));
+                    }
+                    let reusable = self.redirects.origin() == from && pool::is_reusable(&response);
+                    let has_body = response
+                        .request_method()
+                        .has_response_body(response.status().code());
+                    if reusable && !has_body {
+                        State::Send(self.build_request(response.finish()), 0)
+                    } else if reusable {
+                        State::Discard(response.discard_body())
+                    } else {
+                        State::Connect((self.connect)(&self.redirects.origin()).into_future())
+                    }
+                }
+                State::Discard(mut f) => match track!(f.poll())? {
+                    Async::NotReady => {
+                        self.state = State::Discard(f);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready(connection) => State::Send(self.build_request(connection), 0),
+                },
+                State::Done => panic!("Cannot poll FollowRedirects twice"),
+            };
+            self.state = next;
+        }
+    }
+}
+
+fn is_credential(name: &str) -> bool {
+    ["Authorization", "Cookie", "Proxy-Authorization"]
+        .iter()
+        .any(|n| name.eq_ignore_ascii_case(n))
+}
+
+fn is_http(uri: &Uri) -> bool {
+    uri.scheme().is_some_and(|s| s == "http" || s == "https") && uri.origin().is_some()
+}
@@ -0,0 +1,580 @@
+//! Retrying idempotent requests.
+//!
+//! A request is retried only if its method is idempotent
+//! ([RFC 7231 §4.2.2](https://tools.ietf.org/html/rfc7231#section-4.2.2)) and
+//!
+//! - the connection failed before any byte of the response arrived
+//!   (e.g., an idle keep-alive connection had been closed by the server), or
+//! - the response is `503 Service Unavailable` or `429 Too Many Requests`
+//!   (the `Retry-After` header is honoured).
+//!
+//! `Policy` has the configuration (the maximum number of retries, the exponential backoff and
+//! the retry budget shared between requests), and `Retry` decides whether each failure of
+//! a request is retried and how long to wait before that.
+//! `SendRequest` is a future which sends a request with the decisions made by `Retry`.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate httparse;
+//! # extern crate miasht;
+//! use std::io;
+//! use std::time::{Duration, UNIX_EPOCH};
+//! use miasht::{Error, Method};
+//! use miasht::cache::ManualClock;
+//! use miasht::header::Headers;
+//! use miasht::retry::Policy;
+//!
+//! # fn main() {
+//! let mut policy = Policy::with_clock(ManualClock::new(UNIX_EPOCH));
+//! policy.set_max_retries(2);
+//!
+//! let mut retry = policy.start(Method::Get);
+//! let closed = Error::from(io::Error::new(io::ErrorKind::UnexpectedEof, "Closed"));
+//! assert!(retry.on_error(&closed, false).is_some());
+//! assert!(retry.on_error(&closed, true).is_none()); // The response had started
+//!
+//! let fields = [httparse::Header { name: "Retry-After", value: b"5" }];
+//! assert_eq!(retry.on_response(503, &Headers::new(&fields)), Some(Duration::from_secs(5)));
+//! assert_eq!(retry.on_response(503, &Headers::new(&fields)), None); // Too many retries
+//!
+//! // Non idempotent requests are not retried.
+//! let mut retry = policy.start(Method::Post);
+//! assert!(retry.on_error(&closed, false).is_none());
+//! # }
+//! ```
+use std::cmp;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures::{Async, Future, IntoFuture, Poll};
+
+use {Error, Method, TransportStream};
+use cache::{Clock, SystemClock};
+use client;
+use date::HttpDate;
+use header::{ContentLength, Header, Headers};
+use util::{self, random_bytes};
+
+/// Returns `true` if `method` is idempotent, otherwise `false`.
+///
+/// This is equivalent to `Method::is_idempotent`.
+pub fn is_idempotent(method: Method) -> bool {
+    method.is_idempotent()
+}
+
+/// Returns `true` if `error` is caused by a failure of the connection, otherwise `false`.
+pub fn is_connection_error(error: &Error) -> bool {
+    error.concrete_cause::<io::Error>().is_some_and(|e| {
+        matches!(
+            e.kind(),
+            io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::TimedOut
+        )
+    })
+}
+
+/// `Retry-After` header.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::header::Header;
+/// use miasht::retry::RetryAfter;
+///
+/// assert_eq!(RetryAfter::parse_value_str("120").unwrap(), RetryAfter::Delay(120));
+/// assert!(RetryAfter::parse_value_str("Fri, 31 Dec 1999 23:59:59 GMT").is_ok());
+/// assert!(RetryAfter::parse_value_str("soon").is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RetryAfter {
+    /// The time after which the request can be retried.
+    Date(HttpDate),
+
+    /// The number of seconds to wait.
+    Delay(u64),
+}
+impl RetryAfter {
+    /// Returns the duration to wait from `clock`'s current time.
+    pub fn delay<C: Clock>(&self, clock: &C) -> Duration {
+        match *self {
+            RetryAfter::Date(date) => date.to_system_time()
+                .duration_since(clock.now())
+                .unwrap_or_default(),
+            RetryAfter::Delay(secs) => Duration::from_secs(secs),
+        }
+    }
+}
+impl<'a> Header<'a> for RetryAfter {
+    type Error = Error;
+    fn name() -> &'static str {
+        "Retry-After"
+    }
+    fn write_value<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match *self {
+            RetryAfter::Date(date) => write!(writer, "{}", date),
+            RetryAfter::Delay(secs) => write!(writer, "{}", secs),
+        }
+    }
+    fn parse_value_str(value: &'a str) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+            Ok(RetryAfter::Delay(value.parse().unwrap_or(u64::from(u32::MAX))))
+        } else {
+            let date = track!(value.parse(), "Malformed Retry-After: {:?}", value)?;
+            Ok(RetryAfter::Date(date))
+        }
+    }
+}
+
+/// A retry budget shared between requests.
+///
+/// Each request deposits `ratio` tokens, and each retry withdraws one token.
+/// Retries are not allowed when the balance is less than one token,
+/// so the ratio of retries to requests is bounded by `ratio` in the long run
+/// (a retry storm cannot overload a failing server).
+///
+/// Clones share the same balance.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::retry::Budget;
+///
+/// let budget = Budget::new(0.5, 1.0);
+/// assert!(budget.withdraw());
+/// assert!(!budget.withdraw());
+///
+/// budget.deposit();
+/// budget.deposit();
+/// assert!(budget.withdraw());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Budget(Arc<Mutex<BudgetState>>);
+#[derive(Debug)]
+struct BudgetState {
+    ratio: f64,
+    capacity: f64,
+    balance: f64,
+}
+impl Budget {
+    /// The default value of the ratio.
+    pub const DEFAULT_RATIO: f64 = 0.2;
+
+    /// The default value of the capacity.
+    pub const DEFAULT_CAPACITY: f64 = 10.0;
+
+    /// Makes a new `Budget` instance.
+    ///
+    /// The balance starts at `capacity` and never exceeds it.
+    pub fn new(ratio: f64, capacity: f64) -> Self {
+        Budget(Arc::new(Mutex::new(BudgetState {
+            ratio,
+            capacity,
+            balance: capacity,
+        })))
+    }
+
+    /// Returns the current balance.
+    pub fn balance(&self) -> f64 {
+        self.0.lock().expect("Poisoned lock").balance
+    }
+
+    /// Deposits the tokens for a request.
+    pub fn deposit(&self) {
+        let mut state = self.0.lock().expect("Poisoned lock");
+        state.balance = state.capacity.min(state.balance + state.ratio);
+    }
+
+    /// Withdraws a token for a retry.
+    ///
+    /// Returns `false` if the balance is not enough.
+    pub fn withdraw(&self) -> bool {
+        let mut state = self.0.lock().expect("Poisoned lock");
+        if state.balance < 1.0 {
+            return false;
+        }
+        state.balance -= 1.0;
+        true
+    }
+}
+impl Default for Budget {
+    fn default() -> Self {
+        Budget::new(Self::DEFAULT_RATIO, Self::DEFAULT_CAPACITY)
+    }
+}
+
+/// The retry policy.
+///
+/// The delay before the `n`-th retry (`n` starts from zero) is chosen randomly
+/// between the half of `min(max_delay, base_delay * 2^n)` and the whole of it
+/// (exponential backoff with jitter), unless the response specifies `Retry-After`.
+#[derive(Debug, Clone)]
+pub struct Policy<C = SystemClock> {
+    clock: C,
+    max_retries: usize,
+    base_delay: Duration,
+    max_delay: Duration,
+    max_retry_after: Duration,
+    budget: Option<Budget>,
+}
+impl Policy<SystemClock> {
+    /// Makes a new `Policy` instance which uses the system clock.
+    pub fn new() -> Self {
+        Policy::with_clock(SystemClock)
+    }
+}
+impl Default for Policy<SystemClock> {
+    fn default() -> Self {
+        Policy::new()
+    }
+}
+impl<C: Clock + Clone> Policy<C> {
+    /// The default value of the maximum number of retries per request.
+    pub const DEFAULT_MAX_RETRIES: usize = 3;
+
+    /// The default value of the base delay of the backoff.
+    pub const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(100);
+
+    /// The default value of the maximum delay of the backoff.
+    pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(10);
+
+    /// The default value of the maximum `Retry-After` delay to wait.
+    pub const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+    /// Makes a new `Policy` instance which uses `clock`.
+    ///
+    /// It has no retry budget.
+    pub fn with_clock(clock: C) -> Self {
+        Policy {
+            clock,
+            max_retries: Self::DEFAULT_MAX_RETRIES,
+            base_delay: Self::DEFAULT_BASE_DELAY,
+            max_delay: Self::DEFAULT_MAX_DELAY,
+            max_retry_after: Self::DEFAULT_MAX_RETRY_AFTER,
+            budget: None,
+        }
+    }
+
+    /// Sets the maximum number of retries per request.
+    pub fn set_max_retries(&mut self, max: usize) {
+        self.max_retries = max;
+    }
+
+    /// Sets the base delay of the backoff.
+    pub fn set_base_delay(&mut self, delay: Duration) {
+        self.base_delay = delay;
+    }
+
+    /// Sets the maximum delay of the backoff.
+    pub fn set_max_delay(&mut self, delay: Duration) {
+        self.max_delay = delay;
+    }
+
+    /// Sets the maximum `Retry-After` delay to wait.
+    ///
+    /// If a response asks to wait longer, it is not retried.
+    pub fn set_max_retry_after(&mut self, delay: Duration) {
+        self.max_retry_after = delay;
+    }
+
+    /// Sets the retry budget.
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = Some(budget);
+    }
+
+    /// Returns the delay before the `retries`-th retry (starting from zero).
+    pub fn backoff(&self, retries: usize) -> Duration {
+        let factor = 1 << cmp::min(retries, 16);
+        let cap = self.base_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |d| cmp::min(d, self.max_delay));
+        let half = (cap.as_nanos() / 2) as u64;
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&random_bytes()[..8]);
+        let jitter = u64::from_le_bytes(bytes) % (half + 1);
+        Duration::from_nanos(half + jitter)
+    }
+
+    /// Starts a request of `method`.
+    ///
+    /// The tokens for the request are deposited to the budget.
+    pub fn start(&self, method: Method) -> Retry<C> {
+        if let Some(ref budget) = self.budget {
+            budget.deposit();
+        }
+        Retry {
+            policy: self.clone(),
+            method,
+            retries: 0,
+        }
+    }
+}
+
+/// The retry state of a request.
+///
+/// This is created by calling `Policy::start` method.
+#[derive(Debug, Clone)]
+pub struct Retry<C = SystemClock> {
+    policy: Policy<C>,
+    method: Method,
+    retries: usize,
+}
+impl<C: Clock + Clone> Retry<C> {
+    /// Returns the method of the request.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// Returns the number of retries which have been decided.
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+
+    /// Decides whether the request which failed with `error` is retried.
+    ///
+    /// `response_started` should be `true` if any byte of the response has been received
+    /// (see `client::ReadResponse::is_started`).
+    ///
+    /// If it is retried, the delay before the retry is returned.
+    pub fn on_error(&mut self, error: &Error, response_started: bool) -> Option<Duration> {
+        if response_started || !is_connection_error(error) {
+            return None;
+        }
+        let delay = self.policy.backoff(self.retries);
+        self.retry(delay)
+    }
+
+    /// Decides whether the request which received the response of `status` and `headers` is retried.
+    ///
+    /// Only `503` and `429` responses are retried.
+    /// If they have a valid `Retry-After` header longer than the maximum, they are not retried.
+    ///
+    /// If it is retried, the delay before the retry is returned.
+    pub fn on_response(&mut self, status: u16, headers: &Headers) -> Option<Duration> {
+        if status != 503 && status != 429 {
+            return None;
+        }
+        let delay = match headers.parse::<RetryAfter>() {
+            Ok(Some(retry_after)) => retry_after.delay(&self.policy.clock),
+            _ => self.policy.backoff(self.retries),
+        };
+        if delay > self.policy.max_retry_after {
+            return None;
+        }
+        self.retry(delay)
+    }
+
+    fn retry(&mut self, delay: Duration) -> Option<Duration> {
+        if !is_idempotent(self.method) || self.retries >= self.policy.max_retries {
+            return None;
+        }
+        if let Some(ref budget) = self.policy.budget {
+            if !budget.withdraw() {
+                return None;
+            }
+        }
+        self.retries += 1;
+        Some(delay)
+    }
+}
+
+/// A future which sends a request and retries it with the decisions made by `Retry`.
+///
+/// The request is built from the method of `Retry`, the request target `target`,
+/// the headers added by `add_raw_header` and the body set by `body`
+/// (`Content-Length` is added automatically if it is not empty).
+///
+/// Before a retry, the future returned by `sleep` is waited for the delay,
+/// and a new connection is made by the future returned by `connect`.
+///
+/// If the request is not retried, the last response or error is returned.
+///
+/// # Examples
+///
+/// ```no_run
+/// # extern crate futures;
+/// # extern crate miasht;
+/// use std::thread;
+/// use std::time::Duration;
+/// use futures::Future;
+/// use miasht::{Error, Method, TransportStream};
+/// use miasht::client::Connection;
+/// use miasht::retry::{Policy, SendRequest};
+///
+/// fn get<T, F>(connection: Connection<T>, connect: F)
+/// where
+///     T: TransportStream,
+///     F: FnMut() -> Result<Connection<T>, Error>,
+/// {
+///     let sleep = |d: Duration| -> Result<(), Error> { thread::sleep(d); Ok(()) };
+///     let retry = Policy::new().start(Method::Get);
+///     let response = SendRequest::new(retry, "/", connection, connect, sleep)
+///         .add_raw_header("Host", b"example.com")
+///         .wait()
+///         .unwrap();
+///     println!("{}", response.status().code());
+/// }
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct SendRequest<T, K, C, F, S, G>
+where
+    F: IntoFuture,
+    G: IntoFuture,
+{
+    retry: Retry<K>,
+    target: String,
+    connect: C,
+    sleep: S,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+    state: State<T, F::Future, G::Future>,
+}
+#[derive(Debug)]
+enum State<T, F, G> {
+    Start(client::Connection<T>),
+    Sleep(G),
+    Connect(F),
+    Send(client::Request<T>, usize),
+    Read(client::ReadResponse<T>),
+    Done,
+}
+impl<T, K, C, F, S, G> SendRequest<T, K, C, F, S, G>
+where
+    T: TransportStream,
+    K: Clock + Clone,
+    C: FnMut() -> F,
+    F: IntoFuture<Item = client::Connection<T>, Error = Error>,
+    S: FnMut(Duration) -> G,
+    G: IntoFuture<Item = (), Error = Error>,
+{
+    /// Makes a new `SendRequest` future.
+    ///
+    /// The first attempt uses `connection` (e.g., an idle connection taken from a pool).
+    pub fn new(retry: Retry<K>, target: &str, connection: client::Connection<T>, connect: C, sleep: S) -> Self {
+        SendRequest {
+            retry,
+            target: target.to_owned(),
+            connect,
+            sleep,
+            headers: Vec::new(),
+            body: Vec::new(),
+            state: State::Start(connection),
+        }
+    }
+
+    /// Adds a header which is sent with the request.
+    pub fn add_raw_header(mut self, name: &str, value: &[u8]) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Sets the body of the request.
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Returns the retry state of the request.
+    pub fn retry(&self) -> &Retry<K> {
+        &self.retry
+    }
+
+    fn build_request(&self, connection: client::Connection<T>) -> client::Request<T> {
+        let mut request = connection.build_request(self.retry.method(), &self.target);
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                request.add_raw_header(name, value);
+            }
+        }
+        if !self.body.is_empty() {
+            request.add_header(&ContentLength(self.body.len() as u64));
+        }
+        request.finish()
+    }
+
+    fn next_attempt(&mut self, delay: Duration) -> State<T, F::Future, G::Future> {
+        if delay == Duration::from_secs(0) {
+            State::Connect((self.connect)().into_future())
+        } else {
+            State::Sleep((self.sleep)(delay).into_future())
+        }
+    }
+}
+impl<T, K, C, F, S, G> Future for SendRequest<T, K, C, F, S, G>
+where
+    T: TransportStream,
+    K: Clock + Clone,
+    C: FnMut() -> F,
+    F: IntoFuture<Item = client::Connection<T>, Error = Error>,
+    S: FnMut(Duration) -> G,
+    G: IntoFuture<Item = (), Error = Error>,
+{
+    type Item = client::Response<T>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match mem::replace(&mut self.state, State::Done) {
+                State::Start(connection) => State::Send(self.build_request(connection), 0),
+                State::Sleep(mut f) => match track!(f.poll())? {
+                    Async::NotReady => {
+                        self.state = State::Sleep(f);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready(()) => State::Connect((self.connect)().into_future()),
+                },
+                State::Connect(mut f) => match track!(f.poll())? {
+                    Async::NotReady => {
+                        self.state = State::Connect(f);
+                        return Ok(Async::NotReady);
+                    }
+                    Async::Ready(connection) => State::Send(self.build_request(connection), 0),
+                },
+                State::Send(mut request, mut written) => {
+                    let polled = match util::write_all(&mut request, &self.body, &mut written) {
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+                        Err(e) => Err(track!(Error::from(e))),
+                        Ok(()) => track!(request.poll()),
+                    };
+                    match polled {
+                        Err(e) => match self.retry.on_error(&e, false) {
+                            None => return Err(e),
+                            Some(delay) => self.next_attempt(delay),
+                        },
+                        Ok(Async::NotReady) => {
+                            self.state = State::Send(request, written);
+                            return Ok(Async::NotReady);
+                        }
+                        Ok(Async::Ready(connection)) => State::Read(connection.read_response()),
+                    }
+                }
+                State::Read(mut f) => match track!(f.poll()) {
+                    Err(e) => match self.retry.on_error(&e, f.is_started()) {
+                        None => return Err(e),
+                        Some(delay) => self.next_attempt(delay),
+                    },
+                    Ok(Async::NotReady) => {
+                        self.state = State::Read(f);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(response)) => {
+                        let code = response.status().code();
+                        match self.retry.on_response(code, response.headers()) {
+                            None => return Ok(Async::Ready(response)),
+                            Some(delay) => self.next_attempt(delay),
+                        }
+                    }
+                },
+                State::Done => panic!("Cannot poll SendRequest twice"),
+            };
+            self.state = next;
+        }
+    }
+}
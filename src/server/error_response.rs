@@ -0,0 +1,178 @@
+use std::io;
+use std::mem;
+use futures::{Async, Future, Poll};
+
+use {Error, Status, TransportStream};
+use header::ContentLength;
+use util;
+use super::{Connection, Response};
+
+/// The format of the body of an `ErrorResponse` made by `ErrorResponse::from_error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorFormat {
+    /// No body.
+    Empty,
+
+    /// A `text/plain` body which consists of the status code and the reason phrase.
+    Text,
+
+    /// An `application/problem+json` body ([RFC 7807](https://tools.ietf.org/html/rfc7807)).
+    Json,
+}
+
+/// A response which reports an error to the client.
+///
+/// The response is written with the `Connection: close` header,
+/// and the connection is closed after that (see `Connection::write_error_response`).
+///
+/// # Examples
+///
+/// ```
+/// use miasht::{Error, Status};
+/// use miasht::server::{ErrorFormat, ErrorResponse};
+///
+/// let error = Error::from(Status::BadRequest);
+/// let response = ErrorResponse::from_error(&error, ErrorFormat::Json);
+/// assert_eq!(response.status(), Status::BadRequest);
+/// assert_eq!(response.content_type(), Some("application/problem+json"));
+/// assert_eq!(
+///     response.body(),
+///     br#"{"type":"about:blank","title":"Bad Request","status":400}"#
+/// );
+///
+/// // Custom error page
+/// let response = ErrorResponse::new(Status::NotFound)
+///     .with_body("text/html", b"<h1>Not Found</h1>".to_vec());
+/// assert_eq!(response.content_type(), Some("text/html"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ErrorResponse {
+    status: Status,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+impl ErrorResponse {
+    /// Makes a new `ErrorResponse` instance which has `status` and no body.
+    pub fn new(status: Status) -> Self {
+        ErrorResponse {
+            status,
+            content_type: None,
+            body: Vec::new(),
+        }
+    }
+
+    /// Makes a response which reports `error` in `format`.
+    ///
+    /// The status is the kind of `error` if it is a `4xx` or `5xx` status,
+    /// otherwise `Status::InternalServerError`.
+    /// The details of `error` are not included in the body.
+    pub fn from_error(error: &Error, format: ErrorFormat) -> Self {
+        let status = *error.kind();
+        let status = if status.is_client_error() || status.is_server_error() {
+            status
+        } else {
+            Status::InternalServerError
+        };
+        let response = ErrorResponse::new(status);
+        match format {
+            ErrorFormat::Empty => response,
+            ErrorFormat::Text => {
+                let body = format!("{}\n", status);
+                response.with_body("text/plain; charset=utf-8", body.into_bytes())
+            }
+            ErrorFormat::Json => {
+                let body = format!(
+                    r#"{{"type":"about:blank","title":"{}","status":{}}}"#,
+                    status.reason_phrase(),
+                    status.code()
+                );
+                response.with_body("application/problem+json", body.into_bytes())
+            }
+        }
+    }
+
+    /// Sets the body and its content type.
+    pub fn with_body(mut self, content_type: &str, body: Vec<u8>) -> Self {
+        self.content_type = Some(content_type.to_owned());
+        self.body = body;
+        self
+    }
+
+    /// Returns the status of the response.
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Returns the content type of the body.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Returns the body of the response.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+pub fn write<T>(mut connection: Connection<T>, response: ErrorResponse) -> WriteErrorResponse<T>
+where
+    T: TransportStream,
+{
+    // The rest of the request is never read.
+    connection.inner.discard_unread_bytes();
+
+    let mut builder = connection.build_response(response.status);
+    builder.add_raw_header("Connection", b"close");
+    if let Some(ref content_type) = response.content_type {
+        builder.add_raw_header("Content-Type", content_type.as_bytes());
+    }
+    builder.add_header(&ContentLength(response.body.len() as u64));
+    WriteErrorResponse {
+        state: State::Write(builder.finish(), response.body, 0),
+    }
+}
+
+/// A future which writes an `ErrorResponse` and closes the connection.
+///
+/// This is created by `Connection::write_error_response`.
+#[derive(Debug)]
+pub struct WriteErrorResponse<T> {
+    state: State<T>,
+}
+impl<T: TransportStream> Future for WriteErrorResponse<T> {
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match mem::replace(&mut self.state, State::Done) {
+                State::Write(mut response, body, mut offset) => {
+                    match util::write_all(&mut response, &body, &mut offset) {
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            self.state = State::Write(response, body, offset);
+                            return Ok(Async::NotReady);
+                        }
+                        Err(e) => return Err(track!(Error::from(e))),
+                        Ok(()) => {}
+                    }
+                    State::Flush(response)
+                }
+                State::Flush(mut response) => {
+                    if let Async::NotReady = track!(response.poll())? {
+                        self.state = State::Flush(response);
+                        return Ok(Async::NotReady);
+                    }
+                    return Ok(Async::Ready(()));
+                }
+                State::Done => panic!("Cannot poll WriteErrorResponse twice"),
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[derive(Debug)]
+enum State<T> {
+    Write(Response<T>, Vec<u8>, usize),
+    Flush(Response<T>),
+    Done,
+}
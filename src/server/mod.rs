@@ -1,10 +1,13 @@
-pub use self::request::{ReadRequest, Request};
+pub use self::error_response::{ErrorFormat, ErrorResponse, WriteErrorResponse};
+pub use self::request::{ReadRequest, Request, RespondOnError};
 pub use self::response::{Response, ResponseBuilder};
 
 use {TransportStream, Version};
 use connection;
+use upgrade::Upgraded;
 use status::RawStatus;
 
+mod error_response;
 mod request;
 mod response;
 
@@ -30,15 +33,37 @@ impl<T: TransportStream> Connection<T> {
     pub fn read_request(self) -> ReadRequest<T> {
         ReadRequest::new(self)
     }
+    /// Starts building a response which has `status`.
+    ///
+    /// If `status` is invalid (see `RawStatus::validate`),
+    /// `500 Internal Server Error` is written instead.
     pub fn build_response<'a, S>(self, status: S) -> ResponseBuilder<T>
     where
         S: Into<RawStatus<'a>>,
     {
         response::builder(self, status.into())
     }
+
+    /// Writes `response` with the `Connection: close` header and closes the connection.
+    ///
+    /// The unread bytes of the connection are discarded.
+    pub fn write_error_response(self, response: ErrorResponse) -> WriteErrorResponse<T> {
+        error_response::write(self, response)
+    }
+
+    /// Returns the raw stream of the connection.
+    ///
+    /// Note that the bytes buffered in the connection are discarded.
+    /// Use `into_upgraded` to keep them.
     pub fn into_raw_stream(self) -> T {
         self.inner.stream
     }
+
+    /// Converts into the raw stream which also returns the bytes buffered in the connection.
+    pub fn into_upgraded(self) -> Upgraded<T> {
+        let (stream, buffered) = self.inner.into_parts();
+        Upgraded::new(stream, buffered)
+    }
 }
 impl<T> AsMut<connection::Connection<T>> for Connection<T> {
     fn as_mut(&mut self) -> &mut connection::Connection<T> {
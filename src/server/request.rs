@@ -1,12 +1,21 @@
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
 use std::io::{self, BufRead, Read};
+use std::str::FromStr;
 use httparse;
 use futures::{Async, Future, Poll};
+use trackable::error::ErrorKindExt;
 
 use {Error, Method, Status, Version};
 use {Metadata, TransportStream};
 use status::RawStatus;
-use header::Headers;
-use super::Connection;
+use conditional::{self, Validators};
+use header::{ContentLength, ContentType, Headers};
+use multipart::{self, Multipart};
+use query::{self, FormDecoder, ReadForm};
+use range::{self, Resolution};
+use super::{Connection, ErrorResponse, WriteErrorResponse};
 
 #[derive(Debug)]
 pub struct ReadRequest<T>(Option<Connection<T>>);
@@ -15,6 +24,44 @@ impl<T: TransportStream> ReadRequest<T> {
         connection.inner.buffer.enter_read_phase();
         ReadRequest(Some(connection))
     }
+
+    /// Makes a future which responds to the client if reading the request fails.
+    ///
+    /// On failure, `handler` is called with the error and the returned response is written
+    /// (see `Connection::write_error_response`), then the future fails with the error.
+    /// If the error is caused by the connection itself (i.e., an I/O error), nothing is written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate miasht;
+    /// use futures::Future;
+    /// use miasht::{Error, TransportStream};
+    /// use miasht::server::{Connection, ErrorFormat, ErrorResponse};
+    ///
+    /// fn serve<T: TransportStream + 'static>(connection: Connection<T>) -> Box<dyn Future<Item = (), Error = Error>> {
+    ///     let future = connection
+    ///         .read_request()
+    ///         .respond_on_error(|e| ErrorResponse::from_error(e, ErrorFormat::Text))
+    ///         .and_then(|request| {
+    ///             println!("{} {}", request.method(), request.path());
+    ///             Ok(())
+    ///         });
+    ///     Box::new(future)
+    /// }
+    /// # fn main() {}
+    /// ```
+    pub fn respond_on_error<H>(self, handler: H) -> RespondOnError<T, H>
+    where
+        H: FnMut(&Error) -> ErrorResponse,
+    {
+        RespondOnError {
+            read: self,
+            handler,
+            writing: None,
+        }
+    }
 }
 impl<T: TransportStream> Future for ReadRequest<T> {
     type Item = Request<T>;
@@ -23,43 +70,100 @@ impl<T: TransportStream> Future for ReadRequest<T> {
         let mut connection = self.0.take().expect("Cannot poll ReadRequest twice");
         let (bytes, headers) = unsafe { connection.inner.buffer_and_headers() };
         let mut req = httparse::Request::new(headers);
-        if let httparse::Status::Complete(body_offset) =
-            track!(req.parse(bytes).map_err(Error::from))?
-        {
-            connection.inner.buffer.consume(body_offset);
-            let version = if req.version.unwrap() == 0 {
-                Version::Http1_0
-            } else {
-                debug_assert_eq!(req.version.unwrap(), 1);
-                Version::Http1_1
-            };
-            let method = if let Some(method) = Method::try_from_str(req.method.unwrap()) {
-                method
+        let parsed = track!(req.parse(bytes).map_err(Error::from)).and_then(|status| {
+            if let httparse::Status::Complete(_) = status {
+                let method: Method = track!(req.method.unwrap().parse())?;
+                Ok((status, Some(method)))
             } else {
-                track_panic!(
-                    Status::BadRequest,
-                    "Unknown HTTP method: {}",
-                    req.method.unwrap().to_string()
-                );
-            };
-            Ok(Async::Ready(Request {
-                version: version,
-                path: req.path.unwrap(),
-                method: method,
-                headers: Headers::new(req.headers),
-                connection: connection,
-            }))
-        } else {
-            let filled = track!(connection.inner.fill_buffer().map_err(Error::from))?;
-            self.0 = Some(connection);
-            if filled {
+                Ok((status, None))
+            }
+        });
+        match parsed {
+            Err(e) => {
+                self.0 = Some(connection);
+                Err(e)
+            }
+            Ok((httparse::Status::Complete(body_offset), Some(method))) => {
+                connection.inner.buffer.consume(body_offset);
+                let version = if req.version.unwrap() == 0 {
+                    Version::Http1_0
+                } else {
+                    debug_assert_eq!(req.version.unwrap(), 1);
+                    Version::Http1_1
+                };
+                Ok(Async::Ready(Request {
+                    version: version,
+                    path: req.path.unwrap(),
+                    method: method,
+                    headers: Headers::new(req.headers),
+                    connection: connection,
+                }))
+            }
+            Ok(_) if connection.inner.buffer.is_full() => {
+                // Without a line break, the request line itself is too long.
+                let status = if bytes.contains(&b'\n') {
+                    Status::RequestHeaderFieldsTooLarge
+                } else {
+                    Status::UriTooLong
+                };
+                self.0 = Some(connection);
+                track_panic!(status, "The request head exceeds the buffer: size={}", bytes.len())
+            }
+            Ok(_) => {
+                let filled = connection.inner.fill_buffer().map_err(Error::from);
+                self.0 = Some(connection);
+                if track!(filled)? {
+                    self.poll()
+                } else {
+                    Ok(Async::NotReady)
+                }
+            }
+        }
+    }
+}
+
+/// A future which reads a request and responds to the client on failure.
+///
+/// This is created by `ReadRequest::respond_on_error`.
+pub struct RespondOnError<T, H> {
+    read: ReadRequest<T>,
+    handler: H,
+    writing: Option<(WriteErrorResponse<T>, Error)>,
+}
+impl<T: TransportStream, H> Future for RespondOnError<T, H>
+where
+    H: FnMut(&Error) -> ErrorResponse,
+{
+    type Item = Request<T>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some((mut writing, error)) = self.writing.take() {
+            // Failures of writing the response are ignored in favor of the original error.
+            if let Ok(Async::NotReady) = writing.poll() {
+                self.writing = Some((writing, error));
+                return Ok(Async::NotReady);
+            }
+            return Err(track!(error));
+        }
+        match self.read.poll() {
+            Err(e) => {
+                let connection = self.read.0.take().expect("Never fails");
+                if e.concrete_cause::<io::Error>().is_some() {
+                    return Err(track!(e));
+                }
+                let response = (self.handler)(&e);
+                self.writing = Some((connection.write_error_response(response), e));
                 self.poll()
-            } else {
-                Ok(Async::NotReady)
             }
+            polled => polled,
         }
     }
 }
+impl<T: fmt::Debug, H> fmt::Debug for RespondOnError<T, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RespondOnError {{ read: {:?}, writing: {:?}, .. }}", self.read, self.writing)
+    }
+}
 
 #[derive(Debug)]
 pub struct Request<T> {
@@ -82,18 +186,181 @@ impl<T> Request<T> {
     pub fn headers(&self) -> &Headers {
         &self.headers
     }
+
+    /// Returns the query part of the request target (without the leading `'?'`).
+    pub fn query_string(&self) -> Option<&str> {
+        self.path.find('?').map(|i| &self.path[i + 1..])
+    }
+
+    /// Returns an iterator over the decoded key-value pairs of the query string.
+    pub fn query(&self) -> query::Pairs<'_> {
+        query::parse(self.query_string().unwrap_or(""))
+    }
+
+    /// Returns the decoded value of the query parameter named `key`.
+    pub fn query_value(&self, key: &str) -> Option<Cow<'_, str>> {
+        self.query().get(key)
+    }
+
+    /// Parses the value of the query parameter named `key` as `V`.
+    ///
+    /// If the value is malformed, `Status::BadRequest` error will be returned.
+    pub fn parse_query_value<V>(&self, key: &str) -> ::Result<Option<V>>
+    where
+        V: FromStr,
+        V::Err: error::Error + Send + Sync + 'static,
+    {
+        if let Some(value) = self.query_value(key) {
+            let value = track!(
+                value
+                    .parse()
+                    .map_err(|e| Error::from(Status::BadRequest.cause(e)))
+            )?;
+            Ok(Some(value))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Evaluates the conditional headers of this request against `validators`.
+    ///
+    /// See `conditional::evaluate` for more details.
+    pub fn evaluate_preconditions(&self, validators: &Validators) -> Status {
+        conditional::evaluate(self.method, &self.headers, validators)
+    }
+
+    /// Evaluates the `Range` and `If-Range` headers of this request
+    /// for a representation of `length` bytes.
+    ///
+    /// See `range::evaluate` for more details.
+    pub fn evaluate_range(&self, validators: &Validators, length: u64) -> Resolution {
+        range::evaluate(self.method, &self.headers, validators, length)
+    }
+
     pub fn finish(mut self) -> Connection<T> {
         self.connection.version = self.version;
         self.connection
     }
 }
+impl<T: TransportStream> Request<T> {
+    /// Makes a future which reads the `application/x-www-form-urlencoded` body of this request.
+    ///
+    /// The length of the body is determined by the `Content-Length` header.
+    /// If the request has neither `Content-Length` nor `Transfer-Encoding`, the body is empty
+    /// (RFC 7230 §3.3.3). If it only has `Transfer-Encoding`,
+    /// `Status::LengthRequired` error will be returned.
+    pub fn into_form_reader(self, max_size: u64) -> ::Result<ReadForm<Self>> {
+        let content_length = track!(self.headers.parse::<ContentLength>().map_err(Error::from))?;
+        let content_length = match content_length {
+            Some(h) => h.0,
+            None => {
+                track_assert!(
+                    self.headers.get("Transfer-Encoding").is_none(),
+                    Status::LengthRequired
+                );
+                0
+            }
+        };
+        track_assert!(
+            content_length <= max_size,
+            Status::PayloadTooLarge,
+            "content_length={}, max_size={}",
+            content_length,
+            max_size
+        );
+        Ok(ReadForm::new(self, Some(content_length), FormDecoder::new(max_size)))
+    }
+
+    /// Makes a streaming parser for the `multipart/*` body of this request.
+    ///
+    /// The boundary is taken from the `Content-Type` header and
+    /// the body is limited to the length specified by the `Content-Length` header.
+    /// If the request has no `Content-Length` header, `Status::LengthRequired` error will be returned.
+    pub fn into_multipart(self, limits: multipart::Limits) -> ::Result<Multipart<io::Take<Self>>> {
+        let content_length = track!(self.headers.parse::<ContentLength>().map_err(Error::from))?;
+        let content_length = track_assert_some!(content_length, Status::LengthRequired).0;
+        track_assert!(
+            content_length <= limits.max_total_size,
+            Status::PayloadTooLarge,
+            "content_length={}, max_total_size={}",
+            content_length,
+            limits.max_total_size
+        );
+        let boundary = {
+            let content_type = track!(self.headers.parse::<ContentType>().map_err(Error::from))?;
+            let content_type = track_assert_some!(content_type, Status::UnsupportedMediaType);
+            let boundary = track_assert_some!(
+                multipart::boundary(&content_type),
+                Status::UnsupportedMediaType,
+                "Not a multipart body: content_type={:?}",
+                content_type.0
+            );
+            boundary.to_owned()
+        };
+        Ok(Multipart::with_limits(
+            self.take(content_length),
+            &boundary,
+            limits,
+        ))
+    }
+}
 impl<T: TransportStream> Read for Request<T> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if !self.connection.inner.buffer.is_empty() {
-            self.connection.inner.buffer.read(buf)
-        } else {
-            self.connection.inner.stream.read(buf)
-        }
+        self.connection.inner.read_body(buf)
+    }
+}
+/// The body can be read without invalidating the request line and the headers.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate miasht;
+/// use std::io::{self, Cursor, Read, Write};
+/// use futures::Future;
+/// use miasht::TransportStream;
+/// use miasht::chunked::{ChunkedDecoder, ChunkedEncoder};
+///
+/// struct Mock(Cursor<Vec<u8>>);
+/// impl Read for Mock {
+///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+///         self.0.read(buf)
+///     }
+/// }
+/// impl Write for Mock {
+///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+///         Ok(buf.len())
+///     }
+///     fn flush(&mut self) -> io::Result<()> {
+///         Ok(())
+///     }
+/// }
+/// impl TransportStream for Mock {}
+///
+/// # fn main() {
+/// let body = vec![b'a'; 5000];
+/// let mut encoder = ChunkedEncoder::new(Vec::new());
+/// encoder.write_all(&body).unwrap();
+/// let chunked = encoder.finish().unwrap();
+///
+/// let mut bytes = b"POST /path?q=1 HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+/// bytes.extend_from_slice(&chunked);
+/// let connection = miasht::server::Connection::new(Mock(Cursor::new(bytes)), 1024, 8192, 32);
+/// let mut request = connection.read_request().wait().unwrap();
+///
+/// let mut decoded = Vec::new();
+/// ChunkedDecoder::new(&mut request).read_to_end(&mut decoded).unwrap();
+/// assert_eq!(decoded, body);
+/// assert_eq!(request.path(), "/path?q=1");
+/// assert_eq!(request.headers().get("Host"), Some(&b"example.com"[..]));
+/// # }
+/// ```
+impl<T: TransportStream> BufRead for Request<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.connection.inner.fill_body_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        self.connection.inner.consume_body(amt)
     }
 }
 impl<T> Metadata for Request<T> {
@@ -10,6 +10,12 @@ pub fn builder<T>(mut connection: Connection<T>, status: RawStatus) -> ResponseB
 where
     T: TransportStream,
 {
+    // An invalid status line would corrupt the response (or split it into two).
+    let status = if status.validate().is_ok() {
+        status
+    } else {
+        Status::InternalServerError.as_raw()
+    };
     connection.inner.buffer.enter_write_phase();
     let _ = write!(
         connection.inner.buffer,
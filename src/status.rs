@@ -18,71 +18,57 @@ impl<'a> RawStatus<'a> {
     pub fn reason(&self) -> &str {
         self.reason
     }
+    /// Converts to the corresponding `Status` (see `Status::from_code`).
+    ///
+    /// The reason phrase is ignored.
     pub fn normalize(&self) -> Option<Status> {
-        Some(match self.code {
-            100 => Status::Continue,
-            101 => Status::SwitchingProtocols,
-            102 => Status::Processing,
+        Status::from_code(self.code)
+    }
 
-            200 => Status::Ok,
-            201 => Status::Created,
-            202 => Status::Accepted,
-            203 => Status::NonAuthoritativeInformation,
-            204 => Status::NoContent,
-            205 => Status::ResetContent,
-            206 => Status::PartialContent,
-            207 => Status::MultiStatus,
-            208 => Status::AlreadyReported,
-            226 => Status::ImUsed,
+    /// Returns `true` if the status is `1xx`, otherwise `false`.
+    pub fn is_informational(&self) -> bool {
+        self.code / 100 == 1
+    }
 
-            300 => Status::MultipleChoices,
-            301 => Status::MovedPermanently,
-            302 => Status::Found,
-            303 => Status::SeeOther,
-            304 => Status::NotModified,
-            305 => Status::UseProxy,
-            307 => Status::TemporaryRedirect,
-            308 => Status::PermanentRedirect,
+    /// Returns `true` if the status is `2xx`, otherwise `false`.
+    pub fn is_success(&self) -> bool {
+        self.code / 100 == 2
+    }
 
-            400 => Status::BadRequest,
-            401 => Status::Unauthorized,
-            402 => Status::PaymentRequired,
-            403 => Status::Forbidden,
-            404 => Status::NotFound,
-            405 => Status::MethodNotAllowed,
-            406 => Status::NotAcceptable,
-            407 => Status::ProxyAuthenticationRequired,
-            408 => Status::RequestTimeout,
-            409 => Status::Conflict,
-            410 => Status::Gone,
-            411 => Status::LengthRequired,
-            412 => Status::PreconditionFailed,
-            413 => Status::PayloadTooLarge,
-            414 => Status::UriTooLong,
-            415 => Status::UnsupportedMediaType,
-            416 => Status::RangeNotSatisfiable,
-            417 => Status::ExceptionFailed,
-            418 => Status::ImATeapot,
-            421 => Status::MisdirectedRequest,
-            422 => Status::UnprocessableEntity,
-            423 => Status::Locked,
-            424 => Status::FailedDependency,
-            426 => Status::UpgradeRequired,
-            451 => Status::UnavailableForLegalReasons,
+    /// Returns `true` if the status is `3xx`, otherwise `false`.
+    pub fn is_redirection(&self) -> bool {
+        self.code / 100 == 3
+    }
 
-            500 => Status::InternalServerError,
-            501 => Status::NotImplemented,
-            502 => Status::BadGateway,
-            503 => Status::ServiceUnavailable,
-            504 => Status::GatewayTimeout,
-            505 => Status::HttpVersionNotSupported,
-            506 => Status::VariantAlsoNegotiates,
-            507 => Status::InsufficientStorage,
-            508 => Status::LoopDetected,
-            509 => Status::BandwidthLimitExceeded,
-            510 => Status::NotExtended,
-            _ => return None,
-        })
+    /// Returns `true` if the status is `4xx`, otherwise `false`.
+    pub fn is_client_error(&self) -> bool {
+        self.code / 100 == 4
+    }
+
+    /// Returns `true` if the status is `5xx`, otherwise `false`.
+    pub fn is_server_error(&self) -> bool {
+        self.code / 100 == 5
+    }
+
+    /// Checks that the status can be written in a status line.
+    ///
+    /// The code must be in the range `100..=999` and
+    /// the reason phrase must not contain control characters except for HTAB.
+    /// Otherwise, `Status::InternalServerError` error will be returned.
+    pub fn validate(&self) -> ::Result<()> {
+        track_assert!(
+            (100..1000).contains(&self.code),
+            Status::InternalServerError,
+            "Invalid status code: {}",
+            self.code
+        );
+        track_assert!(
+            self.reason.bytes().all(|b| b == b'\t' || (b >= 0x20 && b != 0x7F)),
+            Status::InternalServerError,
+            "Invalid reason phrase: {:?}",
+            self.reason
+        );
+        Ok(())
     }
 }
 impl<'a> fmt::Display for RawStatus<'a> {
@@ -107,6 +93,7 @@ pub enum Status {
     Continue,
     SwitchingProtocols,
     Processing,
+    EarlyHints,
 
     // 2xx
     Ok,
@@ -148,13 +135,17 @@ pub enum Status {
     UriTooLong,
     UnsupportedMediaType,
     RangeNotSatisfiable,
-    ExceptionFailed,
+    ExpectationFailed,
     ImATeapot,
     MisdirectedRequest,
     UnprocessableEntity,
     Locked,
     FailedDependency,
+    TooEarly,
     UpgradeRequired,
+    PreconditionRequired,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
     UnavailableForLegalReasons,
 
     // 5xx
@@ -169,6 +160,25 @@ pub enum Status {
     LoopDetected,
     BandwidthLimitExceeded,
     NotExtended,
+    NetworkAuthenticationRequired,
+
+    /// A status code which has no variant of its own (e.g., an unregistered code).
+    ///
+    /// This must not hold a code which has a variant of its own, or a code out of
+    /// the range `100..=999`: such a value is not equal to the status of the same code
+    /// (e.g., `Status::Other(404) != Status::NotFound`).
+    /// Use `Status::from_code` to make a `Status` from an arbitrary code.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::Status;
+    ///
+    /// assert_ne!(Status::Other(404), Status::NotFound);
+    /// assert_eq!(Status::from_code(404), Some(Status::NotFound));
+    /// assert_eq!(Status::from_code(499), Some(Status::Other(499)));
+    /// ```
+    Other(u16),
 }
 impl fmt::Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -176,6 +186,128 @@ impl fmt::Display for Status {
     }
 }
 impl Status {
+    /// The former name of `Status::ExpectationFailed`.
+    #[deprecated(note = "Use `Status::ExpectationFailed` instead")]
+    #[allow(non_upper_case_globals)]
+    pub const ExceptionFailed: Status = Status::ExpectationFailed;
+
+    /// Returns the status which has `code`.
+    ///
+    /// Codes which have no variant of their own are converted to `Status::Other`.
+    /// If `code` is not in the range `100..=999`, this returns `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::Status;
+    ///
+    /// assert_eq!(Status::from_code(404), Some(Status::NotFound));
+    /// assert_eq!(Status::from_code(599), Some(Status::Other(599)));
+    /// assert_eq!(Status::from_code(1000), None);
+    ///
+    /// let status = Status::from_code(429).unwrap();
+    /// assert!(status.is_client_error());
+    /// assert_eq!(status.to_string(), "429 Too Many Requests");
+    /// ```
+    pub fn from_code(code: u16) -> Option<Self> {
+        Some(match code {
+            100 => Status::Continue,
+            101 => Status::SwitchingProtocols,
+            102 => Status::Processing,
+            103 => Status::EarlyHints,
+
+            200 => Status::Ok,
+            201 => Status::Created,
+            202 => Status::Accepted,
+            203 => Status::NonAuthoritativeInformation,
+            204 => Status::NoContent,
+            205 => Status::ResetContent,
+            206 => Status::PartialContent,
+            207 => Status::MultiStatus,
+            208 => Status::AlreadyReported,
+            226 => Status::ImUsed,
+
+            300 => Status::MultipleChoices,
+            301 => Status::MovedPermanently,
+            302 => Status::Found,
+            303 => Status::SeeOther,
+            304 => Status::NotModified,
+            305 => Status::UseProxy,
+            307 => Status::TemporaryRedirect,
+            308 => Status::PermanentRedirect,
+
+            400 => Status::BadRequest,
+            401 => Status::Unauthorized,
+            402 => Status::PaymentRequired,
+            403 => Status::Forbidden,
+            404 => Status::NotFound,
+            405 => Status::MethodNotAllowed,
+            406 => Status::NotAcceptable,
+            407 => Status::ProxyAuthenticationRequired,
+            408 => Status::RequestTimeout,
+            409 => Status::Conflict,
+            410 => Status::Gone,
+            411 => Status::LengthRequired,
+            412 => Status::PreconditionFailed,
+            413 => Status::PayloadTooLarge,
+            414 => Status::UriTooLong,
+            415 => Status::UnsupportedMediaType,
+            416 => Status::RangeNotSatisfiable,
+            417 => Status::ExpectationFailed,
+            418 => Status::ImATeapot,
+            421 => Status::MisdirectedRequest,
+            422 => Status::UnprocessableEntity,
+            423 => Status::Locked,
+            424 => Status::FailedDependency,
+            425 => Status::TooEarly,
+            426 => Status::UpgradeRequired,
+            428 => Status::PreconditionRequired,
+            429 => Status::TooManyRequests,
+            431 => Status::RequestHeaderFieldsTooLarge,
+            451 => Status::UnavailableForLegalReasons,
+
+            500 => Status::InternalServerError,
+            501 => Status::NotImplemented,
+            502 => Status::BadGateway,
+            503 => Status::ServiceUnavailable,
+            504 => Status::GatewayTimeout,
+            505 => Status::HttpVersionNotSupported,
+            506 => Status::VariantAlsoNegotiates,
+            507 => Status::InsufficientStorage,
+            508 => Status::LoopDetected,
+            509 => Status::BandwidthLimitExceeded,
+            510 => Status::NotExtended,
+            511 => Status::NetworkAuthenticationRequired,
+            _ if (100..1000).contains(&code) => Status::Other(code),
+            _ => return None,
+        })
+    }
+
+    /// Returns `true` if the status is `1xx`, otherwise `false`.
+    pub fn is_informational(&self) -> bool {
+        self.as_raw().is_informational()
+    }
+
+    /// Returns `true` if the status is `2xx`, otherwise `false`.
+    pub fn is_success(&self) -> bool {
+        self.as_raw().is_success()
+    }
+
+    /// Returns `true` if the status is `3xx`, otherwise `false`.
+    pub fn is_redirection(&self) -> bool {
+        self.as_raw().is_redirection()
+    }
+
+    /// Returns `true` if the status is `4xx`, otherwise `false`.
+    pub fn is_client_error(&self) -> bool {
+        self.as_raw().is_client_error()
+    }
+
+    /// Returns `true` if the status is `5xx`, otherwise `false`.
+    pub fn is_server_error(&self) -> bool {
+        self.as_raw().is_server_error()
+    }
+
     pub fn as_raw(&self) -> RawStatus<'static> {
         RawStatus::new(self.code(), self.reason_phrase())
     }
@@ -184,6 +316,7 @@ impl Status {
             Status::Continue => 100,
             Status::SwitchingProtocols => 101,
             Status::Processing => 102,
+            Status::EarlyHints => 103,
 
             Status::Ok => 200,
             Status::Created => 201,
@@ -222,13 +355,17 @@ impl Status {
             Status::UriTooLong => 414,
             Status::UnsupportedMediaType => 415,
             Status::RangeNotSatisfiable => 416,
-            Status::ExceptionFailed => 417,
+            Status::ExpectationFailed => 417,
             Status::ImATeapot => 418,
             Status::MisdirectedRequest => 421,
             Status::UnprocessableEntity => 422,
             Status::Locked => 423,
             Status::FailedDependency => 424,
+            Status::TooEarly => 425,
             Status::UpgradeRequired => 426,
+            Status::PreconditionRequired => 428,
+            Status::TooManyRequests => 429,
+            Status::RequestHeaderFieldsTooLarge => 431,
             Status::UnavailableForLegalReasons => 451,
 
             Status::InternalServerError => 500,
@@ -242,6 +379,8 @@ impl Status {
             Status::LoopDetected => 508,
             Status::BandwidthLimitExceeded => 509,
             Status::NotExtended => 510,
+            Status::NetworkAuthenticationRequired => 511,
+            Status::Other(code) => code,
         }
     }
     pub fn reason_phrase(&self) -> &'static str {
@@ -249,6 +388,7 @@ impl Status {
             Status::Continue => "Continue",
             Status::SwitchingProtocols => "Switching Protocols",
             Status::Processing => "Processing",
+            Status::EarlyHints => "Early Hints",
 
             Status::Ok => "OK",
             Status::Created => "Created",
@@ -287,13 +427,17 @@ impl Status {
             Status::UriTooLong => "URI Too Long",
             Status::UnsupportedMediaType => "Unsupported Media Type",
             Status::RangeNotSatisfiable => "Range Not Satisfiable",
-            Status::ExceptionFailed => "Expectation Failed",
+            Status::ExpectationFailed => "Expectation Failed",
             Status::ImATeapot => "I'm a teapot",
             Status::MisdirectedRequest => "Misdirected Request",
-            Status::UnprocessableEntity => "Unporcessable Entity",
+            Status::UnprocessableEntity => "Unprocessable Entity",
             Status::Locked => "Locked",
             Status::FailedDependency => "Failed Dependency",
+            Status::TooEarly => "Too Early",
             Status::UpgradeRequired => "Upgrade Required",
+            Status::PreconditionRequired => "Precondition Required",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
             Status::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
 
             Status::InternalServerError => "Internal Server Error",
@@ -307,6 +451,8 @@ impl Status {
             Status::LoopDetected => "Loop Detected",
             Status::BandwidthLimitExceeded => "Bandwidth Limit Exceeded",
             Status::NotExtended => "Not Extended",
+            Status::NetworkAuthenticationRequired => "Network Authentication Required",
+            Status::Other(_) => "",
         }
     }
 }
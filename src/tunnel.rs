@@ -0,0 +1,206 @@
+//! HTTP tunneling with the `CONNECT` method ([RFC 7231 §4.3.6](https://tools.ietf.org/html/rfc7231#section-4.3.6)).
+//!
+//! A proxy accepts a `CONNECT` request with `establish` and relays the bytes
+//! between the resulting stream and the target.
+//! A client sends a `CONNECT` request with `Connect` and uses the resulting stream
+//! as the transport of a new `client::Connection`.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # extern crate futures;
+//! # extern crate miasht;
+//! use futures::Future;
+//! use miasht::{Method, TransportStream};
+//! use miasht::client::Connection;
+//! use miasht::tunnel::{self, Authority, Connect};
+//!
+//! fn get_via_proxy<T: TransportStream>(proxy: Connection<T>) {
+//!     let authority: Authority = "example.com:80".parse().unwrap();
+//!     let request = tunnel::request(proxy, &authority);
+//!     let stream = Connect::new(request.finish()).wait().unwrap();
+//!
+//!     let mut request = Connection::new(stream, 1024, 8192, 32).build_request(Method::Get, "/");
+//!     request.add_raw_header("Host", b"example.com");
+//!     let _response = request.finish().and_then(|c| c.read_response()).wait().unwrap();
+//! }
+//! # fn main() {}
+//! ```
+use std::fmt;
+use std::str::FromStr;
+use futures::{Async, Future, Poll};
+
+use {Error, Method, Status, TransportStream};
+use client;
+use server;
+use status::RawStatus;
+use upgrade::{UpgradeResponse, Upgraded};
+
+/// The authority-form request target (`host:port`).
+///
+/// # Examples
+///
+/// ```
+/// use miasht::tunnel::Authority;
+///
+/// let authority: Authority = "example.com:443".parse().unwrap();
+/// assert_eq!(authority.host, "example.com");
+/// assert_eq!(authority.port, 443);
+///
+/// let authority: Authority = "[::1]:8080".parse().unwrap();
+/// assert_eq!(authority.host, "::1");
+/// assert_eq!(authority.to_string(), "[::1]:8080");
+///
+/// assert!("example.com".parse::<Authority>().is_err());
+/// assert!("/index.html".parse::<Authority>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Authority {
+    /// The host name or IP address (IPv6 addresses have no brackets).
+    pub host: String,
+    pub port: u16,
+}
+impl Authority {
+    /// Makes a new `Authority` instance.
+    pub fn new(host: &str, port: u16) -> Self {
+        Authority {
+            host: host.to_owned(),
+            port,
+        }
+    }
+}
+impl fmt::Display for Authority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+impl FromStr for Authority {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let i = track_assert_some!(s.rfind(':'), Status::BadRequest, "No port: {:?}", s);
+        let (host, port) = (&s[..i], &s[i + 1..]);
+        track_assert!(
+            !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()),
+            Status::BadRequest,
+            "Malformed port: {:?}",
+            s
+        );
+        let port = track_assert_some!(port.parse().ok(), Status::BadRequest, "Malformed port: {:?}", s);
+
+        let host = if host.starts_with('[') && host.ends_with(']') {
+            let host = &host[1..host.len() - 1];
+            track_assert!(
+                host.contains(':') && host.bytes().all(|b| b.is_ascii_hexdigit() || b == b':' || b == b'.'),
+                Status::BadRequest,
+                "Malformed IPv6 address: {:?}",
+                s
+            );
+            host
+        } else {
+            track_assert!(
+                !host.is_empty() && host.bytes().all(is_reg_name_char),
+                Status::BadRequest,
+                "Malformed host: {:?}",
+                s
+            );
+            host
+        };
+        Ok(Authority::new(host, port))
+    }
+}
+
+/// Returns the target of the `CONNECT` request `request`.
+///
+/// If the method of the request is not `CONNECT`, `Status::MethodNotAllowed` error will be returned.
+/// If the request target is not in the authority-form, `Status::BadRequest` error will be returned.
+pub fn target<T>(request: &server::Request<T>) -> ::Result<Authority> {
+    track_assert_eq!(request.method(), Method::Connect, Status::MethodNotAllowed);
+    track!(request.path().parse())
+}
+
+/// Accepts the `CONNECT` request `request`.
+///
+/// The resulting future sends the `200 Connection Established` response
+/// and yields the stream of the tunnel.
+/// It contains the bytes which the client has sent after the request.
+///
+/// The connection to the target should be established before calling this.
+/// If it fails, an error response (e.g., `502 Bad Gateway`) should be sent instead.
+pub fn establish<T>(request: server::Request<T>) -> UpgradeResponse<T>
+where
+    T: TransportStream,
+{
+    let status = RawStatus::new(200, "Connection Established");
+    UpgradeResponse::new(request.finish().build_response(status).finish())
+}
+
+/// Starts building the `CONNECT` request to `authority`.
+///
+/// The `Host` header has already been added.
+/// Additional headers (e.g., `Proxy-Authorization`) can be added to the returned builder.
+pub fn request<T>(connection: client::Connection<T>, authority: &Authority) -> client::RequestBuilder<T>
+where
+    T: TransportStream,
+{
+    let target = authority.to_string();
+    let mut request = connection.build_request(Method::Connect, &target);
+    request.add_raw_header("Host", target.as_bytes());
+    request
+}
+
+/// A future which sends a `CONNECT` request and yields the stream of the tunnel.
+///
+/// If the proxy responds with a non-`2xx` status, `Status::BadGateway` error will be returned.
+#[derive(Debug)]
+pub struct Connect<T> {
+    state: State<T>,
+}
+#[derive(Debug)]
+enum State<T> {
+    Send(client::Request<T>),
+    Read(client::ReadResponse<T>),
+    Done,
+}
+impl<T: TransportStream> Connect<T> {
+    /// Makes a new `Connect` future.
+    ///
+    /// `request` should have been built by `request` function.
+    pub fn new(request: client::Request<T>) -> Self {
+        Connect {
+            state: State::Send(request),
+        }
+    }
+}
+impl<T: TransportStream> Future for Connect<T> {
+    type Item = Upgraded<T>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Send(ref mut f) => State::Read(try_ready!(track!(f.poll())).read_response()),
+                State::Read(ref mut f) => {
+                    let response = try_ready!(track!(f.poll()));
+                    self.state = State::Done;
+                    let code = response.status().code();
+                    track_assert!(
+                        (200..300).contains(&code),
+                        Status::BadGateway,
+                        "CONNECT failed: status={}",
+                        code
+                    );
+                    return Ok(Async::Ready(response.finish().into_upgraded()));
+                }
+                State::Done => panic!("Cannot poll Connect twice"),
+            };
+            self.state = next;
+        }
+    }
+}
+
+fn is_reg_name_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"-._~%!$&'()*+,;=".contains(&b)
+}
@@ -0,0 +1,281 @@
+//! Protocol upgrade ([RFC 7230 §6.7](https://tools.ietf.org/html/rfc7230#section-6.7)).
+//!
+//! After the `101 Switching Protocols` response, the connection is taken over
+//! by another protocol. `Upgraded` is the raw stream of such a connection.
+//! It keeps the bytes which have been read into the buffer of the HTTP connection
+//! but not consumed yet (e.g., the first frames sent by the peer just after the handshake),
+//! so that nothing is lost.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # extern crate futures;
+//! # extern crate miasht;
+//! use std::io::Read;
+//! use futures::Future;
+//! use miasht::TransportStream;
+//! use miasht::server::Request;
+//! use miasht::upgrade;
+//!
+//! fn handle<T: TransportStream>(request: Request<T>) {
+//!     match upgrade::accept(request, "echo") {
+//!         Err(response) => {
+//!             // The request is not a valid upgrade request.
+//!             response.wait().unwrap();
+//!         }
+//!         Ok(builder) => {
+//!             let mut stream = builder.finish().wait().unwrap();
+//!             let mut buf = [0; 1024];
+//!             let _ = stream.read(&mut buf).unwrap();
+//!         }
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+use std::io::{self, Read, Write};
+use std::str;
+use futures::{Async, Future, Poll};
+
+use {Error, Status, TransportStream};
+use client;
+use header::{ContentLength, Header, Headers, HeadersMut};
+use server;
+use auth::is_token;
+
+/// Returns the protocols listed in the `Upgrade` header.
+///
+/// If the `Connection` header does not contain the `upgrade` option,
+/// the `Upgrade` header is ignored and an empty list is returned.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate httparse;
+/// # extern crate miasht;
+/// use miasht::header::Headers;
+/// use miasht::upgrade;
+///
+/// # fn main() {
+/// let fields = [
+///     httparse::Header { name: "Upgrade", value: b"h2c, websocket" },
+///     httparse::Header { name: "Connection", value: b"Upgrade, HTTP2-Settings" },
+/// ];
+/// assert_eq!(upgrade::protocols(&Headers::new(&fields)), ["h2c", "websocket"]);
+///
+/// let fields = [httparse::Header { name: "Upgrade", value: b"websocket" }];
+/// assert!(upgrade::protocols(&Headers::new(&fields)).is_empty());
+/// # }
+/// ```
+pub fn protocols<'a>(headers: &'a Headers) -> Vec<&'a str> {
+    if has_token(headers, "Connection", "upgrade") {
+        list(headers, "Upgrade")
+    } else {
+        Vec::new()
+    }
+}
+
+/// Returns `true` if `headers` request the upgrade to `protocol`, otherwise `false`.
+///
+/// The protocol names are compared case-insensitively.
+/// If `protocol` has no version (e.g., `"websocket"`), any version of the protocol matches.
+pub fn is_requested(headers: &Headers, protocol: &str) -> bool {
+    protocols(headers).iter().any(|p| matches(p, protocol))
+}
+
+/// Adds the `Upgrade` and `Connection` headers which request the upgrade to `protocols`.
+pub fn add_request_headers(headers: &mut HeadersMut, protocols: &[&str]) {
+    headers.add_raw_header("Upgrade", protocols.join(", ").as_bytes());
+    headers.add_raw_header("Connection", b"Upgrade");
+}
+
+/// Accepts the upgrade to `protocol` requested by `request`.
+///
+/// If succeeded, the builder of the `101 Switching Protocols` response is returned.
+/// Otherwise the error response is returned (it should be polled to be sent):
+/// `426 Upgrade Required` if the request does not ask for `protocol`,
+/// or `400 Bad Request` if the request has a body
+/// (the body would be indistinguishable from the data of the new protocol).
+pub fn accept<T>(request: server::Request<T>, protocol: &str) -> Result<UpgradeBuilder<T>, server::Response<T>>
+where
+    T: TransportStream,
+{
+    let status = if !is_requested(request.headers(), protocol) {
+        Some(Status::UpgradeRequired)
+    } else if has_body(request.headers()) {
+        Some(Status::BadRequest)
+    } else {
+        None
+    };
+    let connection = request.finish();
+    if let Some(status) = status {
+        let mut response = connection.build_response(status);
+        if status == Status::UpgradeRequired {
+            response.add_raw_header("Upgrade", protocol.as_bytes());
+            response.add_raw_header("Connection", b"Upgrade");
+        }
+        response.add_header(&ContentLength(0));
+        return Err(response.finish());
+    }
+
+    let mut response = connection.build_response(Status::SwitchingProtocols);
+    response.add_raw_header("Upgrade", protocol.as_bytes());
+    response.add_raw_header("Connection", b"Upgrade");
+    Ok(UpgradeBuilder(response))
+}
+
+/// Completes the upgrade to `protocol` with the response `response`.
+///
+/// If the status of the response is not `101 Switching Protocols` or
+/// the response switches to another protocol, `Status::BadGateway` error will be returned.
+pub fn complete<T>(response: client::Response<T>, protocol: &str) -> ::Result<Upgraded<T>>
+where
+    T: TransportStream,
+{
+    track_assert_eq!(response.status().code(), 101, Status::BadGateway);
+    {
+        let upgrade = list(response.headers(), "Upgrade");
+        track_assert!(
+            upgrade.iter().any(|p| matches(p, protocol)),
+            Status::BadGateway,
+            "Unexpected protocol: upgrade={:?}, expected={:?}",
+            upgrade,
+            protocol
+        );
+    }
+    Ok(response.finish().into_upgraded())
+}
+
+/// The builder of a `101 Switching Protocols` response.
+///
+/// The `Upgrade` and `Connection` headers have already been added.
+#[derive(Debug)]
+pub struct UpgradeBuilder<T>(server::ResponseBuilder<T>);
+impl<T: TransportStream> UpgradeBuilder<T> {
+    pub fn headers_mut(&mut self) -> HeadersMut<'_> {
+        self.0.headers_mut()
+    }
+    pub fn add_raw_header(&mut self, name: &str, value: &[u8]) -> &mut Self {
+        self.0.add_raw_header(name, value);
+        self
+    }
+    pub fn add_header<'a, H: Header<'a>>(&mut self, header: &H) -> &mut Self {
+        self.0.add_header(header);
+        self
+    }
+    pub fn finish(self) -> UpgradeResponse<T> {
+        UpgradeResponse(self.0.finish())
+    }
+}
+
+/// A future which sends a response switching protocols
+/// (`101 Switching Protocols`, or `2xx` for `CONNECT` requests) and yields the upgraded stream.
+#[derive(Debug)]
+pub struct UpgradeResponse<T>(server::Response<T>);
+impl<T> UpgradeResponse<T> {
+    pub(crate) fn new(response: server::Response<T>) -> Self {
+        UpgradeResponse(response)
+    }
+}
+impl<T: TransportStream> Future for UpgradeResponse<T> {
+    type Item = Upgraded<T>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let connection = try_ready!(track!(self.0.poll()));
+        Ok(Async::Ready(connection.into_upgraded()))
+    }
+}
+
+/// The raw stream of an upgraded connection.
+///
+/// Reading returns the buffered bytes first, then reads the stream.
+#[derive(Debug)]
+pub struct Upgraded<T> {
+    stream: T,
+    buffered: Vec<u8>,
+    offset: usize,
+}
+impl<T> Upgraded<T> {
+    /// Makes a new `Upgraded` instance.
+    ///
+    /// `buffered` is the bytes which have been read from `stream` but not consumed yet.
+    pub fn new(stream: T, buffered: Vec<u8>) -> Self {
+        Upgraded {
+            stream,
+            buffered,
+            offset: 0,
+        }
+    }
+
+    /// Returns the buffered bytes which have not been read yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffered[self.offset..]
+    }
+
+    /// Returns a reference to the inner stream.
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the inner stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
+
+    /// Decomposes into the inner stream and the buffered bytes which have not been read yet.
+    pub fn into_parts(mut self) -> (T, Vec<u8>) {
+        self.buffered.drain(..self.offset);
+        (self.stream, self.buffered)
+    }
+}
+impl<T: Read> Read for Upgraded<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset < self.buffered.len() {
+            let size = (&self.buffered[self.offset..]).read(buf)?;
+            self.offset += size;
+            Ok(size)
+        } else {
+            self.stream.read(buf)
+        }
+    }
+}
+impl<T: Write> Write for Upgraded<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+impl<T: TransportStream> TransportStream for Upgraded<T> {}
+
+fn matches(offered: &str, protocol: &str) -> bool {
+    if offered.eq_ignore_ascii_case(protocol) {
+        return true;
+    }
+    match offered.find('/') {
+        Some(i) => !protocol.contains('/') && offered[..i].eq_ignore_ascii_case(protocol),
+        None => false,
+    }
+}
+
+fn has_body(headers: &Headers) -> bool {
+    headers.get("Transfer-Encoding").is_some()
+        || headers.get("Content-Length").is_some_and(|v| v != b"0")
+}
+
+pub(crate) fn has_token(headers: &Headers, name: &str, token: &str) -> bool {
+    list(headers, name)
+        .iter()
+        .any(|t| t.eq_ignore_ascii_case(token))
+}
+
+pub(crate) fn list<'a>(headers: &'a Headers, name: &str) -> Vec<&'a str> {
+    headers
+        .iter()
+        .filter(|h| h.0.eq_ignore_ascii_case(name))
+        .filter_map(|h| str::from_utf8(h.1).ok())
+        .flat_map(|v| v.split(','))
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty() && t.split('/').all(is_token))
+        .collect()
+}
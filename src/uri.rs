@@ -0,0 +1,597 @@
+//! URI ([RFC 3986](https://tools.ietf.org/html/rfc3986)).
+//!
+//! # Examples
+//!
+//! ```
+//! use miasht::uri::Uri;
+//!
+//! let uri: Uri = "http://user@[::1]:8080/a/b/../c%7e?x=1#top".parse().unwrap();
+//! assert_eq!(uri.scheme(), Some("http"));
+//! assert_eq!(uri.userinfo(), Some("user"));
+//! assert_eq!(uri.host(), Some("::1"));
+//! assert_eq!(uri.port(), Some(8080));
+//! assert_eq!(uri.path(), "/a/b/../c%7e");
+//! assert_eq!(uri.query(), Some("x=1"));
+//! assert_eq!(uri.fragment(), Some("top"));
+//!
+//! assert_eq!(uri.normalize().to_string(), "http://user@[::1]:8080/a/c~?x=1#top");
+//! assert_eq!(uri.request_target(), "/a/b/../c%7e?x=1");
+//!
+//! let next = uri.resolve(&"d?y=2".parse().unwrap()).unwrap();
+//! assert_eq!(next.to_string(), "http://user@[::1]:8080/a/d?y=2");
+//! ```
+use std::fmt;
+use std::str::{self, FromStr};
+
+use {Error, Status};
+use pool::Key;
+
+/// A component of a URI, which determines the characters to be percent-encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Component {
+    Userinfo,
+    Host,
+
+    /// A path (`'/'` is not encoded).
+    Path,
+
+    /// A segment of a path (`'/'` is encoded).
+    PathSegment,
+    Query,
+    Fragment,
+}
+impl Component {
+    fn allows(self, b: u8) -> bool {
+        if is_unreserved(b) || is_sub_delim(b) {
+            return true;
+        }
+        match self {
+            Component::Userinfo => b == b':',
+            Component::Host => false,
+            Component::Path => b == b':' || b == b'@' || b == b'/',
+            Component::PathSegment => b == b':' || b == b'@',
+            Component::Query | Component::Fragment => b":@/?".contains(&b),
+        }
+    }
+}
+
+/// Percent-encodes the characters of `s` which are not allowed in `component`.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::uri::{self, Component};
+///
+/// assert_eq!(uri::encode("a b/c", Component::Path), "a%20b/c");
+/// assert_eq!(uri::encode("a b/c", Component::PathSegment), "a%20b%2Fc");
+/// assert_eq!(uri::encode("100%", Component::Query), "100%25");
+/// assert_eq!(uri::encode("あ", Component::Fragment), "%E3%81%82");
+/// ```
+pub fn encode(s: &str, component: Component) -> String {
+    let mut buf = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b != b'%' && component.allows(b) {
+            buf.push(b as char);
+        } else {
+            push_encoded(&mut buf, b);
+        }
+    }
+    buf
+}
+
+/// Decodes the percent-encoded string `s`.
+///
+/// If `s` contains a malformed percent-encoded sequence or the decoded bytes are not UTF-8,
+/// `Status::BadRequest` error will be returned.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::uri;
+///
+/// assert_eq!(uri::decode("a%20b+c").unwrap(), "a b+c");
+/// assert!(uri::decode("100%").is_err());
+/// assert!(uri::decode("%FF").is_err());
+/// ```
+pub fn decode(s: &str) -> ::Result<String> {
+    let bytes = s.as_bytes();
+    let mut buf = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let b = track_assert_some!(
+                pct_byte(&bytes[i..]),
+                Status::BadRequest,
+                "Malformed percent-encoding: {:?}",
+                s
+            );
+            buf.push(b);
+            i += 3;
+        } else {
+            buf.push(bytes[i]);
+            i += 1;
+        }
+    }
+    let decoded = track_assert_some!(
+        String::from_utf8(buf).ok(),
+        Status::BadRequest,
+        "Non UTF-8 percent-encoding: {:?}",
+        s
+    );
+    Ok(decoded)
+}
+
+/// Returns the default port of `scheme` (`http`, `https`, `ws` and `wss` are known).
+pub fn default_port(scheme: &str) -> Option<u16> {
+    match scheme.to_ascii_lowercase().as_str() {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        _ => None,
+    }
+}
+
+/// URI reference, i.e., an absolute URI or a relative reference.
+///
+/// The components are kept in the percent-encoded form.
+/// The host of an IPv6 literal has no brackets.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Uri {
+    scheme: Option<String>,
+    userinfo: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    path: String,
+    query: Option<String>,
+    fragment: Option<String>,
+}
+impl Uri {
+    /// Returns the scheme.
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    /// Returns the userinfo.
+    pub fn userinfo(&self) -> Option<&str> {
+        self.userinfo.as_deref()
+    }
+
+    /// Returns the host.
+    ///
+    /// This is `None` if the URI has no authority component.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// Returns the port.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Returns the port, or the default port of the scheme if the port is omitted.
+    pub fn port_or_default(&self) -> Option<u16> {
+        self.port.or_else(|| self.scheme().and_then(default_port))
+    }
+
+    /// Returns the path.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the query.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// Returns the fragment.
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+
+    /// Returns `true` if the URI has a scheme, otherwise `false`.
+    pub fn is_absolute(&self) -> bool {
+        self.scheme.is_some()
+    }
+
+    /// Returns the origin (the scheme, host and port) of the URI.
+    ///
+    /// `None` is returned if the URI has no scheme or host, or the port is unknown.
+    pub fn origin(&self) -> Option<Key> {
+        let scheme = self.scheme()?;
+        let host = self.host().filter(|h| !h.is_empty())?;
+        Some(Key::new(scheme, host, self.port_or_default()?))
+    }
+
+    /// Returns the value of the `Host` header (the host and the port without userinfo).
+    pub fn host_header(&self) -> Option<String> {
+        let host = self.host()?;
+        let mut value = if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host.to_owned()
+        };
+        if let Some(port) = self.port {
+            value.push_str(&format!(":{}", port));
+        }
+        Some(value)
+    }
+
+    /// Returns the origin-form request target (i.e., the path and the query)
+    /// which can be passed to `client::Connection::build_request`.
+    ///
+    /// An empty path is converted to `"/"`.
+    pub fn request_target(&self) -> String {
+        let mut target = if self.path.is_empty() {
+            "/".to_owned()
+        } else {
+            self.path.clone()
+        };
+        if let Some(ref query) = self.query {
+            target.push('?');
+            target.push_str(query);
+        }
+        target
+    }
+
+    /// Returns a copy of this URI without the fragment.
+    pub fn without_fragment(&self) -> Self {
+        Uri {
+            fragment: None,
+            ..self.clone()
+        }
+    }
+
+    /// Resolves `reference` against this URI
+    /// ([RFC 3986 §5.2](https://tools.ietf.org/html/rfc3986#section-5.2)).
+    ///
+    /// If this URI is not absolute, `Status::BadRequest` error will be returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::uri::Uri;
+    ///
+    /// let base: Uri = "http://a/b/c/d;p?q".parse().unwrap();
+    /// let resolve = |r: &str| base.resolve(&r.parse().unwrap()).unwrap().to_string();
+    /// assert_eq!(resolve("g"), "http://a/b/c/g");
+    /// assert_eq!(resolve("../g"), "http://a/b/g");
+    /// assert_eq!(resolve("../../../g"), "http://a/g");
+    /// assert_eq!(resolve("//g"), "http://g");
+    /// assert_eq!(resolve("?y"), "http://a/b/c/d;p?y");
+    /// assert_eq!(resolve("#s"), "http://a/b/c/d;p?q#s");
+    /// assert_eq!(resolve(""), "http://a/b/c/d;p?q");
+    /// ```
+    pub fn resolve(&self, reference: &Uri) -> ::Result<Uri> {
+        track_assert!(self.is_absolute(), Status::BadRequest, "Not an absolute URI: {}", self);
+        let r = reference;
+        let mut t = if r.scheme.is_some() {
+            Uri {
+                path: remove_dot_segments(&r.path),
+                ..r.clone()
+            }
+        } else if r.host.is_some() {
+            Uri {
+                scheme: self.scheme.clone(),
+                path: remove_dot_segments(&r.path),
+                ..r.clone()
+            }
+        } else {
+            let (path, query) = if r.path.is_empty() {
+                (self.path.clone(), r.query.clone().or_else(|| self.query.clone()))
+            } else if r.path.starts_with('/') {
+                (remove_dot_segments(&r.path), r.query.clone())
+            } else {
+                (remove_dot_segments(&self.merge(&r.path)), r.query.clone())
+            };
+            Uri {
+                path,
+                query,
+                ..self.clone()
+            }
+        };
+        t.fragment = r.fragment.clone();
+        Ok(t)
+    }
+
+    /// Returns the normalized URI
+    /// ([RFC 3986 §6.2.2](https://tools.ietf.org/html/rfc3986#section-6.2.2) and
+    /// [§6.2.3](https://tools.ietf.org/html/rfc3986#section-6.2.3)).
+    ///
+    /// - The scheme and the host are converted to lowercase
+    /// - The hexadecimal digits of percent-encodings are converted to uppercase
+    /// - The percent-encoded unreserved characters are decoded
+    /// - The dot segments in the path are removed
+    /// - The default port of the scheme is removed
+    /// - An empty path is converted to `"/"` if the URI has an authority
+    pub fn normalize(&self) -> Uri {
+        let scheme = self.scheme.as_ref().map(|s| s.to_ascii_lowercase());
+        let port = match (self.port, scheme.as_ref().and_then(|s| default_port(s))) {
+            (Some(p), Some(d)) if p == d => None,
+            (p, _) => p,
+        };
+        let mut path = normalize_pct(&self.path);
+        if self.scheme.is_some() || self.host.is_some() {
+            path = remove_dot_segments(&path);
+        }
+        if path.is_empty() && self.host.is_some() {
+            path.push('/');
+        }
+        Uri {
+            scheme,
+            userinfo: self.userinfo.as_ref().map(|s| normalize_pct(s)),
+            host: self.host.as_ref().map(|s| normalize_pct(s).to_ascii_lowercase()),
+            port,
+            path,
+            query: self.query.as_ref().map(|s| normalize_pct(s)),
+            fragment: self.fragment.as_ref().map(|s| normalize_pct(s)),
+        }
+    }
+
+    fn merge(&self, path: &str) -> String {
+        if self.host.is_some() && self.path.is_empty() {
+            format!("/{}", path)
+        } else {
+            let i = self.path.rfind('/').map_or(0, |i| i + 1);
+            format!("{}{}", &self.path[..i], path)
+        }
+    }
+}
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref scheme) = self.scheme {
+            write!(f, "{}:", scheme)?;
+        }
+        if let Some(ref host) = self.host {
+            write!(f, "//")?;
+            if let Some(ref userinfo) = self.userinfo {
+                write!(f, "{}@", userinfo)?;
+            }
+            if host.contains(':') {
+                write!(f, "[{}]", host)?;
+            } else {
+                write!(f, "{}", host)?;
+            }
+            if let Some(port) = self.port {
+                write!(f, ":{}", port)?;
+            }
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(ref query) = self.query {
+            write!(f, "?{}", query)?;
+        }
+        if let Some(ref fragment) = self.fragment {
+            write!(f, "#{}", fragment)?;
+        }
+        Ok(())
+    }
+}
+impl FromStr for Uri {
+    type Err = Error;
+
+    /// Parses a URI reference.
+    ///
+    /// If `s` contains characters which are not allowed (e.g., spaces), or
+    /// the host or the port is malformed, `Status::BadRequest` error will be returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::Status;
+    /// use miasht::uri::Uri;
+    ///
+    /// let uri: Uri = "http://[::1]:8080/".parse().unwrap();
+    /// assert_eq!(uri.port(), Some(8080));
+    /// assert_eq!("http://a:/".parse::<Uri>().unwrap().port(), None);
+    ///
+    /// for malformed in &[
+    ///     "http://[::1]é/", "http://[::1]x/", "http://[::1/", "http://[a.b]/", "http://[::1]:é/",
+    ///     "http://a:é/", "http://a:8x/", "http://a:65536/", "http://a b/", "http://é/", "http://a@b@[/",
+    /// ] {
+    ///     let e = malformed.parse::<Uri>().unwrap_err();
+    ///     assert_eq!(*e.kind(), Status::BadRequest, "{}", malformed);
+    /// }
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // See: https://tools.ietf.org/html/rfc3986#appendix-B
+        let (rest, fragment) = match s.find('#') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let (rest, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+        let (scheme, rest) = match rest.find([':', '/']) {
+            Some(i) if rest[i..].starts_with(':') => (Some(&rest[..i]), &rest[i + 1..]),
+            _ => (None, rest),
+        };
+        let (authority, path) = if let Some(rest) = rest.strip_prefix("//") {
+            let i = rest.find('/').unwrap_or(rest.len());
+            (Some(&rest[..i]), &rest[i..])
+        } else {
+            (None, rest)
+        };
+
+        if let Some(scheme) = scheme {
+            track_assert!(
+                scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && scheme.bytes().all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b)),
+                Status::BadRequest,
+                "Malformed scheme: {:?}",
+                s
+            );
+        } else {
+            let first = path.split('/').next().expect("Never fails");
+            track_assert!(
+                !first.contains(':'),
+                Status::BadRequest,
+                "Malformed relative reference: {:?}",
+                s
+            );
+        }
+        track!(validate(path, Component::Path), "uri={:?}", s)?;
+        if let Some(query) = query {
+            track!(validate(query, Component::Query), "uri={:?}", s)?;
+        }
+        if let Some(fragment) = fragment {
+            track!(validate(fragment, Component::Fragment), "uri={:?}", s)?;
+        }
+        track_assert!(
+            authority.is_some() || !path.starts_with("//"),
+            Status::BadRequest,
+            "Malformed path: {:?}",
+            s
+        );
+
+        let (userinfo, host, port) = if let Some(authority) = authority {
+            let (userinfo, host_port) = match authority.rfind('@') {
+                Some(i) => (Some(&authority[..i]), &authority[i + 1..]),
+                None => (None, authority),
+            };
+            if let Some(userinfo) = userinfo {
+                track!(validate(userinfo, Component::Userinfo), "uri={:?}", s)?;
+            }
+            let (host, port) = track!(parse_host_port(host_port), "uri={:?}", s)?;
+            (userinfo, Some(host), port)
+        } else {
+            (None, None, None)
+        };
+        Ok(Uri {
+            scheme: scheme.map(|s| s.to_owned()),
+            userinfo: userinfo.map(|s| s.to_owned()),
+            host: host.map(|s| s.to_owned()),
+            port,
+            path: path.to_owned(),
+            query: query.map(|s| s.to_owned()),
+            fragment: fragment.map(|s| s.to_owned()),
+        })
+    }
+}
+
+fn parse_host_port(s: &str) -> ::Result<(&str, Option<u16>)> {
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+        let end = track_assert_some!(rest.find(']'), Status::BadRequest, "Unclosed IP literal: {:?}", s);
+        let host = &rest[..end];
+        track_assert!(
+            host.contains(':') && host.bytes().all(|b| b.is_ascii_hexdigit() || b == b':' || b == b'.'),
+            Status::BadRequest,
+            "Malformed IPv6 address: {:?}",
+            s
+        );
+        (host, &rest[end + 1..])
+    } else {
+        let i = s.find(':').unwrap_or(s.len());
+        let host = &s[..i];
+        track!(validate(host, Component::Host))?;
+        (host, &s[i..])
+    };
+    let port = match port {
+        "" | ":" => None,
+        _ => {
+            let digits = port.strip_prefix(':').filter(|d| d.bytes().all(|b| b.is_ascii_digit()));
+            let digits = track_assert_some!(digits, Status::BadRequest, "Malformed port: {:?}", s);
+            let port = track_assert_some!(digits.parse().ok(), Status::BadRequest, "Too large port: {:?}", s);
+            Some(port)
+        }
+    };
+    Ok((host, port))
+}
+
+fn validate(s: &str, component: Component) -> ::Result<()> {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            track_assert!(
+                pct_byte(&bytes[i..]).is_some(),
+                Status::BadRequest,
+                "Malformed percent-encoding: {:?}",
+                s
+            );
+            i += 3;
+        } else {
+            track_assert!(
+                component.allows(bytes[i]),
+                Status::BadRequest,
+                "Invalid character in {:?}: {:?}",
+                component,
+                s
+            );
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
+fn normalize_pct(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut buf = String::with_capacity(s.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match pct_byte(&bytes[i..]) {
+            Some(b) if is_unreserved(b) => {
+                buf.push(b as char);
+                i += 3;
+            }
+            Some(b) => {
+                push_encoded(&mut buf, b);
+                i += 3;
+            }
+            None => {
+                let end = s[i + 1..].find('%').map_or(s.len(), |j| i + 1 + j);
+                buf.push_str(&s[i..end]);
+                i = end;
+            }
+        }
+    }
+    buf
+}
+
+// See: https://tools.ietf.org/html/rfc3986#section-5.2.4
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output = String::new();
+    while !input.is_empty() {
+        if input.starts_with("../") {
+            input = &input[3..];
+        } else if input.starts_with("./") || input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") || input == "/.." {
+            input = if input == "/.." { "/" } else { &input[3..] };
+            let i = output.rfind('/').unwrap_or(0);
+            output.truncate(i);
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            let end = input[1..].find('/').map_or(input.len(), |i| i + 1);
+            output.push_str(&input[..end]);
+            input = &input[end..];
+        }
+    }
+    output
+}
+
+fn pct_byte(bytes: &[u8]) -> Option<u8> {
+    if bytes.len() < 3 || bytes[0] != b'%' {
+        return None;
+    }
+    let hi = (bytes[1] as char).to_digit(16)?;
+    let lo = (bytes[2] as char).to_digit(16)?;
+    Some((hi << 4 | lo) as u8)
+}
+
+fn push_encoded(buf: &mut String, b: u8) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    buf.push('%');
+    buf.push(HEX[(b >> 4) as usize] as char);
+    buf.push(HEX[(b & 0xF) as usize] as char);
+}
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"-._~".contains(&b)
+}
+
+fn is_sub_delim(b: u8) -> bool {
+    b"!$&'()*+,;=".contains(&b)
+}
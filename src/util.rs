@@ -0,0 +1,23 @@
+use std::io::{self, Write};
+use getrandom;
+
+/// Returns 16 bytes from the random number generator of the OS.
+pub fn random_bytes() -> [u8; 16] {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("Cannot get random bytes from the OS");
+    bytes
+}
+
+/// Writes `bytes[*offset..]` into `writer`, advancing `offset` as the bytes are written.
+///
+/// If this fails with `WouldBlock`, it can be called again with the same `offset`.
+pub fn write_all<W: Write>(writer: &mut W, bytes: &[u8], offset: &mut usize) -> io::Result<()> {
+    while *offset < bytes.len() {
+        let size = writer.write(&bytes[*offset..])?;
+        if size == 0 {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "Cannot write the body"));
+        }
+        *offset += size;
+    }
+    Ok(())
+}
@@ -0,0 +1,1009 @@
+//! The WebSocket protocol ([RFC 6455](https://tools.ietf.org/html/rfc6455)).
+//!
+//! The opening handshake is performed with the ordinary HTTP API
+//! (`ServerHandshake` for servers and `ClientHandshake` for clients).
+//! After that, the raw stream of the connection is wrapped by `WebSocket`
+//! which reads and writes frames.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate futures;
+//! extern crate miasht;
+//!
+//! use std::io::Cursor;
+//! use futures::{Async, Stream};
+//! use miasht::websocket::{CloseCode, Message, Role, WebSocket};
+//!
+//! # fn main() {
+//! // The frames sent by a client (i.e., masked).
+//! let mut client = WebSocket::new(Cursor::new(Vec::new()), Role::Client);
+//! client.send(Message::Text("Hello".to_owned())).unwrap();
+//! client.send(Message::Ping(b"ping".to_vec())).unwrap();
+//! client.close(CloseCode::NORMAL, "bye").unwrap();
+//! let bytes = client.into_inner().into_inner();
+//!
+//! // A server reads them.
+//! let mut server = WebSocket::new(Cursor::new(bytes), Role::Server);
+//! assert_eq!(server.poll().unwrap(), Async::Ready(Some(Message::Text("Hello".to_owned()))));
+//! assert_eq!(server.poll().unwrap(), Async::Ready(Some(Message::Ping(b"ping".to_vec()))));
+//! match server.poll().unwrap() {
+//!     Async::Ready(Some(Message::Close(Some(frame)))) => {
+//!         assert_eq!(frame.code, CloseCode::NORMAL);
+//!         assert_eq!(frame.reason, "bye");
+//!     }
+//!     other => panic!("{:?}", other),
+//! }
+//! assert_eq!(server.poll().unwrap(), Async::Ready(None));
+//! # }
+//! ```
+use std::io::{self, Read, Write};
+use std::str;
+use base64;
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use sha1;
+use trackable::error::ErrorKindExt;
+
+use {Error, Method, Status, TransportStream, Version};
+use client;
+use header::{ContentLength, Headers, HeadersMut};
+use server;
+use upgrade::{self, has_token, UpgradeResponse, Upgraded};
+use util::random_bytes;
+
+/// The GUID used to compute the `Sec-WebSocket-Accept` value.
+pub const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The WebSocket protocol version supported by this module.
+pub const VERSION: &str = "13";
+
+/// The default value of the maximum message size (16 MiB).
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Computes the `Sec-WebSocket-Accept` value for the `Sec-WebSocket-Key` value `key`.
+///
+/// # Examples
+///
+/// ```
+/// use miasht::websocket::accept_key;
+///
+/// // The example of RFC 6455 §1.3
+/// assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+/// ```
+pub fn accept_key(key: &str) -> String {
+    let mut m = sha1::Sha1::new();
+    m.update(key.as_bytes());
+    m.update(GUID.as_bytes());
+    base64::encode(&m.digest().bytes()[..])
+}
+
+/// The validated opening handshake request of a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerHandshake {
+    key: String,
+    protocols: Vec<String>,
+}
+impl ServerHandshake {
+    /// Validates the opening handshake request `request`.
+    ///
+    /// If `Sec-WebSocket-Version` is not supported, `Status::UpgradeRequired` error will be returned.
+    /// If the request is not a valid handshake, `Status::BadRequest`
+    /// (or `Status::MethodNotAllowed` for non `GET` requests) error will be returned.
+    pub fn from_request<T>(request: &server::Request<T>) -> ::Result<Self> {
+        track_assert_eq!(request.method(), Method::Get, Status::MethodNotAllowed);
+        Self::from_parts(request.version(), request.headers())
+    }
+
+    /// Validates the handshake request made of `version` and `headers`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate httparse;
+    /// # extern crate miasht;
+    /// use miasht::{Status, Version};
+    /// use miasht::header::Headers;
+    /// use miasht::websocket::ServerHandshake;
+    ///
+    /// # fn main() {
+    /// let fields = [
+    ///     httparse::Header { name: "Upgrade", value: b"websocket" },
+    ///     httparse::Header { name: "Connection", value: b"keep-alive, Upgrade" },
+    ///     httparse::Header { name: "Sec-WebSocket-Key", value: b"dGhlIHNhbXBsZSBub25jZQ==" },
+    ///     httparse::Header { name: "Sec-WebSocket-Version", value: b"13" },
+    ///     httparse::Header { name: "Sec-WebSocket-Protocol", value: b"chat, superchat" },
+    /// ];
+    /// let handshake = ServerHandshake::from_parts(Version::Http1_1, &Headers::new(&fields)).unwrap();
+    /// assert_eq!(handshake.accept_key(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    /// assert_eq!(handshake.protocols(), ["chat", "superchat"]);
+    ///
+    /// let fields = [
+    ///     httparse::Header { name: "Upgrade", value: b"websocket" },
+    ///     httparse::Header { name: "Connection", value: b"Upgrade" },
+    ///     httparse::Header { name: "Sec-WebSocket-Key", value: b"dGhlIHNhbXBsZSBub25jZQ==" },
+    ///     httparse::Header { name: "Sec-WebSocket-Version", value: b"8" },
+    /// ];
+    /// let e = ServerHandshake::from_parts(Version::Http1_1, &Headers::new(&fields)).err().unwrap();
+    /// assert_eq!(*e.kind(), Status::UpgradeRequired);
+    /// # }
+    /// ```
+    pub fn from_parts(version: Version, headers: &Headers) -> ::Result<Self> {
+        track_assert_eq!(version, Version::Http1_1, Status::BadRequest);
+        track_assert!(
+            has_token(headers, "Upgrade", "websocket"),
+            Status::BadRequest,
+            "Missing 'Upgrade: websocket' header"
+        );
+        track_assert!(
+            has_token(headers, "Connection", "upgrade"),
+            Status::BadRequest,
+            "Missing 'Connection: upgrade' header"
+        );
+        let version = track_assert_some!(
+            headers.get("Sec-WebSocket-Version"),
+            Status::UpgradeRequired,
+            "Missing 'Sec-WebSocket-Version' header"
+        );
+        track_assert_eq!(version, VERSION.as_bytes(), Status::UpgradeRequired);
+
+        let key = track_assert_some!(
+            headers.get("Sec-WebSocket-Key"),
+            Status::BadRequest,
+            "Missing 'Sec-WebSocket-Key' header"
+        );
+        let key = track!(str::from_utf8(key).map_err(|e| Error::from(Status::BadRequest.cause(e))))?
+            .trim();
+        let decoded = track!(base64::decode(key).map_err(|e| Error::from(Status::BadRequest.cause(e))))?;
+        track_assert_eq!(decoded.len(), 16, Status::BadRequest, "key={:?}", key);
+
+        Ok(ServerHandshake {
+            key: key.to_owned(),
+            protocols: upgrade::list(headers, "Sec-WebSocket-Protocol")
+                .into_iter()
+                .map(|p| p.to_owned())
+                .collect(),
+        })
+    }
+
+    /// Returns the `Sec-WebSocket-Key` value of the request.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the subprotocols requested by the client (in order of preference).
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Returns the `Sec-WebSocket-Accept` value of the response.
+    pub fn accept_key(&self) -> String {
+        accept_key(&self.key)
+    }
+
+    /// Adds the headers of the `101 Switching Protocols` response.
+    ///
+    /// If `protocol` is `Some(_)`, it must be one of the requested subprotocols.
+    pub fn add_headers(&self, headers: &mut HeadersMut, protocol: Option<&str>) -> ::Result<()> {
+        if let Some(protocol) = protocol {
+            track_assert!(
+                self.protocols.iter().any(|p| p == protocol),
+                Status::InternalServerError,
+                "Unrequested subprotocol: {:?}",
+                protocol
+            );
+        }
+        headers
+            .add_raw_header("Upgrade", b"websocket")
+            .add_raw_header("Connection", b"Upgrade")
+            .add_raw_header("Sec-WebSocket-Accept", self.accept_key().as_bytes());
+        if let Some(protocol) = protocol {
+            headers.add_raw_header("Sec-WebSocket-Protocol", protocol.as_bytes());
+        }
+        Ok(())
+    }
+
+    /// Makes the `101 Switching Protocols` response to `request`.
+    ///
+    /// The resulting future yields the upgraded stream after the response is sent.
+    /// It can be wrapped by `WebSocket` with `Role::Server`.
+    pub fn accept<T>(&self, request: server::Request<T>, protocol: Option<&str>) -> ::Result<UpgradeResponse<T>>
+    where
+        T: TransportStream,
+    {
+        let mut response = request.finish().build_response(Status::SwitchingProtocols);
+        track!(self.add_headers(&mut response.headers_mut(), protocol))?;
+        Ok(UpgradeResponse::new(response.finish()))
+    }
+}
+
+/// Makes the error response to the handshake request `request` rejected with `error`.
+///
+/// The status of the response is the kind of `error`.
+/// If it is `Status::UpgradeRequired`, the supported version is
+/// advertised by the `Sec-WebSocket-Version` header.
+pub fn reject<T>(request: server::Request<T>, error: &Error) -> server::Response<T>
+where
+    T: TransportStream,
+{
+    let status = *error.kind();
+    let mut response = request.finish().build_response(status);
+    if status == Status::UpgradeRequired {
+        response.add_raw_header("Sec-WebSocket-Version", VERSION.as_bytes());
+        response.add_raw_header("Upgrade", b"websocket");
+    }
+    response.add_header(&ContentLength(0));
+    response.finish()
+}
+
+/// The opening handshake of a client.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate base64;
+/// # extern crate miasht;
+/// use miasht::websocket::ClientHandshake;
+///
+/// # fn main() {///
+/// let handshake = ClientHandshake::new().protocol("chat");
+/// assert_eq!(base64::decode(handshake.key()).unwrap().len(), 16);
+/// assert_eq!(handshake.protocols(), ["chat"]);
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientHandshake {
+    key: String,
+    protocols: Vec<String>,
+}
+impl ClientHandshake {
+    /// Makes a new `ClientHandshake` instance with a random key.
+    pub fn new() -> Self {
+        ClientHandshake {
+            key: base64::encode(&random_bytes()),
+            protocols: Vec::new(),
+        }
+    }
+
+    /// Adds a subprotocol requested by the client.
+    pub fn protocol(mut self, protocol: &str) -> Self {
+        self.protocols.push(protocol.to_owned());
+        self
+    }
+
+    /// Returns the `Sec-WebSocket-Key` value of the request.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the requested subprotocols.
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Adds the handshake headers to `request`.
+    ///
+    /// The request should be a `GET` request and have the `Host` header.
+    pub fn add_headers<T>(&self, request: &mut client::RequestBuilder<T>)
+    where
+        T: TransportStream,
+    {
+        upgrade::add_request_headers(&mut request.headers_mut(), &["websocket"]);
+        request
+            .add_raw_header("Sec-WebSocket-Key", self.key.as_bytes())
+            .add_raw_header("Sec-WebSocket-Version", VERSION.as_bytes());
+        if !self.protocols.is_empty() {
+            request.add_raw_header("Sec-WebSocket-Protocol", self.protocols.join(", ").as_bytes());
+        }
+    }
+
+    /// Verifies the handshake response `response`.
+    ///
+    /// If succeeded, the subprotocol selected by the server is returned.
+    pub fn verify<T>(&self, response: &client::Response<T>) -> ::Result<Option<String>> {
+        self.verify_parts(response.status().code(), response.headers())
+    }
+
+    /// Verifies the handshake response `response` and returns the upgraded stream
+    /// with the subprotocol selected by the server.
+    ///
+    /// The stream can be wrapped by `WebSocket` with `Role::Client`.
+    pub fn complete<T>(&self, response: client::Response<T>) -> ::Result<(Upgraded<T>, Option<String>)>
+    where
+        T: TransportStream,
+    {
+        let protocol = track!(self.verify(&response))?;
+        let stream = track!(upgrade::complete(response, "websocket"))?;
+        Ok((stream, protocol))
+    }
+
+    /// Verifies the handshake response made of `status` and `headers`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate httparse;
+    /// # extern crate miasht;
+    /// use miasht::header::Headers;
+    /// use miasht::websocket::{accept_key, ClientHandshake};
+    ///
+    /// # fn main() {
+    /// let handshake = ClientHandshake::new();
+    /// let accept = accept_key(handshake.key());
+    /// let fields = [
+    ///     httparse::Header { name: "Upgrade", value: b"websocket" },
+    ///     httparse::Header { name: "Connection", value: b"Upgrade" },
+    ///     httparse::Header { name: "Sec-WebSocket-Accept", value: accept.as_bytes() },
+    /// ];
+    /// assert_eq!(handshake.verify_parts(101, &Headers::new(&fields)).ok(), Some(None));
+    /// assert!(handshake.verify_parts(200, &Headers::new(&fields)).is_err());
+    /// # }
+    /// ```
+    pub fn verify_parts(&self, status: u16, headers: &Headers) -> ::Result<Option<String>> {
+        track_assert_eq!(status, 101, Status::BadGateway);
+        track_assert!(
+            has_token(headers, "Upgrade", "websocket"),
+            Status::BadGateway,
+            "Missing 'Upgrade: websocket' header"
+        );
+        track_assert!(
+            has_token(headers, "Connection", "upgrade"),
+            Status::BadGateway,
+            "Missing 'Connection: upgrade' header"
+        );
+        let accept = track_assert_some!(
+            headers.get("Sec-WebSocket-Accept"),
+            Status::BadGateway,
+            "Missing 'Sec-WebSocket-Accept' header"
+        );
+        let expected = accept_key(&self.key);
+        track_assert_eq!(accept, expected.as_bytes(), Status::BadGateway);
+
+        let mut selected = upgrade::list(headers, "Sec-WebSocket-Protocol");
+        track_assert!(selected.len() <= 1, Status::BadGateway);
+        let selected = selected.pop().map(|p| p.to_owned());
+        if let Some(ref protocol) = selected {
+            track_assert!(
+                self.protocols.contains(protocol),
+                Status::BadGateway,
+                "Unrequested subprotocol: {:?}",
+                protocol
+            );
+        }
+        Ok(selected)
+    }
+}
+impl Default for ClientHandshake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The role of an endpoint.
+///
+/// Clients mask the frames they send and servers do not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// Frame opcodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+impl Opcode {
+    /// Converts the four bits opcode `n` to `Opcode`.
+    ///
+    /// Reserved opcodes result in `None`.
+    pub fn from_u8(n: u8) -> Option<Self> {
+        Some(match n {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xA => Opcode::Pong,
+            _ => return None,
+        })
+    }
+
+    /// Returns the four bits value of the opcode.
+    pub fn as_u8(&self) -> u8 {
+        match *self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    /// Returns `true` if this is a control opcode, otherwise `false`.
+    pub fn is_control(&self) -> bool {
+        self.as_u8() >= 0x8
+    }
+}
+
+/// A frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+impl Frame {
+    /// Makes a new frame which has the `FIN` bit.
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Frame {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+
+    /// Appends the encoded frame to `buf`.
+    ///
+    /// If `mask` is `Some(_)`, the payload is masked with the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::websocket::{Frame, Opcode};
+    ///
+    /// // The examples of RFC 6455 §5.7
+    /// let mut buf = Vec::new();
+    /// Frame::new(Opcode::Text, b"Hello".to_vec()).encode(None, &mut buf);
+    /// assert_eq!(buf, [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+    ///
+    /// let mut buf = Vec::new();
+    /// Frame::new(Opcode::Text, b"Hello".to_vec()).encode(Some([0x37, 0xfa, 0x21, 0x3d]), &mut buf);
+    /// assert_eq!(buf, [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]);
+    /// ```
+    pub fn encode(&self, mask: Option<[u8; 4]>, buf: &mut Vec<u8>) {
+        let fin = if self.fin { 0x80 } else { 0 };
+        buf.push(fin | self.opcode.as_u8());
+
+        let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+        let len = self.payload.len();
+        if len < 126 {
+            buf.push(mask_bit | len as u8);
+        } else if len <= 0xFFFF {
+            buf.push(mask_bit | 126);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            buf.push(mask_bit | 127);
+            buf.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+
+        if let Some(key) = mask {
+            buf.extend_from_slice(&key);
+            let start = buf.len();
+            buf.extend_from_slice(&self.payload);
+            apply_mask(&mut buf[start..], key);
+        } else {
+            buf.extend_from_slice(&self.payload);
+        }
+    }
+}
+
+/// A status code of a close frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CloseCode(pub u16);
+impl CloseCode {
+    pub const NORMAL: CloseCode = CloseCode(1000);
+    pub const GOING_AWAY: CloseCode = CloseCode(1001);
+    pub const PROTOCOL_ERROR: CloseCode = CloseCode(1002);
+    pub const UNSUPPORTED_DATA: CloseCode = CloseCode(1003);
+    pub const INVALID_PAYLOAD: CloseCode = CloseCode(1007);
+    pub const POLICY_VIOLATION: CloseCode = CloseCode(1008);
+    pub const MESSAGE_TOO_BIG: CloseCode = CloseCode(1009);
+    pub const MANDATORY_EXTENSION: CloseCode = CloseCode(1010);
+    pub const INTERNAL_ERROR: CloseCode = CloseCode(1011);
+
+    /// Returns `true` if the code can be sent in a close frame, otherwise `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use miasht::websocket::CloseCode;
+    ///
+    /// assert!(CloseCode::NORMAL.is_sendable());
+    /// assert!(CloseCode(4000).is_sendable());
+    /// assert!(!CloseCode(1005).is_sendable());
+    /// assert!(!CloseCode(999).is_sendable());
+    /// ```
+    pub fn is_sendable(&self) -> bool {
+        matches!(self.0, 1000..=1003 | 1007..=1011 | 3000..=4999)
+    }
+}
+
+/// The payload of a close frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+/// A message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+impl Message {
+    fn into_frame(self) -> Frame {
+        match self {
+            Message::Text(s) => Frame::new(Opcode::Text, s.into_bytes()),
+            Message::Binary(b) => Frame::new(Opcode::Binary, b),
+            Message::Ping(b) => Frame::new(Opcode::Ping, b),
+            Message::Pong(b) => Frame::new(Opcode::Pong, b),
+            Message::Close(None) => Frame::new(Opcode::Close, Vec::new()),
+            Message::Close(Some(close)) => {
+                let mut payload = close.code.0.to_be_bytes().to_vec();
+                payload.extend_from_slice(close.reason.as_bytes());
+                Frame::new(Opcode::Close, payload)
+            }
+        }
+    }
+}
+
+/// A WebSocket connection over the stream `S`.
+///
+/// Incoming messages are received via the `Stream` implementation.
+/// Fragmented messages are reassembled, pings are answered automatically
+/// (the pings are also yielded to the user) and a close frame is echoed back.
+/// The stream ends when the closing handshake is completed.
+///
+/// If the peer violates the protocol, a close frame with the appropriate
+/// code is sent and an error is returned.
+/// The kind of the error is `Status::PayloadTooLarge` if a message exceeds
+/// the maximum size, otherwise `Status::BadRequest`.
+///
+/// Outgoing messages are buffered by `send` (or the `Sink` implementation)
+/// and written to the stream when polled.
+/// Both directions can be used with non-blocking streams.
+#[derive(Debug)]
+pub struct WebSocket<S> {
+    stream: S,
+    role: Role,
+    max_message_size: usize,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    write_offset: usize,
+    fragments: Option<(Opcode, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+impl<S: Read + Write> WebSocket<S> {
+    /// Makes a new `WebSocket` instance.
+    pub fn new(stream: S, role: Role) -> Self {
+        Self::with_buffered_bytes(stream, role, Vec::new())
+    }
+
+    /// Makes a new `WebSocket` instance which has already read `bytes` from the stream.
+    ///
+    /// Note that `Upgraded` streams return their buffered bytes by themselves.
+    pub fn with_buffered_bytes(stream: S, role: Role, bytes: Vec<u8>) -> Self {
+        WebSocket {
+            stream,
+            role,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            read_buf: bytes,
+            write_buf: Vec::new(),
+            write_offset: 0,
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Returns the role of this endpoint.
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Returns the maximum size of an incoming message.
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Sets the maximum size of an incoming message.
+    ///
+    /// The default value is `DEFAULT_MAX_MESSAGE_SIZE`.
+    /// Control frames interleaved with a fragmented message are not counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate futures;
+    /// # extern crate miasht;
+    /// use std::io::Cursor;
+    /// use futures::{Async, Stream};
+    /// use miasht::Status;
+    /// use miasht::websocket::{Message, Role, WebSocket};
+    ///
+    /// # fn main() {
+    /// // "abc" + ping("pin") + "d", and then "abc" + "de"
+    /// let bytes = b"\x01\x03abc\x89\x03pin\x80\x01d\x01\x03abc\x80\x02de".to_vec();
+    /// let mut client = WebSocket::new(Cursor::new(bytes), Role::Client);
+    /// client.set_max_message_size(4);
+    /// assert_eq!(client.poll().unwrap(), Async::Ready(Some(Message::Ping(b"pin".to_vec()))));
+    /// assert_eq!(client.poll().unwrap(), Async::Ready(Some(Message::Text("abcd".to_owned()))));
+    /// assert_eq!(*client.poll().unwrap_err().kind(), Status::PayloadTooLarge);
+    /// # }
+    /// ```
+    pub fn set_max_message_size(&mut self, size: usize) -> &mut Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Returns `true` if a close frame has been sent, otherwise `false`.
+    pub fn is_close_sent(&self) -> bool {
+        self.close_sent
+    }
+
+    /// Returns `true` if a close frame has been received, otherwise `false`.
+    pub fn is_close_received(&self) -> bool {
+        self.close_received
+    }
+
+    /// Returns a reference to the inner stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the inner stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Returns the inner stream.
+    ///
+    /// Note that the buffered data are discarded.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Enqueues `message` and tries to write it to the stream.
+    ///
+    /// Messages cannot be sent after a close frame has been sent.
+    pub fn send(&mut self, message: Message) -> ::Result<()> {
+        track!(self.send_frame(message.into_frame()))
+    }
+
+    /// Enqueues the frame `frame` and tries to write it to the stream.
+    ///
+    /// This can be used to send a fragmented message:
+    /// the first frame has the `Text` or `Binary` opcode and
+    /// the following frames have the `Continuation` opcode.
+    /// The `fin` field of the last frame must be `true`.
+    pub fn send_frame(&mut self, frame: Frame) -> ::Result<()> {
+        track_assert!(
+            !self.close_sent,
+            Status::InternalServerError,
+            "A close frame has already been sent"
+        );
+        if frame.opcode.is_control() {
+            track_assert!(frame.fin, Status::InternalServerError);
+            track_assert!(
+                frame.payload.len() <= 125,
+                Status::InternalServerError,
+                "Too large control frame: {} bytes",
+                frame.payload.len()
+            );
+        }
+        if frame.opcode == Opcode::Close {
+            self.close_sent = true;
+        }
+        self.enqueue(&frame);
+        track!(self.poll_flush())?;
+        Ok(())
+    }
+
+    /// Starts the closing handshake.
+    ///
+    /// The stream should be polled until it ends to receive the close frame from the peer.
+    pub fn close(&mut self, code: CloseCode, reason: &str) -> ::Result<()> {
+        track_assert!(code.is_sendable(), Status::InternalServerError, "code={:?}", code);
+        let close = CloseFrame {
+            code,
+            reason: reason.to_owned(),
+        };
+        track!(self.send(Message::Close(Some(close))))
+    }
+
+    /// Writes the buffered frames to the stream.
+    pub fn poll_flush(&mut self) -> Poll<(), Error> {
+        while self.write_offset < self.write_buf.len() {
+            match self.stream.write(&self.write_buf[self.write_offset..]) {
+                Ok(0) => track_panic!(Status::InternalServerError, "Failed to write a frame"),
+                Ok(n) => self.write_offset += n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => return Err(track!(Error::from(e))),
+            }
+        }
+        self.write_buf.clear();
+        self.write_offset = 0;
+        match self.stream.flush() {
+            Ok(()) => Ok(Async::Ready(())),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(track!(Error::from(e))),
+        }
+    }
+
+    fn enqueue(&mut self, frame: &Frame) {
+        let mask = if self.role == Role::Client {
+            let bytes = random_bytes();
+            Some([bytes[0], bytes[1], bytes[2], bytes[3]])
+        } else {
+            None
+        };
+        frame.encode(mask, &mut self.write_buf);
+    }
+
+    fn fail(&mut self, code: CloseCode, error: Error) -> Error {
+        if !self.close_sent {
+            self.close_sent = true;
+            let close = CloseFrame {
+                code,
+                reason: String::new(),
+            };
+            self.enqueue(&Message::Close(Some(close)).into_frame());
+            let _ = self.poll_flush();
+        }
+        error
+    }
+
+    fn protocol_error(&mut self, reason: &str) -> Error {
+        let e = track!(Error::from(Status::BadRequest.cause(reason.to_owned())));
+        self.fail(CloseCode::PROTOCOL_ERROR, e)
+    }
+
+    fn decode_frame(&mut self) -> ::Result<Option<Frame>> {
+        if self.read_buf.len() < 2 {
+            return Ok(None);
+        }
+        let b0 = self.read_buf[0];
+        let b1 = self.read_buf[1];
+        if b0 & 0x70 != 0 {
+            return Err(self.protocol_error("Reserved bits are set"));
+        }
+        let opcode = if let Some(opcode) = Opcode::from_u8(b0 & 0x0F) {
+            opcode
+        } else {
+            return Err(self.protocol_error("Reserved opcode"));
+        };
+        let fin = b0 & 0x80 != 0;
+        let masked = b1 & 0x80 != 0;
+        if masked != (self.role == Role::Server) {
+            return Err(self.protocol_error("Unexpected masking"));
+        }
+
+        let mut offset = 2;
+        let len = match b1 & 0x7F {
+            126 => {
+                if self.read_buf.len() < 4 {
+                    return Ok(None);
+                }
+                offset = 4;
+                u64::from(u16::from_be_bytes([self.read_buf[2], self.read_buf[3]]))
+            }
+            127 => {
+                if self.read_buf.len() < 10 {
+                    return Ok(None);
+                }
+                offset = 10;
+                let mut n = [0; 8];
+                n.copy_from_slice(&self.read_buf[2..10]);
+                let n = u64::from_be_bytes(n);
+                if n >> 63 != 0 {
+                    return Err(self.protocol_error("Malformed payload length"));
+                }
+                n
+            }
+            n => u64::from(n),
+        };
+        if opcode.is_control() && (!fin || len > 125) {
+            return Err(self.protocol_error("Malformed control frame"));
+        }
+        // Control frames may be interleaved with a fragmented message (RFC 6455 §5.4),
+        // so only data frames are counted into the message size.
+        let buffered = self.fragments.as_ref().map_or(0, |f| f.1.len() as u64);
+        if !opcode.is_control() && buffered + len > self.max_message_size as u64 {
+            let e = track!(Error::from(Status::PayloadTooLarge.cause(format!(
+                "Too large message: {} bytes (max={})",
+                buffered + len,
+                self.max_message_size
+            ))));
+            return Err(self.fail(CloseCode::MESSAGE_TOO_BIG, e));
+        }
+
+        let mask = if masked {
+            if self.read_buf.len() < offset + 4 {
+                return Ok(None);
+            }
+            let key = [
+                self.read_buf[offset],
+                self.read_buf[offset + 1],
+                self.read_buf[offset + 2],
+                self.read_buf[offset + 3],
+            ];
+            offset += 4;
+            Some(key)
+        } else {
+            None
+        };
+        let end = offset + len as usize;
+        if self.read_buf.len() < end {
+            return Ok(None);
+        }
+        let mut payload = self.read_buf[offset..end].to_vec();
+        self.read_buf.drain(..end);
+        if let Some(key) = mask {
+            apply_mask(&mut payload, key);
+        }
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> ::Result<Option<Message>> {
+        match frame.opcode {
+            Opcode::Text | Opcode::Binary => {
+                if self.fragments.is_some() {
+                    return Err(self.protocol_error("Expected a continuation frame"));
+                }
+                if frame.fin {
+                    self.complete(frame.opcode, frame.payload).map(Some)
+                } else {
+                    self.fragments = Some((frame.opcode, frame.payload));
+                    Ok(None)
+                }
+            }
+            Opcode::Continuation => {
+                let (opcode, mut payload) = if let Some(f) = self.fragments.take() {
+                    f
+                } else {
+                    return Err(self.protocol_error("Unexpected continuation frame"));
+                };
+                payload.extend_from_slice(&frame.payload);
+                if frame.fin {
+                    self.complete(opcode, payload).map(Some)
+                } else {
+                    self.fragments = Some((opcode, payload));
+                    Ok(None)
+                }
+            }
+            Opcode::Ping => {
+                if !self.close_sent {
+                    self.enqueue(&Frame::new(Opcode::Pong, frame.payload.clone()));
+                }
+                Ok(Some(Message::Ping(frame.payload)))
+            }
+            Opcode::Pong => Ok(Some(Message::Pong(frame.payload))),
+            Opcode::Close => {
+                let close = track!(self.parse_close(&frame.payload))?;
+                self.close_received = true;
+                if !self.close_sent {
+                    self.close_sent = true;
+                    let echo = close.as_ref().map(|c| CloseFrame {
+                        code: c.code,
+                        reason: String::new(),
+                    });
+                    self.enqueue(&Message::Close(echo).into_frame());
+                }
+                Ok(Some(Message::Close(close)))
+            }
+        }
+    }
+
+    fn complete(&mut self, opcode: Opcode, payload: Vec<u8>) -> ::Result<Message> {
+        if opcode == Opcode::Text {
+            match String::from_utf8(payload) {
+                Ok(s) => Ok(Message::Text(s)),
+                Err(e) => {
+                    let e = track!(Error::from(Status::BadRequest.cause(e)));
+                    Err(self.fail(CloseCode::INVALID_PAYLOAD, e))
+                }
+            }
+        } else {
+            Ok(Message::Binary(payload))
+        }
+    }
+
+    fn parse_close(&mut self, payload: &[u8]) -> ::Result<Option<CloseFrame>> {
+        if payload.is_empty() {
+            return Ok(None);
+        }
+        if payload.len() < 2 {
+            return Err(self.protocol_error("Malformed close frame"));
+        }
+        let code = CloseCode(u16::from_be_bytes([payload[0], payload[1]]));
+        if !code.is_sendable() {
+            return Err(self.protocol_error("Invalid close code"));
+        }
+        match str::from_utf8(&payload[2..]) {
+            Ok(reason) => Ok(Some(CloseFrame {
+                code,
+                reason: reason.to_owned(),
+            })),
+            Err(e) => {
+                let e = track!(Error::from(Status::BadRequest.cause(e)));
+                Err(self.fail(CloseCode::INVALID_PAYLOAD, e))
+            }
+        }
+    }
+
+    fn fill_buffer(&mut self) -> Poll<bool, Error> {
+        let mut buf = [0; 4096];
+        match self.stream.read(&mut buf) {
+            Ok(0) => Ok(Async::Ready(false)),
+            Ok(n) => {
+                self.read_buf.extend_from_slice(&buf[..n]);
+                Ok(Async::Ready(true))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(track!(Error::from(e))),
+        }
+    }
+}
+impl<S: Read + Write> Stream for WebSocket<S> {
+    type Item = Message;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let flushed = track!(self.poll_flush())?.is_ready();
+        if self.close_received {
+            return Ok(if flushed {
+                Async::Ready(None)
+            } else {
+                Async::NotReady
+            });
+        }
+        loop {
+            if let Some(frame) = track!(self.decode_frame())? {
+                if let Some(message) = track!(self.handle_frame(frame))? {
+                    let _ = track!(self.poll_flush())?;
+                    return Ok(Async::Ready(Some(message)));
+                }
+                continue;
+            }
+            if !try_ready!(self.fill_buffer()) {
+                track_assert!(
+                    self.close_sent,
+                    Status::BadRequest,
+                    "Connection closed without a close frame"
+                );
+                return Ok(Async::Ready(None));
+            }
+        }
+    }
+}
+impl<S: Read + Write> Sink for WebSocket<S> {
+    type SinkItem = Message;
+    type SinkError = Error;
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        track!(self.send(item))?;
+        Ok(AsyncSink::Ready)
+    }
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        track!(self.poll_flush())
+    }
+}
+
+fn apply_mask(payload: &mut [u8], key: [u8; 4]) {
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= key[i % 4];
+    }
+}